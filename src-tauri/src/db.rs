@@ -1,9 +1,14 @@
-use rusqlite::{params, Connection, Result, Row};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection, DatabaseName, ErrorCode, OpenFlags, OptionalExtension, Result, Row};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fs;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Instant;
 use uuid::Uuid;
-use chrono::Utc;
+use chrono::{Local, NaiveDate, TimeZone, Utc};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use epub_builder::{EpubBuilder, EpubContent, ReferenceType, ZipLibrary};
 use rand::Rng;
 
 // ============ 类型定义 ============
@@ -25,24 +30,159 @@ pub struct DocumentRecord {
     pub created_at: i64,
 }
 
+// 旧版本导出可能缺少较新的字段，反序列化时使用默认值
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AnnotationRecord {
     pub id: String,
+    #[serde(default)]
     pub document_id: String,
     pub user_id: String,
     pub user_name: String,
     pub text: String,
+    #[serde(default)]
     pub note: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_bool_or_int")]
     pub note_visible: bool,
+    #[serde(default)]
     pub note_position_x: f64,
+    #[serde(default)]
     pub note_position_y: f64,
+    #[serde(default = "default_note_width")]
     pub note_width: f64,
+    #[serde(default = "default_note_height")]
     pub note_height: f64,
+    #[serde(default = "default_highlight_color")]
     pub highlight_color: String,
+    #[serde(default = "default_highlight_type")]
     pub highlight_type: String,
     pub anchor_data: String, // JSON 字符串
+    #[serde(default)]
     pub created_at: i64,
+    #[serde(default)]
     pub updated_at: i64,
+    // 数据库中以 JSON 数组存放于 tags 列，仅由 add_annotation 与 set_annotation_tags 写入
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // 仅在导出包中携带，数据库中存放于 comments 表
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<CommentRecord>,
+    // 仅在导出包中携带：高亮两侧的原文片段，导入到已修改的文档时用于重新定位
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<AnchorContext>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AnchorContext {
+    pub prefix: String,
+    pub suffix: String,
+}
+
+fn default_note_width() -> f64 {
+    280.0
+}
+
+fn default_note_height() -> f64 {
+    180.0
+}
+
+fn default_highlight_color() -> String {
+    "#ffd700".to_string()
+}
+
+fn default_highlight_type() -> String {
+    HighlightType::Underline.as_str().to_string()
+}
+
+// 早期导出中 note_visible 以 0/1 存储
+fn deserialize_bool_or_int<'de, D>(deserializer: D) -> std::result::Result<bool, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrInt {
+        Bool(bool),
+        Int(i64),
+    }
+
+    Ok(match BoolOrInt::deserialize(deserializer)? {
+        BoolOrInt::Bool(value) => value,
+        BoolOrInt::Int(value) => value != 0,
+    })
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CommentRecord {
+    pub id: String,
+    pub annotation_id: String,
+    pub user_id: String,
+    pub user_name: String,
+    pub body: String,
+    pub created_at: i64,
+    #[serde(default)]
+    pub parent_id: Option<String>, // 回复的评论 ID，顶层评论为空
+}
+
+// 注解评论树
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AnnotationThread {
+    pub annotation: AnnotationRecord,
+    pub comments: Vec<CommentNode>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CommentNode {
+    pub comment: CommentRecord,
+    pub replies: Vec<CommentNode>,
+}
+
+// 高亮样式，与前端 highlightType 对应（square 为背景色块，解析时也接受 background）
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightType {
+    Underline,
+    Square,
+}
+
+impl HighlightType {
+    pub fn parse(value: &str) -> Result<HighlightType, String> {
+        match value {
+            "underline" => Ok(HighlightType::Underline),
+            "square" | "background" => Ok(HighlightType::Square),
+            _ => Err(format!("Invalid highlight type: {}", value)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HighlightType::Underline => "underline",
+            HighlightType::Square => "square",
+        }
+    }
+}
+
+// 文档格式，由内容启发式判断，决定渲染方式
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentFormat {
+    Markdown,
+    PlainText,
+    Html,
+    Unknown,
+}
+
+// 与前端 AnnotationAnchor 对应；containerPath 为空时偏移量为文档原文中的字节偏移
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AnchorRecord {
+    #[serde(default)]
+    pub container_path: String,
+    #[serde(default)]
+    pub text_node_index: i64,
+    pub start_offset: usize,
+    pub end_offset: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>, // 行号（从 1 开始）
 }
 
 #[derive(Serialize, Deserialize)]
@@ -52,6 +192,8 @@ pub struct SettingsRecord {
     pub editor: EditorSettingsRecord,
     pub export: ExportSettingsRecord,
     pub i18n: I18nSettingsRecord,
+    #[serde(default)]
+    pub database: DatabaseSettingsRecord,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -67,12 +209,47 @@ pub struct EditorSettingsRecord {
     pub default_highlight_type: String,
     pub font_size: i32,
     pub font_family: String,
+    #[serde(default = "default_max_document_bytes")]
+    pub max_document_bytes: u64, // 超过该大小的文档返回 DocumentTooLarge，由前端改为分块加载
+    #[serde(default = "default_color_presets")]
+    pub color_presets: Vec<ColorPreset>,
+    #[serde(default)]
+    pub presentation_mode: bool, // 演示模式：注解与文档的修改命令返回 ReadOnlyMode
+}
+
+fn default_max_document_bytes() -> u64 {
+    50 * 1024 * 1024
+}
+
+// 高亮颜色预设，供颜色选择器使用
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ColorPreset {
+    pub name: String,
+    pub hex: String,
+}
+
+fn default_color_presets() -> Vec<ColorPreset> {
+    [
+        ("gold", "#ffd700"),
+        ("green", "#90ee90"),
+        ("blue", "#87cefa"),
+        ("pink", "#ffb6c1"),
+        ("orange", "#ffa500"),
+        ("purple", "#d8bfd8"),
+    ]
+    .iter()
+    .map(|(name, hex)| ColorPreset { name: name.to_string(), hex: hex.to_string() })
+    .collect()
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ExportSettingsRecord {
     pub default_format: String,
     pub show_notes_by_default: bool,
+    #[serde(default)]
+    pub default_dir: Option<String>, // 相对文件名的导出目录，未设置时使用应用数据目录下的 exports
+    #[serde(default)]
+    pub color_labels: std::collections::BTreeMap<String, String>, // 高亮颜色 → 图例说明
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -80,6 +257,17 @@ pub struct I18nSettingsRecord {
     pub language: String,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DatabaseSettingsRecord {
+    pub read_pool_size: u32,
+}
+
+impl Default for DatabaseSettingsRecord {
+    fn default() -> Self {
+        DatabaseSettingsRecord { read_pool_size: 4 }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum AnnotationPackage {
@@ -95,6 +283,25 @@ pub struct BatchPackage {
     pub exported_at: i64,
     pub source_document: Option<SourceDocumentInfo>,
     pub annotations: Vec<AnnotationRecord>,
+    // 存在时便签坐标为相对该视口的比例（0.0–1.0），导入时按当前视口还原为像素
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub viewport: Option<Viewport>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Viewport {
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Viewport {
+    fn validate(&self) -> Result<(), String> {
+        if self.width.is_finite() && self.height.is_finite() && self.width > 0.0 && self.height > 0.0 {
+            Ok(())
+        } else {
+            Err(format!("Invalid viewport: {}x{}", self.width, self.height))
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -111,6 +318,30 @@ pub struct SourceDocumentInfo {
     pub checksum: String,
 }
 
+// 注解补丁：自某一时间点以来的增量
+#[derive(Serialize, Deserialize)]
+pub struct AnnotationPatch {
+    pub version: String,
+    pub exported_at: i64,
+    pub since: i64,
+    pub source_document: Option<SourceDocumentInfo>,
+    pub added: Vec<AnnotationRecord>,
+    pub updated: Vec<AnnotationRecord>,
+    #[serde(default)]
+    pub deleted: Vec<String>,
+}
+
+// 文档快照摘要（列表用，不含内容）
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnapshotRecord {
+    pub id: String,
+    pub document_id: String,
+    pub label: String,
+    pub checksum: String,
+    pub annotation_count: usize,
+    pub created_at: i64,
+}
+
 // ============ 数据库路径 ============
 
 pub fn get_app_data_dir() -> std::path::PathBuf {
@@ -145,10 +376,70 @@ pub fn get_settings_path() -> std::path::PathBuf {
 
 // ============ 数据库初始化 ============
 
+pub const DOCUMENT_TOO_LARGE_ERROR: &str = "DocumentTooLarge";
+
+// 文档超过设置的大小上限时返回 "DocumentTooLarge: <实际字节数>/<上限>"
+pub fn check_document_size(size: u64, limit: u64) -> Result<(), String> {
+    if size > limit {
+        return Err(format!("{}: {}/{}", DOCUMENT_TOO_LARGE_ERROR, size, limit));
+    }
+    Ok(())
+}
+
+pub const DB_READ_ONLY_ERROR: &str = "DatabaseReadOnly: 数据库文件位于只读位置，无法写入";
+
+pub const PRESENTATION_MODE_ERROR: &str = "ReadOnlyMode: 演示模式下禁止修改";
+
+// 切换演示模式并写入设置，重启后保持
+pub fn set_presentation_mode(enabled: bool) -> Result<(), String> {
+    let mut settings = load_settings()?;
+    settings.editor.presentation_mode = enabled;
+    save_settings(&settings)
+}
+
+// 数据库结构版本，保存在 PRAGMA user_version 中
+pub const SCHEMA_VERSION: i64 = 1;
+
+const REQUIRED_TABLES: &[&str] = &[
+    "users", "documents", "annotations", "comments", "document_snapshots",
+    "annotations_archive", "comments_archive", "exports", "operation_log",
+];
+
+const REQUIRED_INDEXES: &[(&str, &str)] = &[
+    ("idx_annotations_doc", "CREATE INDEX IF NOT EXISTS idx_annotations_doc ON annotations(document_id)"),
+    ("idx_annotations_user", "CREATE INDEX IF NOT EXISTS idx_annotations_user ON annotations(user_id)"),
+    ("idx_annotations_order", "CREATE INDEX IF NOT EXISTS idx_annotations_order ON annotations(document_id, reading_order)"),
+    ("idx_comments_annotation", "CREATE INDEX IF NOT EXISTS idx_comments_annotation ON comments(annotation_id)"),
+    ("idx_snapshots_doc", "CREATE INDEX IF NOT EXISTS idx_snapshots_doc ON document_snapshots(document_id)"),
+    ("idx_annotations_archive_doc", "CREATE INDEX IF NOT EXISTS idx_annotations_archive_doc ON annotations_archive(document_id)"),
+    ("idx_comments_archive_annotation", "CREATE INDEX IF NOT EXISTS idx_comments_archive_annotation ON comments_archive(annotation_id)"),
+    ("idx_exports_created", "CREATE INDEX IF NOT EXISTS idx_exports_created ON exports(created_at)"),
+];
+
+// 等待其他连接释放锁的最长时间，超时后才返回 SQLITE_BUSY
+const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
 pub fn init_db() -> Result<Connection, String> {
     let conn = Connection::open(get_db_path())
         .map_err(|e| e.to_string())?;
+    conn.busy_timeout(BUSY_TIMEOUT).map_err(|e| e.to_string())?;
+
+    // 只读介质上跳过建表和日志模式切换，读操作仍可正常进行
+    if probe_read_only(&conn) {
+        return Ok(conn);
+    }
+
+    create_schema(&conn)?;
+
+    // WAL 模式下读连接不会被写连接阻塞
+    conn.query_row("PRAGMA journal_mode = WAL", [], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?;
+
+    Ok(conn)
+}
 
+// 建表、补列、建索引并记录结构版本，可重复执行
+fn create_schema(conn: &Connection) -> Result<(), String> {
     // 创建表
     conn.execute_batch(r#"
         CREATE TABLE IF NOT EXISTS users (
@@ -183,15 +474,329 @@ pub fn init_db() -> Result<Connection, String> {
             anchor_data TEXT NOT NULL,
             created_at INTEGER,
             updated_at INTEGER,
+            tags TEXT DEFAULT '[]',
+            reading_order INTEGER,
             FOREIGN KEY (document_id) REFERENCES documents(id),
             FOREIGN KEY (user_id) REFERENCES users(id)
         );
 
-        CREATE INDEX IF NOT EXISTS idx_annotations_doc ON annotations(document_id);
-        CREATE INDEX IF NOT EXISTS idx_annotations_user ON annotations(user_id);
+        CREATE TABLE IF NOT EXISTS comments (
+            id TEXT PRIMARY KEY,
+            annotation_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            user_name TEXT NOT NULL,
+            body TEXT NOT NULL,
+            created_at INTEGER,
+            parent_id TEXT,
+            FOREIGN KEY (annotation_id) REFERENCES annotations(id)
+        );
+
+        CREATE TABLE IF NOT EXISTS document_snapshots (
+            id TEXT PRIMARY KEY,
+            document_id TEXT NOT NULL,
+            label TEXT NOT NULL,
+            content TEXT NOT NULL,
+            checksum TEXT NOT NULL,
+            package TEXT NOT NULL,
+            annotation_count INTEGER DEFAULT 0,
+            created_at INTEGER,
+            FOREIGN KEY (document_id) REFERENCES documents(id)
+        );
+
+        CREATE TABLE IF NOT EXISTS annotations_archive (
+            id TEXT PRIMARY KEY,
+            document_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            user_name TEXT NOT NULL,
+            text TEXT NOT NULL,
+            note TEXT,
+            note_visible INTEGER DEFAULT 0,
+            note_position_x REAL DEFAULT 0,
+            note_position_y REAL DEFAULT 0,
+            note_width REAL DEFAULT 280,
+            note_height REAL DEFAULT 180,
+            highlight_color TEXT DEFAULT '#ffd700',
+            highlight_type TEXT DEFAULT 'underline',
+            anchor_data TEXT NOT NULL,
+            created_at INTEGER,
+            updated_at INTEGER,
+            tags TEXT DEFAULT '[]',
+            reading_order INTEGER,
+            archived_at INTEGER
+        );
+
+        CREATE TABLE IF NOT EXISTS comments_archive (
+            id TEXT PRIMARY KEY,
+            annotation_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            user_name TEXT NOT NULL,
+            body TEXT NOT NULL,
+            created_at INTEGER,
+            parent_id TEXT
+        );
+
+        CREATE TABLE IF NOT EXISTS exports (
+            id TEXT PRIMARY KEY,
+            document_id TEXT,
+            format TEXT NOT NULL,
+            path TEXT NOT NULL,
+            created_at INTEGER
+        );
+
+        CREATE TABLE IF NOT EXISTS operation_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            operation TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            created_at INTEGER
+        );
     "#).map_err(|e| e.to_string())?;
 
-    Ok(conn)
+    // 旧版本数据库补充新增列
+    ensure_column(conn, "comments", "parent_id", "TEXT")?;
+    ensure_column(conn, "annotations", "tags", "TEXT DEFAULT '[]'")?;
+    ensure_column(conn, "annotations", "reading_order", "INTEGER")?;
+    ensure_column(conn, "annotations_archive", "reading_order", "INTEGER")?;
+
+    // 早期版本归档时评论留在 comments 表中，迁入 comments_archive
+    conn.execute_batch(&format!(
+        "INSERT OR IGNORE INTO comments_archive ({0}) SELECT {0} FROM comments
+         WHERE annotation_id IN (SELECT id FROM annotations_archive) AND annotation_id NOT IN (SELECT id FROM annotations);
+         DELETE FROM comments
+         WHERE annotation_id IN (SELECT id FROM annotations_archive) AND annotation_id NOT IN (SELECT id FROM annotations);",
+        COMMENT_COLUMNS
+    )).map_err(|e| e.to_string())?;
+
+    // 索引在补列之后创建，部分索引依赖新增列
+    for (_, sql) in REQUIRED_INDEXES {
+        conn.execute_batch(sql).map_err(|e| e.to_string())?;
+    }
+
+    // 记录结构版本；由更新版本创建的数据库保持原值，由启动自检报告
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if version < SCHEMA_VERSION {
+        conn.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// 表中缺少指定列时追加（CREATE TABLE IF NOT EXISTS 不会修改已有表）
+fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<(), String> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))
+        .map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        let name: String = row.get(1).map_err(|e| e.to_string())?;
+        if name == column {
+            return Ok(());
+        }
+    }
+
+    conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+        .map_err(|e| e.to_string())
+}
+
+pub type WriteConnection = MutexGuard<'static, Connection>;
+
+// 所有写命令共享的唯一读写连接，互斥锁保证同一时间只有一个写者
+static WRITE_CONN: OnceLock<Mutex<Connection>> = OnceLock::new();
+
+// 写命令使用：借出共享写连接；数据库只读时返回明确的 DatabaseReadOnly 错误
+pub fn init_db_for_write() -> Result<WriteConnection, String> {
+    if WRITE_CONN.get().is_none() {
+        let conn = init_db()?;
+        ensure_writable(&conn)?;
+        // 并发初始化时只保留先完成的连接
+        let _ = WRITE_CONN.set(Mutex::new(conn));
+    }
+
+    let conn = WRITE_CONN.get()
+        .ok_or_else(|| "Write connection not initialized".to_string())?;
+    // 持锁的命令 panic 后连接本身仍可用，事务会随语句对象释放而回滚
+    Ok(conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+}
+
+fn ensure_writable(conn: &Connection) -> Result<(), String> {
+    if probe_read_only(conn) {
+        return Err(DB_READ_ONLY_ERROR.to_string());
+    }
+    Ok(())
+}
+
+// 在保存点内尝试一次写入，随后回滚，用于探测只读状态
+fn probe_read_only(conn: &Connection) -> bool {
+    if conn.is_readonly(DatabaseName::Main).unwrap_or(false) {
+        return true;
+    }
+    if conn.execute_batch("SAVEPOINT write_probe").is_err() {
+        return false;
+    }
+
+    let probe = conn.execute_batch("CREATE TABLE write_probe (id INTEGER)");
+    let _ = conn.execute_batch("ROLLBACK TO write_probe; RELEASE write_probe");
+
+    match probe {
+        Err(e) => e.sqlite_error_code() == Some(ErrorCode::ReadOnly),
+        Ok(()) => false,
+    }
+}
+
+// ============ 启动自检 ============
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DiagnosticIssue {
+    pub kind: String, // "integrity" | "schema_version" | "missing_table" | "missing_index"
+    pub object: Option<String>, // 缺失的表名或索引名
+    pub message: String,
+    pub fatal: bool,
+    pub fixed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DiagnosticsReport {
+    pub integrity_ok: bool,
+    pub schema_version: i64,
+    pub issues: Vec<DiagnosticIssue>,
+}
+
+impl DiagnosticsReport {
+    pub fn has_fatal(&self) -> bool {
+        self.issues.iter().any(|i| i.fatal && !i.fixed)
+    }
+}
+
+fn sqlite_object_names(conn: &Connection, kind: &str) -> Result<std::collections::HashSet<String>, String> {
+    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type = ?")
+        .map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([kind]).map_err(|e| e.to_string())?;
+    let mut names = std::collections::HashSet::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        names.insert(row.get(0).map_err(|e| e.to_string())?);
+    }
+    Ok(names)
+}
+
+// 检查数据库完整性、结构版本以及必需的表和索引；缺失的表和索引通过 init_db 重建，
+// 完整性损坏或版本过新属于无法自动修复的问题，只做报告
+pub fn run_startup_diagnostics() -> Result<DiagnosticsReport, String> {
+    let mut report = DiagnosticsReport::default();
+
+    // 只读检查阶段使用独立连接，修复前关闭
+    {
+        let conn = Connection::open(get_db_path()).map_err(|e| e.to_string())?;
+        {
+            let mut stmt = conn.prepare("PRAGMA integrity_check").map_err(|e| e.to_string())?;
+            let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+            let mut problems: Vec<String> = Vec::new();
+            while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+                let line: String = row.get(0).map_err(|e| e.to_string())?;
+                if line != "ok" {
+                    problems.push(line);
+                }
+            }
+            report.integrity_ok = problems.is_empty();
+            for message in problems {
+                report.issues.push(DiagnosticIssue { kind: "integrity".to_string(), object: None, message, fatal: true, fixed: false });
+            }
+        }
+
+        report.schema_version = conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        if report.schema_version > SCHEMA_VERSION {
+            report.issues.push(DiagnosticIssue {
+                kind: "schema_version".to_string(),
+                object: None,
+                message: format!("Database schema version {} is newer than supported {}", report.schema_version, SCHEMA_VERSION),
+                fatal: true,
+                fixed: false,
+            });
+        }
+
+        // 全新数据库没有任何表，不算缺失
+        let tables = sqlite_object_names(&conn, "table")?;
+        let indexes = sqlite_object_names(&conn, "index")?;
+        if !tables.is_empty() {
+            for table in REQUIRED_TABLES.iter().filter(|t| !tables.contains(**t)) {
+                report.issues.push(DiagnosticIssue {
+                    kind: "missing_table".to_string(),
+                    object: Some(table.to_string()),
+                    message: format!("Missing table: {}", table),
+                    fatal: false,
+                    fixed: false,
+                });
+            }
+            for (index, _) in REQUIRED_INDEXES.iter().filter(|(name, _)| !indexes.contains(*name)) {
+                report.issues.push(DiagnosticIssue {
+                    kind: "missing_index".to_string(),
+                    object: Some(index.to_string()),
+                    message: format!("Missing index: {}", index),
+                    fatal: false,
+                    fixed: false,
+                });
+            }
+        }
+    }
+
+    // 数据库已损坏时不再写入
+    if !report.has_fatal() {
+        let conn = init_db()?;
+        let tables = sqlite_object_names(&conn, "table")?;
+        let indexes = sqlite_object_names(&conn, "index")?;
+        for issue in &mut report.issues {
+            issue.fixed = match (issue.kind.as_str(), &issue.object) {
+                ("missing_table", Some(name)) => tables.contains(name),
+                ("missing_index", Some(name)) => indexes.contains(name),
+                _ => issue.fixed,
+            };
+        }
+        report.schema_version = conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+    }
+
+    for issue in &report.issues {
+        let message = format!("Startup diagnostics [{}]: {}{}", issue.kind, issue.message, if issue.fixed { " (fixed)" } else { "" });
+        if issue.fatal {
+            log_error(&message);
+        } else {
+            log_warn(&message);
+        }
+    }
+    Ok(report)
+}
+
+// ============ 读连接池 ============
+//
+// 写操作：通过 init_db_for_write() 借用唯一的共享读写连接（互斥锁保证单写者）
+// 读操作：通过 read_db() 从只读连接池借用连接，池大小由 settings.database.read_pool_size 配置
+
+pub type ReadConnection = r2d2::PooledConnection<SqliteConnectionManager>;
+
+static READ_POOL: OnceLock<r2d2::Pool<SqliteConnectionManager>> = OnceLock::new();
+
+pub fn read_db() -> Result<ReadConnection, String> {
+    if READ_POOL.get().is_none() {
+        // 确保表结构已创建且数据库已切换到 WAL
+        init_db()?;
+        let pool_size = load_settings()
+            .map(|s| s.database.read_pool_size)
+            .unwrap_or_else(|_| DatabaseSettingsRecord::default().read_pool_size)
+            .max(1);
+        let manager = SqliteConnectionManager::file(get_db_path())
+            .with_flags(OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+            .with_init(|c| c.busy_timeout(BUSY_TIMEOUT));
+        let pool = r2d2::Pool::builder()
+            .max_size(pool_size)
+            .build(manager)
+            .map_err(|e| e.to_string())?;
+        // 并发初始化时只保留先完成的连接池
+        let _ = READ_POOL.set(pool);
+    }
+
+    READ_POOL.get()
+        .ok_or_else(|| "Read pool not initialized".to_string())?
+        .get()
+        .map_err(|e| e.to_string())
 }
 
 // ============ 用户操作 ============
@@ -222,60 +827,248 @@ pub fn get_or_create_user(conn: &Connection, name: String) -> Result<UserRecord,
     Ok(UserRecord { id, name, created_at: now })
 }
 
-pub fn update_user_name(conn: &Connection, id: &str, name: &str) -> Result<(), String> {
-    conn.execute(
-        "UPDATE users SET name = ? WHERE id = ?",
-        params![name, id],
-    ).map_err(|e| e.to_string())?;
-    Ok(())
-}
-
-pub fn generate_random_name() -> String {
-    const ADJECTIVES: &[&str] = &["Swift", "Bright", "Calm", "Eager", "Gentle", "Happy", "Jolly", "Kind", "Lively", "Nice", "Proud", "Silly", "Witty", "Zesty", "Cool", "Fine", "Bold", "Wild"];
-    const NOUNS: &[&str] = &["Panda", "Tiger", "Eagle", "Lion", "Wolf", "Bear", "Fox", "Hawk", "Owl", "Deer", "Rabbit", "Swan", "Dove", "Frog", "Fish", "Whale", "Dolphin", "Shark", "Cat", "Dog"];
-
-    let mut rng = rand::thread_rng();
-    let adj = ADJECTIVES[rng.gen_range(0..ADJECTIVES.len())];
-    let noun = NOUNS[rng.gen_range(0..NOUNS.len())];
-    let num: u32 = rng.gen_range(1000..10000);
-
-    format!("{}{}{}", adj, noun, num)
-}
-
-// ============ 文档操作 ============
-
-pub fn get_document_by_path(conn: &Connection, path: &str) -> Result<Option<DocumentRecord>, String> {
-    let mut stmt = conn.prepare("SELECT id, path, content, checksum, last_modified, created_at FROM documents WHERE path = ?")
+pub fn get_user_by_id(conn: &Connection, id: &str) -> Result<Option<UserRecord>, String> {
+    let mut stmt = conn.prepare("SELECT id, name, created_at FROM users WHERE id = ?")
         .map_err(|e| e.to_string())?;
-    let mut rows = stmt.query([path]).map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([id]).map_err(|e| e.to_string())?;
 
     if let Some(row) = rows.next().map_err(|e| e.to_string())? {
-        Ok(Some(DocumentRecord {
+        Ok(Some(UserRecord {
             id: row.get(0).map_err(|e| e.to_string())?,
-            path: row.get(1).map_err(|e| e.to_string())?,
-            content: row.get(2).map_err(|e| e.to_string())?,
-            checksum: row.get(3).map_err(|e| e.to_string())?,
-            last_modified: row.get(4).map_err(|e| e.to_string())?,
-            created_at: row.get(5).map_err(|e| e.to_string())?,
+            name: row.get(1).map_err(|e| e.to_string())?,
+            created_at: row.get(2).map_err(|e| e.to_string())?,
         }))
     } else {
         Ok(None)
     }
 }
 
-pub fn save_document(conn: &Connection, path: &str, content: &str) -> Result<DocumentRecord, String> {
-    let checksum = compute_checksum(content);
-    let now = Utc::now().timestamp_millis();
-
-    // 检查是否存在
-    if let Some(existing) = get_document_by_path(conn, path)? {
-        // 更新
-        conn.execute(
-            "UPDATE documents SET content = ?, checksum = ?, last_modified = ? WHERE id = ?",
-            params![content, checksum, now, existing.id],
-        ).map_err(|e| e.to_string())?;
+// 按名称（忽略大小写与首尾空白）查找本地用户，不存在时创建
+pub fn find_or_create_user_by_name(conn: &Connection, name: &str) -> Result<UserRecord, String> {
+    let name = name.trim();
+    let mut stmt = conn.prepare("SELECT id, name, created_at FROM users WHERE TRIM(name) = ? COLLATE NOCASE ORDER BY created_at LIMIT 1")
+        .map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([name]).map_err(|e| e.to_string())?;
 
-        return Ok(DocumentRecord {
+    if let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        return Ok(UserRecord {
+            id: row.get(0).map_err(|e| e.to_string())?,
+            name: row.get(1).map_err(|e| e.to_string())?,
+            created_at: row.get(2).map_err(|e| e.to_string())?,
+        });
+    }
+
+    let user = UserRecord {
+        id: Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        created_at: Utc::now().timestamp_millis(),
+    };
+    conn.execute(
+        "INSERT INTO users (id, name, created_at) VALUES (?, ?, ?)",
+        params![user.id, user.name, user.created_at],
+    ).map_err(|e| e.to_string())?;
+    Ok(user)
+}
+
+// 将一个用户的全部注解转给另一个用户，返回转移的条数
+pub fn reassign_annotations(conn: &Connection, from_user_id: &str, to_user_id: &str) -> Result<usize, String> {
+    if get_user_by_id(conn, from_user_id)?.is_none() {
+        return Err(format!("User not found: {}", from_user_id));
+    }
+    let target = get_user_by_id(conn, to_user_id)?
+        .ok_or_else(|| format!("User not found: {}", to_user_id))?;
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let moved = tx.execute(
+        "UPDATE annotations SET user_id = ?, user_name = ?, updated_at = ? WHERE user_id = ?",
+        params![target.id, target.name, Utc::now().timestamp_millis(), from_user_id],
+    ).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(moved)
+}
+
+// 删除用户后保留其注解时使用的占位用户
+const PLACEHOLDER_USER_ID: &str = "deleted-user";
+const PLACEHOLDER_USER_NAME: &str = "Deleted User";
+
+// 删除非当前用户：delete_annotations 为 true 时删除其注解（可撤销），否则转给占位用户；
+// 其评论始终转给占位用户。返回删除或转移的注解数量
+pub fn delete_user(conn: &Connection, id: &str, delete_annotations: bool) -> Result<usize, String> {
+    let active = get_or_create_user(conn, "admin".to_string())?;
+    if active.id == id {
+        return Err("Cannot delete the active user".to_string());
+    }
+    if id == PLACEHOLDER_USER_ID {
+        return Err("Cannot delete the placeholder user".to_string());
+    }
+    let user = get_user_by_id(conn, id)?
+        .ok_or_else(|| format!("User not found: {}", id))?;
+
+    let now = Utc::now().timestamp_millis();
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    tx.execute(
+        "INSERT OR IGNORE INTO users (id, name, created_at) VALUES (?, ?, ?)",
+        params![PLACEHOLDER_USER_ID, PLACEHOLDER_USER_NAME, now],
+    ).map_err(|e| e.to_string())?;
+
+    let affected = if delete_annotations {
+        let mut annotations = Vec::new();
+        {
+            let mut stmt = tx.prepare("SELECT id FROM annotations WHERE user_id = ?")
+                .map_err(|e| e.to_string())?;
+            let mut rows = stmt.query([id]).map_err(|e| e.to_string())?;
+            while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+                let anno_id: String = row.get(0).map_err(|e| e.to_string())?;
+                if let Some(anno) = get_annotation_by_id(&tx, &anno_id)? {
+                    annotations.push(anno);
+                }
+            }
+        }
+        if !annotations.is_empty() {
+            log_deletion(&tx, "delete_user", DeletedRows {
+                documents: Vec::new(),
+                annotations: with_comments(&tx, annotations)?,
+                users: vec![user],
+                ..Default::default()
+            })?;
+        }
+        tx.execute(
+            "DELETE FROM comments WHERE annotation_id IN (SELECT id FROM annotations WHERE user_id = ?)",
+            params![id],
+        ).map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM annotations WHERE user_id = ?", params![id])
+            .map_err(|e| e.to_string())?
+    } else {
+        tx.execute(
+            "UPDATE annotations SET user_id = ?, user_name = ?, updated_at = ? WHERE user_id = ?",
+            params![PLACEHOLDER_USER_ID, PLACEHOLDER_USER_NAME, now, id],
+        ).map_err(|e| e.to_string())?
+    };
+
+    tx.execute(
+        "UPDATE comments SET user_id = ?, user_name = ? WHERE user_id = ?",
+        params![PLACEHOLDER_USER_ID, PLACEHOLDER_USER_NAME, id],
+    ).map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM users WHERE id = ?", params![id])
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(affected)
+}
+
+// 注解与评论中冗余存储了用户名，改名时一并更新
+pub fn update_user_name(conn: &Connection, id: &str, name: &str) -> Result<(), String> {
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    tx.execute(
+        "UPDATE users SET name = ? WHERE id = ?",
+        params![name, id],
+    ).map_err(|e| e.to_string())?;
+    tx.execute(
+        "UPDATE annotations SET user_name = ? WHERE user_id = ?",
+        params![name, id],
+    ).map_err(|e| e.to_string())?;
+    tx.execute(
+        "UPDATE comments SET user_name = ? WHERE user_id = ?",
+        params![name, id],
+    ).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub fn generate_random_name() -> String {
+    const ADJECTIVES: &[&str] = &["Swift", "Bright", "Calm", "Eager", "Gentle", "Happy", "Jolly", "Kind", "Lively", "Nice", "Proud", "Silly", "Witty", "Zesty", "Cool", "Fine", "Bold", "Wild"];
+    const NOUNS: &[&str] = &["Panda", "Tiger", "Eagle", "Lion", "Wolf", "Bear", "Fox", "Hawk", "Owl", "Deer", "Rabbit", "Swan", "Dove", "Frog", "Fish", "Whale", "Dolphin", "Shark", "Cat", "Dog"];
+
+    let mut rng = rand::thread_rng();
+    let adj = ADJECTIVES[rng.gen_range(0..ADJECTIVES.len())];
+    let noun = NOUNS[rng.gen_range(0..NOUNS.len())];
+    let num: u32 = rng.gen_range(1000..10000);
+
+    format!("{}{}{}", adj, noun, num)
+}
+
+// ============ 文档操作 ============
+
+// 规范化文档路径：Windows 下统一分隔符并小写盘符，其他平台路径大小写敏感，保持原样
+pub fn normalize_document_path(path: &str) -> String {
+    if cfg!(windows) {
+        normalize_windows_path(path)
+    } else {
+        path.to_string()
+    }
+}
+
+fn normalize_windows_path(path: &str) -> String {
+    let mut normalized = path.replace('/', "\\");
+    let bytes = normalized.as_bytes();
+    if bytes.len() >= 2 && bytes[1] == b':' && bytes[0].is_ascii_alphabetic() {
+        normalized[..1].make_ascii_lowercase();
+    }
+    normalized
+}
+
+pub fn get_document_by_path(conn: &Connection, path: &str) -> Result<Option<DocumentRecord>, String> {
+    let path = normalize_document_path(path);
+    // Windows 文件系统不区分大小写，旧记录可能以不同大小写保存
+    let sql = if cfg!(windows) {
+        "SELECT id, path, content, checksum, last_modified, created_at FROM documents WHERE path = ? COLLATE NOCASE ORDER BY created_at LIMIT 1"
+    } else {
+        "SELECT id, path, content, checksum, last_modified, created_at FROM documents WHERE path = ?"
+    };
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([&path]).map_err(|e| e.to_string())?;
+
+    if let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        Ok(Some(DocumentRecord {
+            id: row.get(0).map_err(|e| e.to_string())?,
+            path: row.get(1).map_err(|e| e.to_string())?,
+            content: row.get(2).map_err(|e| e.to_string())?,
+            checksum: row.get(3).map_err(|e| e.to_string())?,
+            last_modified: row.get(4).map_err(|e| e.to_string())?,
+            created_at: row.get(5).map_err(|e| e.to_string())?,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn get_document_by_id(conn: &Connection, id: &str) -> Result<Option<DocumentRecord>, String> {
+    let mut stmt = conn.prepare("SELECT id, path, content, checksum, last_modified, created_at FROM documents WHERE id = ?")
+        .map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([id]).map_err(|e| e.to_string())?;
+
+    if let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        Ok(Some(DocumentRecord {
+            id: row.get(0).map_err(|e| e.to_string())?,
+            path: row.get(1).map_err(|e| e.to_string())?,
+            content: row.get(2).map_err(|e| e.to_string())?,
+            checksum: row.get(3).map_err(|e| e.to_string())?,
+            last_modified: row.get(4).map_err(|e| e.to_string())?,
+            created_at: row.get(5).map_err(|e| e.to_string())?,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn save_document(conn: &Connection, path: &str, content: &str) -> Result<DocumentRecord, String> {
+    let path = normalize_document_path(path);
+    let path = path.as_str();
+    let checksum = compute_checksum(content);
+    let now = Utc::now().timestamp_millis();
+
+    // 检查是否存在
+    if let Some(existing) = get_document_by_path(conn, path)? {
+        // 更新
+        conn.execute(
+            "UPDATE documents SET content = ?, checksum = ?, last_modified = ? WHERE id = ?",
+            params![content, checksum, now, existing.id],
+        ).map_err(|e| e.to_string())?;
+        // 内容变化后注解位置可能改变，重新计算阅读顺序
+        reindex_annotation_order(conn, &existing.id, content)?;
+
+        return Ok(DocumentRecord {
             id: existing.id,
             path: path.to_string(),
             content: content.to_string(),
@@ -302,27 +1095,416 @@ pub fn save_document(conn: &Connection, path: &str, content: &str) -> Result<Doc
     })
 }
 
-#[allow(dead_code)]
+// 在文档末尾追加内容；已有注解的偏移均位于追加区域之前，无需调整
+// max_bytes 为追加后允许的文档大小上限，由调用方从设置中读取
+pub fn append_to_document(conn: &Connection, path: &str, additional_content: &str, max_bytes: u64) -> Result<DocumentRecord, String> {
+    let doc = get_document_by_path(conn, path)?
+        .ok_or_else(|| "Document not found".to_string())?;
+
+    // 先校验追加后的大小，避免写入磁盘后才发现超限
+    let current_len = fs::metadata(&doc.path).map_err(|e| e.to_string())?.len();
+    check_document_size(current_len + additional_content.len() as u64, max_bytes)?;
+
+    {
+        use std::io::Write;
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(&doc.path)
+            .map_err(|e| e.to_string())?;
+        file.write_all(additional_content.as_bytes()).map_err(|e| e.to_string())?;
+    }
+
+    // 以磁盘上的实际内容为准，数据库中的旧内容可能已与文件不一致
+    let content = fs::read_to_string(&doc.path).map_err(|e| e.to_string())?;
+    let checksum = compute_checksum(&content);
+    let now = Utc::now().timestamp_millis();
+    conn.execute(
+        "UPDATE documents SET content = ?, checksum = ?, last_modified = ? WHERE id = ?",
+        params![content, checksum, now, doc.id],
+    ).map_err(|e| e.to_string())?;
+    reindex_annotation_order(conn, &doc.id, &content)?;
+
+    Ok(DocumentRecord {
+        content,
+        checksum,
+        last_modified: now,
+        ..doc
+    })
+}
+
 pub fn delete_document(conn: &Connection, doc_id: &str) -> Result<(), String> {
-    // 先删除关联的注解
-    conn.execute("DELETE FROM annotations WHERE document_id = ?", params![doc_id])
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    // 已归档的注解先移回，与其他注解一起记入撤销日志并删除
+    move_annotations(&tx, doc_id, false)?;
+    let annotations = with_comments(&tx, get_annotations_by_doc(&tx, doc_id)?)?;
+
+    // 记录撤销日志
+    log_deletion(&tx, "delete_document", DeletedRows {
+        documents: vec![doc],
+        annotations,
+        ..Default::default()
+    })?;
+
+    // 先删除关联的评论和注解
+    tx.execute(
+        "DELETE FROM comments WHERE annotation_id IN (SELECT id FROM annotations WHERE document_id = ?)",
+        params![doc_id],
+    ).map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM annotations WHERE document_id = ?", params![doc_id])
+        .map_err(|e| e.to_string())?;
+
+    tx.execute("DELETE FROM document_snapshots WHERE document_id = ?", params![doc_id])
         .map_err(|e| e.to_string())?;
 
     // 删除文档
-    conn.execute("DELETE FROM documents WHERE id = ?", params![doc_id])
+    tx.execute("DELETE FROM documents WHERE id = ?", params![doc_id])
         .map_err(|e| e.to_string())?;
 
+    tx.commit().map_err(|e| e.to_string())?;
     Ok(())
 }
 
+// 重新计算所有文档的校验和，返回修正的数量
+pub fn recompute_checksums(conn: &Connection) -> Result<usize, String> {
+    let documents: Vec<(String, String, String)> = {
+        let mut stmt = conn.prepare("SELECT id, content, checksum FROM documents")
+            .map_err(|e| e.to_string())?;
+        let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+        let mut docs = Vec::new();
+        while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            docs.push((
+                row.get(0).map_err(|e| e.to_string())?,
+                row.get(1).map_err(|e| e.to_string())?,
+                row.get(2).map_err(|e| e.to_string())?,
+            ));
+        }
+        docs
+    };
+
+    let mut corrected = 0;
+    for (id, content, stored) in documents {
+        let checksum = compute_checksum(&content);
+        if checksum == stored {
+            continue;
+        }
+        conn.execute(
+            "UPDATE documents SET checksum = ? WHERE id = ?",
+            params![checksum, id],
+        ).map_err(|e| e.to_string())?;
+        corrected += 1;
+    }
+
+    Ok(corrected)
+}
+
+// ============ 项目清单 ============
+
+// 可作为文档打开的扩展名，与前端打开对话框一致
+const DOCUMENT_EXTENSIONS: &[&str] = &["md", "markdown", "txt"];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub status: String, // tracked：已入库；untracked：磁盘上有但未入库；missing：已入库但文件不存在
+    pub checksum: Option<String>,
+    pub annotation_count: usize,
+    pub last_modified: Option<i64>,
+    pub authors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProjectManifest {
+    pub root: String,
+    pub generated_at: i64,
+    pub documents: Vec<ManifestEntry>,
+}
+
+// 生成 dir 下文档的 JSON 清单：已入库文档附带校验和、注解数量与作者，其余文档文件标记为 untracked
+pub fn generate_project_manifest(conn: &Connection, dir: &str) -> Result<String, String> {
+    let root = std::path::Path::new(dir);
+    if !root.is_dir() {
+        return Err(format!("Not a directory: {}", dir));
+    }
+
+    // 递归收集文档文件，跳过隐藏文件与目录
+    let mut on_disk = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current).map_err(|e| e.to_string())? {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.')) {
+                continue;
+            }
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| DOCUMENT_EXTENSIONS.iter().any(|d| ext.eq_ignore_ascii_case(d)))
+            {
+                on_disk.push(normalize_document_path(&path.to_string_lossy()));
+            }
+        }
+    }
+
+    let mut documents = Vec::new();
+    let mut tracked_paths = std::collections::HashSet::new();
+    {
+        let mut stmt = conn.prepare("SELECT id, path, checksum, last_modified FROM documents")
+            .map_err(|e| e.to_string())?;
+        let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+        while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            let id: String = row.get(0).map_err(|e| e.to_string())?;
+            let path: String = row.get(1).map_err(|e| e.to_string())?;
+            if !std::path::Path::new(&path).starts_with(root) {
+                continue;
+            }
+
+            let annotations = get_annotations_by_doc(conn, &id)?;
+            let mut authors: Vec<String> = Vec::new();
+            for anno in &annotations {
+                if !authors.contains(&anno.user_name) {
+                    authors.push(anno.user_name.clone());
+                }
+            }
+            authors.sort();
+
+            let normalized = normalize_document_path(&path);
+            let status = if std::path::Path::new(&path).exists() { "tracked" } else { "missing" };
+            tracked_paths.insert(if cfg!(windows) { normalized.to_lowercase() } else { normalized });
+            documents.push(ManifestEntry {
+                path,
+                status: status.to_string(),
+                checksum: Some(row.get(2).map_err(|e| e.to_string())?),
+                annotation_count: annotations.len(),
+                last_modified: row.get(3).map_err(|e| e.to_string())?,
+                authors,
+            });
+        }
+    }
+
+    for path in on_disk {
+        let key = if cfg!(windows) { path.to_lowercase() } else { path.clone() };
+        if tracked_paths.contains(&key) {
+            continue;
+        }
+        documents.push(ManifestEntry {
+            path,
+            status: "untracked".to_string(),
+            checksum: None,
+            annotation_count: 0,
+            last_modified: None,
+            authors: Vec::new(),
+        });
+    }
+    documents.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let manifest = ProjectManifest {
+        root: dir.to_string(),
+        generated_at: Utc::now().timestamp_millis(),
+        documents,
+    };
+    serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())
+}
+
+// ============ 文档格式检测 ============
+
+const HTML_TAG_HINTS: &[&str] = &[
+    "<p>", "</p>", "<div", "</div>", "<span", "</span>", "<br", "<body", "<head", "<table", "<ul>", "<li>", "<a href",
+];
+
+pub fn detect_document_format(content: &str) -> DocumentFormat {
+    let trimmed = content.trim_start();
+    if trimmed.is_empty() {
+        return DocumentFormat::Unknown;
+    }
+
+    // 含 NUL 或大量控制字符时视为二进制内容
+    let control_chars = content.chars()
+        .filter(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+        .count();
+    if content.contains('\0') || control_chars * 20 > content.chars().count() {
+        return DocumentFormat::Unknown;
+    }
+
+    let lower = trimmed.to_lowercase();
+    if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        return DocumentFormat::Html;
+    }
+
+    let markdown_score = markdown_score(content);
+    let html_tags: usize = HTML_TAG_HINTS.iter().map(|tag| lower.matches(tag).count()).sum();
+
+    if html_tags >= 2 && html_tags > markdown_score {
+        DocumentFormat::Html
+    } else if markdown_score >= 2 {
+        DocumentFormat::Markdown
+    } else {
+        DocumentFormat::PlainText
+    }
+}
+
+// 标题与代码围栏权重为 2，列表、引用、链接、加粗为 1
+fn markdown_score(content: &str) -> usize {
+    let mut score = 0;
+    for line in content.lines() {
+        let line = line.trim_start();
+        let hashes = line.chars().take_while(|&c| c == '#').count();
+        let is_heading = (1..=6).contains(&hashes) && line[hashes..].starts_with(' ');
+        let is_fence = line.starts_with("```") || line.starts_with("~~~");
+        let is_list = line.starts_with("- ") || line.starts_with("* ") || line.starts_with("> ")
+            || line.split_once(". ").is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+
+        if is_heading || is_fence {
+            score += 2;
+        } else if is_list {
+            score += 1;
+        }
+
+        if line.contains("](") || line.contains("**") {
+            score += 1;
+        }
+    }
+    score
+}
+
+// ============ 文档大纲 ============
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OutlineEntry {
+    pub level: u8,
+    pub title: String,
+    pub offset: usize, // 标题行起点的字符偏移
+}
+
+// 解析 ATX 标题（# 至 ######），跳过代码围栏内的行
+pub fn get_document_outline(content: &str) -> Vec<OutlineEntry> {
+    let mut entries = Vec::new();
+    let mut fence: Option<(char, usize)> = None; // (围栏字符, 长度)
+    let mut offset = 0;
+
+    for line in content.split_inclusive('\n') {
+        let line_offset = offset;
+        offset += line.chars().count();
+
+        let trimmed = line.trim_end_matches(['\n', '\r']);
+        let indent = trimmed.len() - trimmed.trim_start_matches(' ').len();
+        if indent > 3 {
+            continue;
+        }
+        let text = &trimmed[indent..];
+
+        // 围栏以同种字符、不短于开启长度的行结束
+        let marker = text.chars().next().filter(|c| *c == '`' || *c == '~');
+        if let Some(c) = marker {
+            let run = text.chars().take_while(|&x| x == c).count();
+            if run >= 3 {
+                match fence {
+                    None => fence = Some((c, run)),
+                    Some((open, len)) if open == c && run >= len && text[run..].trim().is_empty() => fence = None,
+                    Some(_) => {}
+                }
+                continue;
+            }
+        }
+        if fence.is_some() {
+            continue;
+        }
+
+        let hashes = text.chars().take_while(|&c| c == '#').count();
+        if !(1..=6).contains(&hashes) {
+            continue;
+        }
+        let rest = &text[hashes..];
+        if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+            continue;
+        }
+
+        // 去掉可选的结尾 # 序列
+        let mut title = rest.trim();
+        let without_closing = title.trim_end_matches('#');
+        if without_closing.is_empty() || without_closing.ends_with([' ', '\t']) {
+            title = without_closing.trim_end();
+        }
+
+        entries.push(OutlineEntry {
+            level: hashes as u8,
+            title: title.to_string(),
+            offset: line_offset,
+        });
+    }
+
+    entries
+}
+
+// ============ 语言检测 ============
+
+// 拉丁字母文本按常见虚词区分语言
+const LATIN_STOPWORDS: &[(&str, &[&str])] = &[
+    ("en", &["the", "and", "of", "to", "is", "in", "that", "it", "with", "for"]),
+    ("fr", &["le", "la", "les", "et", "des", "est", "une", "dans", "que", "pour"]),
+    ("de", &["der", "die", "und", "das", "ist", "nicht", "ein", "mit", "den", "zu"]),
+    ("es", &["el", "los", "las", "y", "que", "es", "una", "por", "con", "para"]),
+];
+
+// 按字符所属文字推断主要语言，返回 ISO 639-1 代码；无可识别文字时返回 "und"
+pub fn detect_language(content: &str) -> String {
+    let (mut han, mut kana, mut hangul, mut cyrillic, mut arabic, mut latin) = (0usize, 0usize, 0usize, 0usize, 0usize, 0usize);
+    for c in content.chars() {
+        match c as u32 {
+            0x3040..=0x30FF => kana += 1,
+            0xAC00..=0xD7AF => hangul += 1,
+            0x0400..=0x04FF => cyrillic += 1,
+            0x0600..=0x06FF => arabic += 1,
+            _ if is_cjk(c) => han += 1,
+            _ if c.is_ascii_alphabetic() || matches!(c, 'À'..='ÿ') => latin += 1,
+            _ => {}
+        }
+    }
+
+    // 日文混用汉字与假名，假名占比达到一成即视为日文
+    if kana > 0 && kana * 10 >= kana + han {
+        return "ja".to_string();
+    }
+    // 拉丁字母每个词约 5 个字符，中日韩文字每字约为一词，按此折算后比较
+    let scripts = [
+        ("zh", han * 5),
+        ("ko", hangul * 5),
+        ("ru", cyrillic),
+        ("ar", arabic),
+        ("latin", latin),
+    ];
+    let (script, count) = scripts.iter().max_by_key(|(_, count)| *count).copied().unwrap_or(("latin", 0));
+    if count == 0 {
+        return "und".to_string();
+    }
+    if script != "latin" {
+        return script.to_string();
+    }
+
+    let words: Vec<String> = tokenize_words(content);
+    // 命中数相同时取列表中靠前的语言
+    LATIN_STOPWORDS
+        .iter()
+        .rev()
+        .map(|(code, stopwords)| (*code, words.iter().filter(|w| stopwords.contains(&w.as_str())).count()))
+        .max_by_key(|(_, hits)| *hits)
+        .filter(|(_, hits)| *hits > 0)
+        .map(|(code, _)| code.to_string())
+        .unwrap_or_else(|| "en".to_string())
+}
+
 // ============ 注解操作 ============
 
 pub fn get_annotations_by_doc(conn: &Connection, doc_id: &str) -> Result<Vec<AnnotationRecord>, String> {
     let mut stmt = conn.prepare("
         SELECT id, document_id, user_id, user_name, text, note, note_visible,
                note_position_x, note_position_y, note_width, note_height,
-               highlight_color, highlight_type, anchor_data, created_at, updated_at
+               highlight_color, highlight_type, anchor_data, created_at, updated_at, tags
         FROM annotations WHERE document_id = ?
+        ORDER BY reading_order IS NULL, reading_order, created_at
     ").map_err(|e| e.to_string())?;
     let mut rows = stmt.query([doc_id]).map_err(|e| e.to_string())?;
 
@@ -338,11 +1520,41 @@ pub fn get_annotations_by_doc(conn: &Connection, doc_id: &str) -> Result<Vec<Ann
     Ok(results)
 }
 
+// 按原文位置重新计算阅读顺序并写入 reading_order（从 0 开始，无法定位的排在最后），返回写入的注解数
+pub fn reindex_annotation_order(conn: &Connection, doc_id: &str, content: &str) -> Result<usize, String> {
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let indexed = write_reading_order(&tx, doc_id, content)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(indexed)
+}
+
+// 同上但不开启事务，供已在事务中的新增与改写路径在末尾调用
+fn write_reading_order(conn: &Connection, doc_id: &str, content: &str) -> Result<usize, String> {
+    let annotations = get_annotations_by_doc(conn, doc_id)?;
+    let mut indexed = 0;
+    for (rank, (anno, _)) in sort_by_position(annotations, content).iter().enumerate() {
+        conn.execute(
+            "UPDATE annotations SET reading_order = ? WHERE id = ?",
+            params![rank as i64, anno.id],
+        ).map_err(|e| e.to_string())?;
+        indexed += 1;
+    }
+    Ok(indexed)
+}
+
+// 以数据库中的文档内容重算阅读顺序；文档不存在时跳过
+fn refresh_reading_order(conn: &Connection, doc_id: &str) -> Result<(), String> {
+    if let Some(doc) = get_document_by_id(conn, doc_id)? {
+        write_reading_order(conn, doc_id, &doc.content)?;
+    }
+    Ok(())
+}
+
 pub fn get_annotation_by_id(conn: &Connection, id: &str) -> Result<Option<AnnotationRecord>, String> {
     let mut stmt = conn.prepare("
         SELECT id, document_id, user_id, user_name, text, note, note_visible,
                note_position_x, note_position_y, note_width, note_height,
-               highlight_color, highlight_type, anchor_data, created_at, updated_at
+               highlight_color, highlight_type, anchor_data, created_at, updated_at, tags
         FROM annotations WHERE id = ?
     ").map_err(|e| e.to_string())?;
     let mut rows = stmt.query([id]).map_err(|e| e.to_string())?;
@@ -354,9 +1566,65 @@ pub fn get_annotation_by_id(conn: &Connection, id: &str) -> Result<Option<Annota
     }
 }
 
-fn row_to_annotation(row: &Row) -> Result<AnnotationRecord, String> {
-    Ok(AnnotationRecord {
-        id: row.get(0).map_err(|e| e.to_string())?,
+// 尚未写笔记的注解（待办队列），按原文位置排序
+pub fn get_unnoted_annotations(conn: &Connection, doc_id: &str) -> Result<Vec<AnnotationRecord>, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+
+    let mut stmt = conn.prepare("
+        SELECT id, document_id, user_id, user_name, text, note, note_visible,
+               note_position_x, note_position_y, note_width, note_height,
+               highlight_color, highlight_type, anchor_data, created_at, updated_at, tags
+        FROM annotations WHERE document_id = ? AND (note IS NULL OR TRIM(note) = '')
+    ").map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([doc_id]).map_err(|e| e.to_string())?;
+
+    let mut results = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        results.push(row_to_annotation(row)?);
+    }
+
+    Ok(sort_by_position(results, &doc.content)
+        .into_iter()
+        .map(|(anno, _)| anno)
+        .collect())
+}
+
+// 获取锚点范围与 [start, end) 相交的注解，锚点无法解析的注解跳过
+pub fn get_annotations_in_range(conn: &Connection, doc_id: &str, start: usize, end: usize) -> Result<Vec<AnnotationRecord>, String> {
+    if start > end {
+        return Err(format!("Invalid range: {}..{}", start, end));
+    }
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+
+    let mut stmt = conn.prepare("
+        SELECT id, document_id, user_id, user_name, text, note, note_visible,
+               note_position_x, note_position_y, note_width, note_height,
+               highlight_color, highlight_type, anchor_data, created_at, updated_at, tags
+        FROM annotations WHERE document_id = ?
+    ").map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([doc_id]).map_err(|e| e.to_string())?;
+
+    let mut results = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        let anno = row_to_annotation(row)?;
+        let (anno_start, anno_end) = match locate_annotation(&anno, &doc.content) {
+            Some(range) => range,
+            None => continue,
+        };
+        if anno_start < end && start < anno_end {
+            results.push((anno_start, anno));
+        }
+    }
+
+    results.sort_by_key(|(pos, _)| *pos);
+    Ok(results.into_iter().map(|(_, anno)| anno).collect())
+}
+
+fn row_to_annotation(row: &Row) -> Result<AnnotationRecord, String> {
+    Ok(AnnotationRecord {
+        id: row.get(0).map_err(|e| e.to_string())?,
         document_id: row.get(1).map_err(|e| e.to_string())?,
         user_id: row.get(2).map_err(|e| e.to_string())?,
         user_name: row.get(3).map_err(|e| e.to_string())?,
@@ -372,18 +1640,27 @@ fn row_to_annotation(row: &Row) -> Result<AnnotationRecord, String> {
         anchor_data: row.get(13).map_err(|e| e.to_string())?,
         created_at: row.get(14).map_err(|e| e.to_string())?,
         updated_at: row.get(15).map_err(|e| e.to_string())?,
+        tags: parse_tags(row.get::<_, Option<String>>(16).map_err(|e| e.to_string())?.as_deref()),
+        comments: Vec::new(),
+        context: None,
     })
 }
 
+// 新增单条注解并重算所在文档的阅读顺序；批量写入使用 insert_annotation，最后统一重算
 pub fn add_annotation(conn: &Connection, annotation: &AnnotationRecord) -> Result<(), String> {
+    insert_annotation(conn, annotation)?;
+    refresh_reading_order(conn, &annotation.document_id)
+}
+
+fn insert_annotation(conn: &Connection, annotation: &AnnotationRecord) -> Result<(), String> {
     let now = Utc::now().timestamp_millis();
 
     conn.execute("
         INSERT INTO annotations (
             id, document_id, user_id, user_name, text, note, note_visible,
             note_position_x, note_position_y, note_width, note_height,
-            highlight_color, highlight_type, anchor_data, created_at, updated_at
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            highlight_color, highlight_type, anchor_data, created_at, updated_at, tags
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
     ", params![
         annotation.id,
         annotation.document_id,
@@ -400,7 +1677,8 @@ pub fn add_annotation(conn: &Connection, annotation: &AnnotationRecord) -> Resul
         annotation.highlight_type,
         annotation.anchor_data,
         annotation.created_at,
-        now
+        now,
+        serde_json::to_string(&annotation.tags).map_err(|e| e.to_string())?
     ]).map_err(|e| e.to_string())?;
 
     Ok(())
@@ -439,242 +1717,2995 @@ pub fn update_annotation(conn: &Connection, annotation: &AnnotationRecord) -> Re
     Ok(())
 }
 
-pub fn delete_annotation(conn: &Connection, id: &str) -> Result<(), String> {
-    conn.execute("DELETE FROM annotations WHERE id = ?", params![id])
-        .map_err(|e| e.to_string())?;
-    Ok(())
+// 批量修改高亮样式，返回被修改的注解数量
+pub fn change_highlight_type(conn: &Connection, doc_id: &str, from_type: &str, to_type: &str) -> Result<usize, String> {
+    let from_type = HighlightType::parse(from_type)?;
+    let to_type = HighlightType::parse(to_type)?;
+    let now = Utc::now().timestamp_millis();
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let changed = tx.execute(
+        "UPDATE annotations SET highlight_type = ?, updated_at = ? WHERE document_id = ? AND highlight_type = ?",
+        params![to_type.as_str(), now, doc_id, from_type.as_str()],
+    ).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(changed)
 }
 
-// ============ 单注解导出/导入 ============
+// 一次性显示或隐藏文档中的全部便签，返回更新的注解数量
+pub fn set_all_notes_visible(conn: &Connection, doc_id: &str, visible: bool) -> Result<usize, String> {
+    let now = Utc::now().timestamp_millis();
 
-pub fn export_annotation(conn: &Connection, anno_id: &str, doc_path: &str) -> Result<String, String> {
-    let annotation = get_annotation_by_id(conn, anno_id)?
-        .ok_or_else(|| "Annotation not found".to_string())?;
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let changed = tx.execute(
+        "UPDATE annotations SET note_visible = ?, updated_at = ? WHERE document_id = ?",
+        params![visible, now, doc_id],
+    ).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
 
-    let doc = get_document_by_path(conn, doc_path)?
-        .ok_or_else(|| "Document not found".to_string())?;
+    Ok(changed)
+}
 
-    let package = BatchPackage {
-        version: "1.0".to_string(),
-        exported_at: Utc::now().timestamp_millis(),
-        source_document: Some(SourceDocumentInfo {
-            name: std::path::Path::new(&doc.path)
-                .file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string(),
-            checksum: doc.checksum,
-        }),
-        annotations: vec![annotation],
-    };
+// 将便签坐标限制在 [0, max] 内，返回被调整的注解数量
+pub fn clamp_note_positions(conn: &Connection, doc_id: &str, max_x: f64, max_y: f64) -> Result<usize, String> {
+    if !max_x.is_finite() || !max_y.is_finite() || max_x < 0.0 || max_y < 0.0 {
+        return Err(format!("Invalid bounds: {}x{}", max_x, max_y));
+    }
+    let now = Utc::now().timestamp_millis();
 
-    serde_json::to_string_pretty(&package).map_err(|e| e.to_string())
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let changed = tx.execute(
+        "UPDATE annotations SET
+            note_position_x = MIN(MAX(note_position_x, 0), ?1),
+            note_position_y = MIN(MAX(note_position_y, 0), ?2),
+            updated_at = ?3
+         WHERE document_id = ?4
+           AND (note_position_x < 0 OR note_position_x > ?1 OR note_position_y < 0 OR note_position_y > ?2)",
+        params![max_x, max_y, now, doc_id],
+    ).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(changed)
 }
 
-pub fn import_annotation(json: &str) -> Result<Vec<AnnotationRecord>, String> {
-    let package: AnnotationPackage = serde_json::from_str(json)
-        .map_err(|e| e.to_string())?;
+// 批量替换笔记内容，返回被修改的笔记数量
+pub fn replace_in_notes(conn: &Connection, doc_id: &str, find: &str, replace: &str, case_sensitive: bool) -> Result<usize, String> {
+    if find.is_empty() {
+        return Err("Find text cannot be empty".to_string());
+    }
 
-    let annotations = match package {
-        AnnotationPackage::Batch(batch) => {
-            if batch.version != "1.0" {
-                return Err("Unsupported version".to_string());
-            }
-            batch.annotations
+    let now = Utc::now().timestamp_millis();
+    let annotations = get_annotations_by_doc(conn, doc_id)?;
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut changed = 0;
+
+    for anno in annotations {
+        let note = match anno.note {
+            Some(n) => n,
+            None => continue,
+        };
+        let replaced = if case_sensitive {
+            note.replace(find, replace)
+        } else {
+            replace_ignore_case(&note, find, replace)
+        };
+        if replaced == note {
+            continue;
         }
-        AnnotationPackage::Single(single) => {
-            if single.version != "1.0" {
-                return Err("Unsupported version".to_string());
+
+        tx.execute(
+            "UPDATE annotations SET note = ?, updated_at = ? WHERE id = ?",
+            params![replaced, now, anno.id],
+        ).map_err(|e| e.to_string())?;
+        changed += 1;
+    }
+
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(changed)
+}
+
+fn replace_ignore_case(text: &str, find: &str, replace: &str) -> String {
+    let needle: Vec<char> = find.chars().flat_map(char::to_lowercase).collect();
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        // 尝试在当前位置匹配（逐字符转小写比较）
+        let mut lowered = Vec::new();
+        let mut matched_len = None;
+        for (i, ch) in rest.char_indices() {
+            lowered.extend(ch.to_lowercase());
+            if !needle.starts_with(&lowered) {
+                break;
+            }
+            if lowered.len() == needle.len() {
+                matched_len = Some(i + ch.len_utf8());
+                break;
             }
-            vec![single.annotation]
         }
-    };
 
-    // 生成新 ID，避免冲突
-    let mut result = Vec::new();
-    for mut anno in annotations {
-        anno.id = Uuid::new_v4().to_string();
-        result.push(anno);
+        match matched_len {
+            Some(len) => {
+                result.push_str(replace);
+                rest = &rest[len..];
+            }
+            None => {
+                result.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
     }
 
-    Ok(result)
+    result
 }
 
-pub fn merge_imported_annotation(conn: &Connection, annotation: &AnnotationRecord, doc_id: &str) -> Result<(), String> {
-    let mut annotation = annotation.clone();
-    annotation.document_id = doc_id.to_string();
-    annotation.created_at = Utc::now().timestamp_millis();
+pub fn delete_annotation(conn: &Connection, id: &str) -> Result<(), String> {
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+
+    if let Some(anno) = get_annotation_by_id(&tx, id)? {
+        log_deletion(&tx, "delete_annotation", DeletedRows {
+            documents: Vec::new(),
+            annotations: with_comments(&tx, vec![anno])?,
+            ..Default::default()
+        })?;
+    }
 
-    add_annotation(conn, &annotation)
+    tx.execute("DELETE FROM comments WHERE annotation_id = ?", params![id])
+        .map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM annotations WHERE id = ?", params![id])
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(())
 }
 
-// 批量导入并去重
-pub fn merge_imported_annotations(conn: &Connection, annotations: &[AnnotationRecord], doc_id: &str) -> Result<usize, String> {
-    let now = Utc::now().timestamp_millis();
-    let mut imported_count = 0;
+// 合并文本相同且锚点重叠的重复注解：保留最早创建的一条，合并不同的笔记，返回删除数量
+pub fn dedupe_annotations(conn: &Connection, doc_id: &str) -> Result<usize, String> {
+    let mut annotations = get_annotations_by_doc(conn, doc_id)?;
+    annotations.sort_by_key(|a| a.created_at);
 
-    // 获取现有的注解文本集合（用于去重）
-    let existing_texts: std::collections::HashSet<String> = {
-        let mut stmt = conn.prepare("SELECT text FROM annotations WHERE document_id = ?")
-            .map_err(|e| e.to_string())?;
-        let mut rows = stmt.query([doc_id]).map_err(|e| e.to_string())?;
-        let mut texts = std::collections::HashSet::new();
-        while let Ok(Some(row)) = rows.next() {
-            if let Ok(text) = row.get::<_, String>(0) {
-                texts.insert(text);
+    let mut kept: Vec<AnnotationRecord> = Vec::new();
+    let mut merged: Vec<(usize, AnnotationRecord)> = Vec::new(); // (保留项下标, 被合并的注解)
+
+    for anno in annotations {
+        let target = kept.iter().position(|k| k.text == anno.text && anchors_overlap(&k.anchor_data, &anno.anchor_data));
+        match target {
+            Some(index) => {
+                let keeper = &mut kept[index];
+                if let Some(note) = anno.note.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+                    match keeper.note.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+                        None => keeper.note = Some(note.to_string()),
+                        Some(existing) if !existing.split("\n\n").any(|part| part == note) => {
+                            keeper.note = Some(format!("{}\n\n{}", existing, note));
+                        }
+                        Some(_) => {}
+                    }
+                }
+                merged.push((index, anno));
             }
+            None => kept.push(anno),
         }
-        texts
-    };
+    }
 
-    for mut anno in annotations.iter().cloned() {
-        // 去重：检查文本是否已存在
-        if existing_texts.contains(&anno.text) {
-            continue;
-        }
+    if merged.is_empty() {
+        return Ok(0);
+    }
 
-        // 生成新 ID
-        anno.id = Uuid::new_v4().to_string();
-        anno.document_id = doc_id.to_string();
-        anno.created_at = now;
-        anno.updated_at = now;
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let duplicates: Vec<AnnotationRecord> = merged.iter().map(|(_, duplicate)| duplicate.clone()).collect();
+    log_deletion(&tx, "dedupe_annotations", DeletedRows {
+        documents: Vec::new(),
+        annotations: with_comments(&tx, duplicates)?,
+        ..Default::default()
+    })?;
+    for (index, duplicate) in &merged {
+        let keeper = &kept[*index];
+        // 评论转移到保留的注解上
+        tx.execute(
+            "UPDATE comments SET annotation_id = ? WHERE annotation_id = ?",
+            params![keeper.id, duplicate.id],
+        ).map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM annotations WHERE id = ?", params![duplicate.id])
+            .map_err(|e| e.to_string())?;
+    }
 
-        add_annotation(conn, &anno)?;
-        imported_count += 1;
+    let mut updated: Vec<usize> = merged.iter().map(|(index, _)| *index).collect();
+    updated.sort_unstable();
+    updated.dedup();
+    for index in updated {
+        update_annotation(&tx, &kept[index])?;
     }
+    tx.commit().map_err(|e| e.to_string())?;
 
-    Ok(imported_count)
+    Ok(merged.len())
 }
 
-// ============ HTML 导出 ============
+// 将 merge_id 的笔记以空行分隔追加到 keep_id 上，锚点与颜色沿用 keep_id；评论与标签一并转移，随后删除 merge_id
+pub fn merge_annotations(conn: &Connection, keep_id: &str, merge_id: &str) -> Result<AnnotationRecord, String> {
+    if keep_id == merge_id {
+        return Err("Cannot merge an annotation into itself".to_string());
+    }
+    let mut keeper = get_annotation_by_id(conn, keep_id)?
+        .ok_or_else(|| format!("Annotation not found: {}", keep_id))?;
+    let merged = get_annotation_by_id(conn, merge_id)?
+        .ok_or_else(|| format!("Annotation not found: {}", merge_id))?;
+    if keeper.document_id != merged.document_id {
+        return Err("Cannot merge annotations from different documents".to_string());
+    }
 
-pub fn export_as_html(conn: &Connection, doc_id: &str, anno_ids: &[String], content: &str) -> Result<String, String> {
-    let doc = {
-        let mut stmt = conn.prepare("SELECT id, path FROM documents WHERE id = ?")
-            .map_err(|e| e.to_string())?;
-        let mut rows = stmt.query([doc_id]).map_err(|e| e.to_string())?;
-        if let Some(row) = rows.next().map_err(|e| e.to_string())? {
-            Some(DocumentRecord {
-                id: row.get(0).map_err(|e| e.to_string())?,
-                path: row.get(1).map_err(|e| e.to_string())?,
-                content: content.to_string(),
-                checksum: String::new(),
-                last_modified: 0,
-                created_at: 0,
-            })
-        } else {
-            None
-        }
-    }.ok_or_else(|| "Document not found".to_string())?;
+    let previous = keeper.clone();
 
-    let mut annotations = Vec::new();
-    for anno_id in anno_ids {
-        if let Some(anno) = get_annotation_by_id(conn, anno_id)? {
-            annotations.push(anno);
-        }
+    if let Some(note) = merged.note.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+        keeper.note = match keeper.note.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+            Some(existing) => Some(format!("{}\n\n{}", existing, note)),
+            None => Some(note.to_string()),
+        };
     }
 
-    // 直接使用前端传来的已渲染 HTML，不再重复解析
-    let html_content = doc.content.clone();
+    let mut tags = keeper.tags.clone();
+    for tag in &merged.tags {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
 
-    // 生成 HTML
-    let html = generate_readonly_html(&doc.path, &html_content, &annotations);
+    // 保留的注解也记录修改前的版本，撤销时笔记与标签一并还原
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    log_deletion(&tx, "merge_annotations", DeletedRows {
+        documents: Vec::new(),
+        annotations: with_comments(&tx, vec![previous, merged])?,
+        ..Default::default()
+    })?;
+    tx.execute(
+        "UPDATE comments SET annotation_id = ? WHERE annotation_id = ?",
+        params![keep_id, merge_id],
+    ).map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM annotations WHERE id = ?", params![merge_id])
+        .map_err(|e| e.to_string())?;
+    update_annotation(&tx, &keeper)?;
+    set_annotation_tags(&tx, keep_id, &tags)?;
+    tx.commit().map_err(|e| e.to_string())?;
 
-    Ok(html)
+    get_annotation_by_id(conn, keep_id)?
+        .ok_or_else(|| "Annotation not found".to_string())
 }
 
-#[allow(dead_code)]
-fn markdown_to_html(markdown: &str) -> String {
-    // 简化版：实际应集成 marked 或 pulldown-cmark
-    let mut html = markdown
-        .replace("&", "&amp;")
-        .replace("<", "&lt;")
-        .replace(">", "&gt;")
-        .replace("# ", "<h1>")
-        .replace("\n## ", "</h1>\n<h2>")
-        .replace("\n### ", "</h2>\n<h3>")
-        .replace("\n#### ", "</h3>\n<h4>")
-        .replace("\n##### ", "</h4>\n<h5>")
-        .replace("\n###### ", "</h5>\n<h6>")
-        .replace("\n", "<br>\n");
+// 在原文偏移 split_offset 处将注解拆成前后两条，元数据与笔记复制到两者，评论保留在前一条上
+pub fn split_annotation(conn: &Connection, id: &str, split_offset: usize, content: &str) -> Result<(AnnotationRecord, AnnotationRecord), String> {
+    let original = get_annotation_by_id(conn, id)?
+        .ok_or_else(|| "Annotation not found".to_string())?;
+    let (start, end) = locate_annotation(&original, content)
+        .ok_or_else(|| "Annotation anchor could not be resolved".to_string())?;
 
-    // 简单的代码块
-    if let Some(start) = html.find("```") {
-        if let Some(end) = html[start+3..].find("```") {
-            let code_start = start + 3;
-            let code_end = start + 3 + end;
-            let code = &html[code_start..code_end];
-            let before = &html[..code_start];
-            let after = &html[code_end + 3..];
-            html = format!("{}<pre><code>{}</code></pre>{}", before, code, after);
-        }
+    if split_offset <= start || split_offset >= end {
+        return Err(format!("Split offset {} is outside annotation range {}..{}", split_offset, start, end));
+    }
+    if !content.is_char_boundary(split_offset) {
+        return Err(format!("Split offset {} is not on a character boundary", split_offset));
     }
 
-    // 简单的粗体和斜体
-    html = html.replace("**", "<strong>").replace("*", "<em>");
+    let now = Utc::now().timestamp_millis();
+    let part = |range: (usize, usize)| AnnotationRecord {
+        id: Uuid::new_v4().to_string(),
+        text: content[range.0..range.1].to_string(),
+        anchor_data: build_text_anchor(range.0, range.1),
+        created_at: now,
+        updated_at: now,
+        comments: Vec::new(),
+        ..original.clone()
+    };
+    let first = part((start, split_offset));
+    let second = part((split_offset, end));
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    // 评论转移前记录原注解，撤销时恢复原注解与评论并删除拆分出的两条
+    log_deletion(&tx, "split_annotation", DeletedRows {
+        documents: Vec::new(),
+        annotations: with_comments(&tx, vec![original])?,
+        created: vec![first.id.clone(), second.id.clone()],
+        ..Default::default()
+    })?;
+    insert_annotation(&tx, &first)?;
+    insert_annotation(&tx, &second)?;
+    tx.execute(
+        "UPDATE comments SET annotation_id = ? WHERE annotation_id = ?",
+        params![first.id, id],
+    ).map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM annotations WHERE id = ?", params![id])
+        .map_err(|e| e.to_string())?;
+    write_reading_order(&tx, &first.document_id, content)?;
+    tx.commit().map_err(|e| e.to_string())?;
 
-    // 简单的列表
-    html = html.replace("- ", "<li>");
+    Ok((first, second))
+}
 
-    html
+// 同一文本节点内的区间有交集即视为重叠
+fn anchors_overlap(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    let (a, b) = (parse_anchors(a), parse_anchors(b));
+    a.iter().any(|x| b.iter().any(|y| {
+        x.container_path == y.container_path
+            && x.text_node_index == y.text_node_index
+            && x.start_offset < y.end_offset
+            && y.start_offset < x.end_offset
+    }))
 }
 
-fn generate_readonly_html(_doc_name: &str, content: &str, annotations: &[AnnotationRecord]) -> String {
-    let mut notes_html = String::new();
+// ============ 注解归档 ============
 
-    for anno in annotations {
-        let empty_note = String::new();
-        let note_text = anno.note.as_ref().unwrap_or(&empty_note);
-        let style = format!(
-            "left: {:.0}px; top: {:.0}px; width: {:.0}px; height: {:.0}px;",
-            anno.note_position_x, anno.note_position_y,
-            anno.note_width, anno.note_height
-        );
+const ANNOTATION_COLUMNS: &str = "id, document_id, user_id, user_name, text, note, note_visible, \
+    note_position_x, note_position_y, note_width, note_height, highlight_color, highlight_type, \
+    anchor_data, created_at, updated_at, tags, reading_order";
 
-        notes_html.push_str(&format!(r#"
-        <div class="sticky-note" data-anno-id="{}" style="{}">
-            <div class="note-header">
-                <span class="note-author">{}</span>
-                <button class="note-close" onclick="closeNote('{}')">&times;</button>
-            </div>
-            <div class="note-content">{}</div>
-        </div>
-        "#,
-            anno.id, style,
-            escape_html(&anno.user_name),
-            anno.id,
-            escape_html(note_text)
-        ));
+const COMMENT_COLUMNS: &str = "id, annotation_id, user_id, user_name, body, created_at, parent_id";
+
+// 将文档的注解及其评论移入归档表，之后的常规查询不再返回这些注解。返回归档数量
+pub fn archive_annotations(conn: &Connection, doc_id: &str) -> Result<usize, String> {
+    if get_document_by_id(conn, doc_id)?.is_none() {
+        return Err("Document not found".to_string());
     }
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let moved = move_annotations(&tx, doc_id, true)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(moved)
+}
 
-    let payload = serde_json::to_string(&annotations).unwrap_or_default();
+// 将归档的注解及其评论移回常规表，返回恢复数量
+pub fn unarchive_annotations(conn: &Connection, doc_id: &str) -> Result<usize, String> {
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let moved = move_annotations(&tx, doc_id, false)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(moved)
+}
 
-    // 注意：使用 format! 和 HTML 手动拼接，避免 script 中 {} 出现问题
-    let html = format!(r#"<!DOCTYPE html>
-<html>
-<head>
-    <meta charset="utf-8">
-    <title>Annotated</title>
-    <style>
-        * {{ margin: 0; padding: 0; box-sizing: border-box; }}
-        body {{ font-family: system-ui, -apple-system, sans-serif; background: #242424; color: #ddd; font-size: 16px !important; line-height: 1.6 !important; position: relative; }}
-        .container {{ max-width: 900px; margin: 0 auto; padding: 20px; }}
-        .container h1 {{ font-size: 2em !important; color: #fff !important; margin: 1em 0 0.5em !important; }}
-        .container h2 {{ font-size: 1.5em !important; color: #fff !important; margin: 1em 0 0.5em !important; }}
-        .container h3 {{ font-size: 1.25em !important; color: #fff !important; margin: 1em 0 0.5em !important; }}
-        .container h4 {{ font-size: 1.1em !important; color: #fff !important; margin: 1em 0 0.5em !important; }}
-        .container h5 {{ font-size: 1em !important; color: #fff !important; margin: 1em 0 0.5em !important; }}
-        .container h6 {{ font-size: 0.9em !important; color: #aaa !important; margin: 1em 0 0.5em !important; }}
-        .container p {{ font-size: 1em !important; margin: 0.8em 0 !important; }}
-        .container ul, .container ol {{ font-size: 1em !important; margin: 0.8em 0 !important; padding-left: 2em; }}
-        .container li {{ font-size: 1em !important; margin: 0.3em 0; }}
-        .container blockquote {{ font-size: 1em !important; margin: 0.8em 0; padding-left: 1em; border-left: 3px solid #444; color: #999; }}
-        .markdown-body {{ position: relative; }}
-        .markdown-body pre {{ background: #1a1a1a; padding: 1em; overflow-x: auto; border-radius: 4px; }}
-        .markdown-body code {{ background: #1a1a1a; padding: 0.2em 0.4em; border-radius: 3px; }}
-        .doc-highlight {{
-            background: rgba(255, 215, 0, 0.3);
-            border-bottom: 2px solid gold;
+// 在 annotations/comments 与对应归档表之间移动文档的注解和评论，需在事务中调用；
+// 移入时先插注解再插评论、先删评论再删注解，保证外键始终有效
+fn move_annotations(conn: &Connection, doc_id: &str, to_archive: bool) -> Result<usize, String> {
+    let (annotations_from, comments_from, comments_to) = if to_archive {
+        ("annotations", "comments", "comments_archive")
+    } else {
+        ("annotations_archive", "comments_archive", "comments")
+    };
+
+    let moved = if to_archive {
+        conn.execute(
+            &format!("INSERT OR REPLACE INTO annotations_archive ({0}, archived_at) SELECT {0}, ? FROM annotations WHERE document_id = ?", ANNOTATION_COLUMNS),
+            params![Utc::now().timestamp_millis(), doc_id],
+        )
+    } else {
+        conn.execute(
+            &format!("INSERT OR REPLACE INTO annotations ({0}) SELECT {0} FROM annotations_archive WHERE document_id = ?", ANNOTATION_COLUMNS),
+            params![doc_id],
+        )
+    }.map_err(|e| e.to_string())?;
+
+    let doc_comments = format!("annotation_id IN (SELECT id FROM {} WHERE document_id = ?)", annotations_from);
+    conn.execute(
+        &format!("INSERT OR REPLACE INTO {1} ({0}) SELECT {0} FROM {2} WHERE {3}", COMMENT_COLUMNS, comments_to, comments_from, doc_comments),
+        params![doc_id],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(&format!("DELETE FROM {} WHERE {}", comments_from, doc_comments), params![doc_id])
+        .map_err(|e| e.to_string())?;
+    conn.execute(&format!("DELETE FROM {} WHERE document_id = ?", annotations_from), params![doc_id])
+        .map_err(|e| e.to_string())?;
+    Ok(moved)
+}
+
+// ============ 崩溃恢复日志 ============
+
+#[derive(Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub operation: String, // "add" 或 "update"
+    pub annotation: AnnotationRecord,
+    pub logged_at: i64,
+}
+
+pub fn get_journal_path() -> std::path::PathBuf {
+    let mut path = get_app_data_dir();
+    fs::create_dir_all(&path).ok();
+    path.push("journal.jsonl");
+    path
+}
+
+// 日志只保存尚未写入数据库的修改：写入前追加一行，写入结束（无论成败）后清空。
+// 调用方需持有共享写连接，保证同一时刻最多只有一条在途记录
+pub fn with_journal<T>(operation: &str, annotation: &AnnotationRecord, write: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    append_journal(operation, annotation)?;
+    let result = write();
+    if let Err(e) = clear_journal() {
+        log_error(&format!("Failed to clear journal: {}", e));
+    }
+    result
+}
+
+fn clear_journal() -> Result<(), String> {
+    let path = get_journal_path();
+    if path.exists() {
+        fs::write(&path, "").map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// 追加一条日志，每行一条 JSON
+fn append_journal(operation: &str, annotation: &AnnotationRecord) -> Result<(), String> {
+    use std::io::Write;
+
+    let entry = JournalEntry {
+        operation: operation.to_string(),
+        annotation: annotation.clone(),
+        logged_at: Utc::now().timestamp_millis(),
+    };
+    let mut line = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+    line.push('\n');
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(get_journal_path())
+        .map_err(|e| e.to_string())?;
+    file.write_all(line.as_bytes()).map_err(|e| e.to_string())
+}
+
+// 回放日志中数据库里不存在或比数据库更新的注解（同一注解取最后一条记录），完成后清空日志，返回恢复数量
+// 已写入数据库的条目会被跳过，已归档的注解不会回放，因此可以在每次启动时调用
+pub fn recover_from_journal(conn: &Connection) -> Result<usize, String> {
+    let path = get_journal_path();
+    if !path.exists() {
+        return Ok(0);
+    }
+    let content = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+
+    // 崩溃时最后一行可能只写了一半，无法解析的行直接跳过
+    let mut latest: Vec<AnnotationRecord> = Vec::new();
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        if let Ok(entry) = serde_json::from_str::<JournalEntry>(line) {
+            match latest.iter_mut().find(|a| a.id == entry.annotation.id) {
+                Some(existing) => *existing = entry.annotation,
+                None => latest.push(entry.annotation),
+            }
+        }
+    }
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut recovered = 0;
+    let mut touched: Vec<String> = Vec::new();
+    for anno in latest {
+        if get_document_by_id(&tx, &anno.document_id)?.is_none() {
+            continue;
+        }
+        let archived = tx.prepare("SELECT 1 FROM annotations_archive WHERE id = ?")
+            .and_then(|mut stmt| stmt.exists([&anno.id]))
+            .map_err(|e| e.to_string())?;
+        if archived {
+            continue;
+        }
+        match get_annotation_by_id(&tx, &anno.id)? {
+            // 日志中的修改比数据库中的新，说明更新未能写入
+            Some(existing) if existing.document_id == anno.document_id && anno.updated_at > existing.updated_at => {
+                update_annotation(&tx, &anno)?;
+            }
+            Some(_) => continue,
+            None => insert_annotation(&tx, &anno)?,
+        }
+        if !touched.contains(&anno.document_id) {
+            touched.push(anno.document_id.clone());
+        }
+        recovered += 1;
+    }
+    for doc_id in &touched {
+        refresh_reading_order(&tx, doc_id)?;
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+
+    clear_journal()?;
+    Ok(recovered)
+}
+
+// ============ 应用日志 ============
+
+// 单个日志文件上限，超过后轮转为 annoti.log.1 … annoti.log.N
+const LOG_MAX_BYTES: u64 = 1024 * 1024;
+const LOG_ROTATE_KEEP: usize = 3;
+
+static LOG_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn get_log_dir() -> std::path::PathBuf {
+    let mut path = get_app_data_dir();
+    path.push("logs");
+    fs::create_dir_all(&path).ok();
+    path
+}
+
+pub fn get_log_path() -> std::path::PathBuf {
+    let mut path = get_log_dir();
+    path.push("annoti.log");
+    path
+}
+
+fn rotated_log_path(index: usize) -> std::path::PathBuf {
+    let mut path = get_log_dir();
+    path.push(format!("annoti.log.{}", index));
+    path
+}
+
+fn rotate_logs() {
+    let _ = fs::remove_file(rotated_log_path(LOG_ROTATE_KEEP));
+    for i in (1..LOG_ROTATE_KEEP).rev() {
+        let _ = fs::rename(rotated_log_path(i), rotated_log_path(i + 1));
+    }
+    let _ = fs::rename(get_log_path(), rotated_log_path(1));
+}
+
+// 写入一行日志；打包后的应用没有控制台，写失败时只能静默忽略
+pub fn write_log(level: &str, message: &str) {
+    use std::io::Write;
+
+    let _guard = match LOG_LOCK.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let path = get_log_path();
+    if fs::metadata(&path).map(|m| m.len() >= LOG_MAX_BYTES).unwrap_or(false) {
+        rotate_logs();
+    }
+
+    let line = format!(
+        "{} [{}] {}\n",
+        Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+        level,
+        message.replace('\n', "\n    ")
+    );
+    if let Ok(mut file) = fs::OpenOptions::new().create(true).append(true).open(&path) {
+        let _ = file.write_all(line.as_bytes());
+    }
+}
+
+pub fn log_info(message: &str) {
+    write_log("INFO", message);
+}
+
+pub fn log_warn(message: &str) {
+    write_log("WARN", message);
+}
+
+pub fn log_error(message: &str) {
+    write_log("ERROR", message);
+}
+
+// 返回最近的 lines 行日志，当前文件不足时从上一个轮转文件补齐
+pub fn read_recent_logs(lines: usize) -> Result<String, String> {
+    let _guard = LOG_LOCK.lock().map_err(|e| e.to_string())?;
+
+    let mut collected: Vec<String> = Vec::new();
+    let files = std::iter::once(get_log_path()).chain((1..=LOG_ROTATE_KEEP).map(rotated_log_path));
+    for path in files {
+        if collected.len() >= lines {
+            break;
+        }
+        if !path.exists() {
+            continue;
+        }
+        let content = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let needed = lines - collected.len();
+        let mut tail: Vec<String> = content.lines().rev().take(needed).map(|l| l.to_string()).collect();
+        tail.reverse();
+        tail.append(&mut collected);
+        collected = tail;
+    }
+    Ok(collected.join("\n"))
+}
+
+// ============ 撤销日志 ============
+
+// 只保留最近的若干条破坏性操作
+const OPERATION_LOG_LIMIT: i64 = 50;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct DeletedRows {
+    pub documents: Vec<DocumentRecord>,
+    pub annotations: Vec<AnnotationRecord>,
+    #[serde(default)]
+    pub created: Vec<String>, // 操作新建的注解 ID（如拆分出的两半），撤销时删除
+    #[serde(default)]
+    pub users: Vec<UserRecord>, // 删除用户时移除的用户行，撤销时先于其注解与评论恢复
+    #[serde(default)]
+    pub reading_orders: std::collections::HashMap<String, i64>, // 记录时由 log_deletion 从数据库读取
+}
+
+fn log_deletion(conn: &Connection, operation: &str, mut rows: DeletedRows) -> Result<(), String> {
+    // 阅读顺序不在 AnnotationRecord 中，删除前从表中读出，撤销时原样写回
+    for anno in &rows.annotations {
+        let order: Option<i64> = conn.query_row(
+            "SELECT reading_order FROM annotations WHERE id = ?",
+            [&anno.id],
+            |row| row.get(0),
+        ).optional().map_err(|e| e.to_string())?.flatten();
+        if let Some(order) = order {
+            rows.reading_orders.insert(anno.id.clone(), order);
+        }
+    }
+    let payload = serde_json::to_string(&rows).map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO operation_log (operation, payload, created_at) VALUES (?, ?, ?)",
+        params![operation, payload, Utc::now().timestamp_millis()],
+    ).map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "DELETE FROM operation_log WHERE id NOT IN (SELECT id FROM operation_log ORDER BY id DESC LIMIT ?)",
+        params![OPERATION_LOG_LIMIT],
+    ).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// 恢复最近一次被记录的删除操作，返回操作名称；日志为空时返回 None
+pub fn undo_last_operation(conn: &Connection) -> Result<Option<String>, String> {
+    let entry: Option<(i64, String, String)> = {
+        let mut stmt = conn.prepare("SELECT id, operation, payload FROM operation_log ORDER BY id DESC LIMIT 1")
+            .map_err(|e| e.to_string())?;
+        let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+        match rows.next().map_err(|e| e.to_string())? {
+            Some(row) => Some((
+                row.get(0).map_err(|e| e.to_string())?,
+                row.get(1).map_err(|e| e.to_string())?,
+                row.get(2).map_err(|e| e.to_string())?,
+            )),
+            None => None,
+        }
+    };
+
+    let (log_id, operation, payload) = match entry {
+        Some(e) => e,
+        None => return Ok(None),
+    };
+    let deleted: DeletedRows = serde_json::from_str(&payload).map_err(|e| e.to_string())?;
+    // 恢复快照记录的是恢复前的文档：撤销时换回原内容与文件，并删除只存在于快照中的注解
+    let restoring_snapshot = operation == "restore_snapshot";
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut doc_id_map = std::collections::HashMap::new();
+    let mut touched: Vec<String> = deleted.documents.iter().map(|doc| doc.id.clone()).collect();
+
+    for user in &deleted.users {
+        tx.execute(
+            "INSERT OR IGNORE INTO users (id, name, created_at) VALUES (?, ?, ?)",
+            params![user.id, user.name, user.created_at],
+        ).map_err(|e| e.to_string())?;
+    }
+
+    for doc in &deleted.documents {
+        if restoring_snapshot {
+            tx.execute(
+                "UPDATE documents SET content = ?, checksum = ?, last_modified = ? WHERE id = ?",
+                params![doc.content, doc.checksum, doc.last_modified, doc.id],
+            ).map_err(|e| e.to_string())?;
+            for anno in get_annotations_by_doc(&tx, &doc.id)? {
+                if deleted.annotations.iter().any(|a| a.id == anno.id) {
+                    continue;
+                }
+                tx.execute("DELETE FROM comments WHERE annotation_id = ?", params![anno.id])
+                    .map_err(|e| e.to_string())?;
+                tx.execute("DELETE FROM annotations WHERE id = ?", params![anno.id])
+                    .map_err(|e| e.to_string())?;
+            }
+            continue;
+        }
+
+        tx.execute(
+            "INSERT OR IGNORE INTO documents (id, path, content, checksum, last_modified, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+            params![doc.id, doc.path, doc.content, doc.checksum, doc.last_modified, doc.created_at],
+        ).map_err(|e| e.to_string())?;
+
+        // 删除后同一路径可能已被重新保存，注解需要挂到现有文档上
+        if let Some(existing) = get_document_by_path(&tx, &doc.path)? {
+            doc_id_map.insert(doc.id.clone(), existing.id);
+        }
+    }
+
+    for anno in &deleted.annotations {
+        let mut anno = anno.clone();
+        if let Some(doc_id) = doc_id_map.get(&anno.document_id) {
+            anno.document_id = doc_id.clone();
+        }
+        // 恢复快照时同 ID 的注解已被快照中的版本替换，撤销时换回记录的版本
+        tx.execute("DELETE FROM comments WHERE annotation_id = ?", params![anno.id])
+            .map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM annotations WHERE id = ?", params![anno.id])
+            .map_err(|e| e.to_string())?;
+        restore_annotation_row(&tx, &anno, deleted.reading_orders.get(&anno.id).copied())?;
+        if !touched.contains(&anno.document_id) {
+            touched.push(anno.document_id.clone());
+        }
+        for comment in &anno.comments {
+            // 合并与去重时评论已转到保留的注解上，撤销时移回原注解
+            tx.execute("DELETE FROM comments WHERE id = ?", params![comment.id])
+                .map_err(|e| e.to_string())?;
+            insert_comment(&tx, comment)?;
+        }
+    }
+
+    for id in &deleted.created {
+        tx.execute("DELETE FROM comments WHERE annotation_id = ?", params![id])
+            .map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM annotations WHERE id = ?", params![id])
+            .map_err(|e| e.to_string())?;
+    }
+    // 恢复与删除都会改变阅读顺序，按恢复后的内容重算
+    for doc_id in &touched {
+        refresh_reading_order(&tx, doc_id)?;
+    }
+
+    tx.execute("DELETE FROM operation_log WHERE id = ?", params![log_id])
+        .map_err(|e| e.to_string())?;
+
+    // 与恢复快照相同：数据库修改全部成功后再写回文件，提交失败时恢复被改写的文件
+    let mut previous_files = Vec::new();
+    if restoring_snapshot {
+        for doc in &deleted.documents {
+            previous_files.push((doc.path.clone(), fs::read(&doc.path).ok()));
+            let temp = format!("{}.tmp", doc.path);
+            if let Err(e) = fs::write(&temp, &doc.content).and_then(|_| fs::rename(&temp, &doc.path)) {
+                let _ = fs::remove_file(&temp);
+                restore_files(previous_files);
+                return Err(e.to_string());
+            }
+        }
+    }
+    if let Err(e) = tx.commit() {
+        restore_files(previous_files);
+        return Err(e.to_string());
+    }
+
+    Ok(Some(operation))
+}
+
+// 按记录原样写回注解行；与 add_annotation 不同，保留记录中的 updated_at 与阅读顺序
+fn restore_annotation_row(conn: &Connection, anno: &AnnotationRecord, reading_order: Option<i64>) -> Result<(), String> {
+    conn.execute("
+        INSERT INTO annotations (
+            id, document_id, user_id, user_name, text, note, note_visible,
+            note_position_x, note_position_y, note_width, note_height,
+            highlight_color, highlight_type, anchor_data, created_at, updated_at, tags, reading_order
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+    ", params![
+        anno.id,
+        anno.document_id,
+        anno.user_id,
+        anno.user_name,
+        anno.text,
+        anno.note,
+        if anno.note_visible { 1 } else { 0 },
+        anno.note_position_x,
+        anno.note_position_y,
+        anno.note_width,
+        anno.note_height,
+        anno.highlight_color,
+        anno.highlight_type,
+        anno.anchor_data,
+        anno.created_at,
+        anno.updated_at,
+        serde_json::to_string(&anno.tags).map_err(|e| e.to_string())?,
+        reading_order
+    ]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// 写回文件的原始内容，用于写入后数据库提交失败的回滚
+fn restore_files(files: Vec<(String, Option<Vec<u8>>)>) {
+    for (path, previous) in files {
+        if let Some(previous) = previous {
+            let _ = fs::write(&path, previous);
+        }
+    }
+}
+
+// ============ 注解补丁 ============
+
+// 导出 since_ms 之后新增/修改的注解；删除记录来自撤销日志中已不存在的注解，只覆盖最近 OPERATION_LOG_LIMIT 次删除
+pub fn export_annotation_patch(conn: &Connection, doc_id: &str, since_ms: i64) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+
+    let mut added = Vec::new();
+    let mut updated = Vec::new();
+    let mut current_ids = std::collections::HashSet::new();
+    for anno in with_comments(conn, get_annotations_by_doc(conn, doc_id)?)? {
+        current_ids.insert(anno.id.clone());
+        if anno.created_at >= since_ms {
+            added.push(anno);
+        } else if anno.updated_at >= since_ms {
+            updated.push(anno);
+        }
+    }
+
+    // 恢复快照等操作会记录被替换的注解后以同一 ID 重新写入，仍存在的注解不算删除
+    let mut deleted: Vec<String> = Vec::new();
+    {
+        let mut stmt = conn.prepare("SELECT payload FROM operation_log WHERE created_at >= ? ORDER BY id")
+            .map_err(|e| e.to_string())?;
+        let mut rows = stmt.query([since_ms]).map_err(|e| e.to_string())?;
+        while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            let payload: String = row.get(0).map_err(|e| e.to_string())?;
+            let rows: DeletedRows = serde_json::from_str(&payload).map_err(|e| e.to_string())?;
+            for anno in rows.annotations {
+                if anno.document_id == doc_id && !current_ids.contains(&anno.id) && !deleted.contains(&anno.id) {
+                    deleted.push(anno.id);
+                }
+            }
+        }
+    }
+
+    let patch = AnnotationPatch {
+        version: "1.0".to_string(),
+        exported_at: Utc::now().timestamp_millis(),
+        since: since_ms,
+        source_document: Some(SourceDocumentInfo {
+            name: std::path::Path::new(&doc.path)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            checksum: doc.checksum,
+        }),
+        added,
+        updated,
+        deleted,
+    };
+    serde_json::to_string_pretty(&patch).map_err(|e| e.to_string())
+}
+
+// 应用补丁：按 ID 新增或更新注解并删除已删除的注解，返回变更条数
+pub fn apply_annotation_patch(conn: &Connection, patch_json: &str, doc_path: &str) -> Result<usize, String> {
+    let patch: AnnotationPatch = serde_json::from_str(patch_json).map_err(|e| e.to_string())?;
+    let doc = get_document_by_path(conn, doc_path)?
+        .ok_or_else(|| "Document not found".to_string())?;
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut applied = 0;
+
+    for anno in patch.added.iter().chain(patch.updated.iter()) {
+        let mut anno = anno.clone();
+        anno.document_id = doc.id.clone();
+
+        match get_annotation_by_id(&tx, &anno.id)? {
+            // 同 ID 注解属于其他文档时不覆盖
+            Some(existing) if existing.document_id != doc.id => continue,
+            Some(_) => {
+                update_annotation(&tx, &anno)?;
+                // 旧版补丁不含标签，此时保留现有标签
+                if !anno.tags.is_empty() {
+                    set_annotation_tags(&tx, &anno.id, &anno.tags)?;
+                }
+            }
+            None => insert_annotation(&tx, &anno)?,
+        }
+
+        let existing_comments: Vec<String> = get_comments_by_annotation(&tx, &anno.id)?
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        for comment in anno.comments.iter().filter(|c| !existing_comments.contains(&c.id)) {
+            let mut comment = comment.clone();
+            comment.annotation_id = anno.id.clone();
+            insert_comment(&tx, &comment)?;
+        }
+        applied += 1;
+    }
+
+    for id in &patch.deleted {
+        tx.execute(
+            "DELETE FROM comments WHERE annotation_id IN (SELECT id FROM annotations WHERE id = ? AND document_id = ?)",
+            params![id, doc.id],
+        ).map_err(|e| e.to_string())?;
+        applied += tx.execute(
+            "DELETE FROM annotations WHERE id = ? AND document_id = ?",
+            params![id, doc.id],
+        ).map_err(|e| e.to_string())?;
+    }
+    write_reading_order(&tx, &doc.id, &doc.content)?;
+
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(applied)
+}
+
+// ============ 文档快照 ============
+
+// 每个文档保留的快照数量上限，超出时删除最旧的
+const SNAPSHOT_LIMIT: i64 = 20;
+
+pub fn create_snapshot(conn: &Connection, path: &str, label: &str) -> Result<SnapshotRecord, String> {
+    let doc = get_document_by_path(conn, path)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = with_comments(conn, get_annotations_by_doc(conn, &doc.id)?)?;
+    let now = Utc::now().timestamp_millis();
+
+    let snapshot = SnapshotRecord {
+        id: Uuid::new_v4().to_string(),
+        document_id: doc.id.clone(),
+        label: label.to_string(),
+        checksum: doc.checksum.clone(),
+        annotation_count: annotations.len(),
+        created_at: now,
+    };
+    let package = BatchPackage {
+        version: "1.0".to_string(),
+        exported_at: now,
+        source_document: Some(SourceDocumentInfo {
+            name: std::path::Path::new(&doc.path)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            checksum: doc.checksum.clone(),
+        }),
+        annotations,
+        viewport: None,
+    };
+    let package_json = serde_json::to_string(&package).map_err(|e| e.to_string())?;
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    tx.execute(
+        "INSERT INTO document_snapshots (id, document_id, label, content, checksum, package, annotation_count, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            snapshot.id, snapshot.document_id, snapshot.label, doc.content, snapshot.checksum,
+            package_json, snapshot.annotation_count as i64, snapshot.created_at
+        ],
+    ).map_err(|e| e.to_string())?;
+    tx.execute(
+        "DELETE FROM document_snapshots WHERE document_id = ? AND id NOT IN
+         (SELECT id FROM document_snapshots WHERE document_id = ? ORDER BY created_at DESC LIMIT ?)",
+        params![doc.id, doc.id, SNAPSHOT_LIMIT],
+    ).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(snapshot)
+}
+
+// 按创建时间倒序列出快照
+pub fn list_snapshots(conn: &Connection, path: &str) -> Result<Vec<SnapshotRecord>, String> {
+    let doc = match get_document_by_path(conn, path)? {
+        Some(doc) => doc,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut stmt = conn.prepare("
+        SELECT id, document_id, label, checksum, annotation_count, created_at
+        FROM document_snapshots WHERE document_id = ? ORDER BY created_at DESC
+    ").map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([&doc.id]).map_err(|e| e.to_string())?;
+
+    let mut results = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        results.push(SnapshotRecord {
+            id: row.get(0).map_err(|e| e.to_string())?,
+            document_id: row.get(1).map_err(|e| e.to_string())?,
+            label: row.get(2).map_err(|e| e.to_string())?,
+            checksum: row.get(3).map_err(|e| e.to_string())?,
+            annotation_count: row.get::<_, i64>(4).map_err(|e| e.to_string())? as usize,
+            created_at: row.get(5).map_err(|e| e.to_string())?,
+        });
+    }
+    Ok(results)
+}
+
+// 恢复快照：改写磁盘文件与文档内容，并以快照中的注解替换当前注解；撤销时换回原内容与被替换的注解
+pub fn restore_snapshot(conn: &Connection, snapshot_id: &str) -> Result<DocumentRecord, String> {
+    let (document_id, content, package_json): (String, String, String) = conn.query_row(
+        "SELECT document_id, content, package FROM document_snapshots WHERE id = ?",
+        [snapshot_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    ).map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => "Snapshot not found".to_string(),
+        e => e.to_string(),
+    })?;
+    let package: BatchPackage = serde_json::from_str(&package_json).map_err(|e| e.to_string())?;
+    let doc = get_document_by_id(conn, &document_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+
+    let checksum = compute_checksum(&content);
+    let now = Utc::now().timestamp_millis();
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    tx.execute(
+        "UPDATE documents SET content = ?, checksum = ?, last_modified = ? WHERE id = ?",
+        params![content, checksum, now, doc.id],
+    ).map_err(|e| e.to_string())?;
+    // 恢复前的文档与被替换的注解写入撤销日志
+    let replaced = with_comments(&tx, get_annotations_by_doc(&tx, &doc.id)?)?;
+    log_deletion(&tx, "restore_snapshot", DeletedRows {
+        documents: vec![doc.clone()],
+        annotations: replaced,
+        ..Default::default()
+    })?;
+    tx.execute(
+        "DELETE FROM comments WHERE annotation_id IN (SELECT id FROM annotations WHERE document_id = ?)",
+        params![doc.id],
+    ).map_err(|e| e.to_string())?;
+    tx.execute("DELETE FROM annotations WHERE document_id = ?", params![doc.id])
+        .map_err(|e| e.to_string())?;
+    for anno in &package.annotations {
+        insert_annotation(&tx, anno)?;
+        for comment in &anno.comments {
+            insert_comment(&tx, comment)?;
+        }
+    }
+    write_reading_order(&tx, &doc.id, &content)?;
+
+    // 数据库修改全部成功后再替换文件（临时文件 + 重命名）；提交失败时写回原文件，保持两者一致
+    let previous = fs::read(&doc.path).ok();
+    let temp = format!("{}.tmp", doc.path);
+    if let Err(e) = fs::write(&temp, &content).and_then(|_| fs::rename(&temp, &doc.path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(e.to_string());
+    }
+    if let Err(e) = tx.commit() {
+        if let Some(previous) = previous {
+            let _ = fs::write(&doc.path, previous);
+        }
+        return Err(e.to_string());
+    }
+
+    Ok(DocumentRecord {
+        content,
+        checksum,
+        last_modified: now,
+        ..doc
+    })
+}
+
+// ============ 评论 ============
+
+pub fn get_comments_by_annotation(conn: &Connection, annotation_id: &str) -> Result<Vec<CommentRecord>, String> {
+    let mut stmt = conn.prepare("
+        SELECT id, annotation_id, user_id, user_name, body, created_at, parent_id
+        FROM comments WHERE annotation_id = ? ORDER BY created_at
+    ").map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([annotation_id]).map_err(|e| e.to_string())?;
+
+    let mut results = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        results.push(CommentRecord {
+            id: row.get(0).map_err(|e| e.to_string())?,
+            annotation_id: row.get(1).map_err(|e| e.to_string())?,
+            user_id: row.get(2).map_err(|e| e.to_string())?,
+            user_name: row.get(3).map_err(|e| e.to_string())?,
+            body: row.get(4).map_err(|e| e.to_string())?,
+            created_at: row.get(5).map_err(|e| e.to_string())?,
+            parent_id: row.get(6).map_err(|e| e.to_string())?,
+        });
+    }
+    Ok(results)
+}
+
+pub fn add_comment(conn: &Connection, annotation_id: &str, body: &str, parent_id: Option<&str>) -> Result<CommentRecord, String> {
+    if get_annotation_by_id(conn, annotation_id)?.is_none() {
+        return Err("Annotation not found".to_string());
+    }
+    // 回复只能指向同一注解下的评论
+    if let Some(parent_id) = parent_id {
+        let siblings = get_comments_by_annotation(conn, annotation_id)?;
+        if !siblings.iter().any(|c| c.id == parent_id) {
+            return Err("Parent comment not found".to_string());
+        }
+    }
+    let user = get_or_create_user(conn, "admin".to_string())?;
+
+    let comment = CommentRecord {
+        id: Uuid::new_v4().to_string(),
+        annotation_id: annotation_id.to_string(),
+        user_id: user.id,
+        user_name: user.name,
+        body: body.to_string(),
+        created_at: Utc::now().timestamp_millis(),
+        parent_id: parent_id.map(|p| p.to_string()),
+    };
+    insert_comment(conn, &comment)?;
+    Ok(comment)
+}
+
+fn insert_comment(conn: &Connection, comment: &CommentRecord) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO comments (id, annotation_id, user_id, user_name, body, created_at, parent_id) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        params![comment.id, comment.annotation_id, comment.user_id, comment.user_name, comment.body, comment.created_at, comment.parent_id],
+    ).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// 为注解附带评论（用于导出包）
+pub fn with_comments(conn: &Connection, annotations: Vec<AnnotationRecord>) -> Result<Vec<AnnotationRecord>, String> {
+    let mut result = Vec::with_capacity(annotations.len());
+    for mut anno in annotations {
+        anno.comments = get_comments_by_annotation(conn, &anno.id)?;
+        result.push(anno);
+    }
+    Ok(result)
+}
+
+// 导入注解后以新 ID 重建其评论，并同步映射回复关系
+fn recreate_comments(conn: &Connection, anno: &AnnotationRecord) -> Result<(), String> {
+    let id_map: std::collections::HashMap<&str, String> = anno.comments
+        .iter()
+        .map(|c| (c.id.as_str(), Uuid::new_v4().to_string()))
+        .collect();
+
+    for comment in &anno.comments {
+        let mut comment = comment.clone();
+        comment.id = id_map[comment.id.as_str()].clone();
+        comment.annotation_id = anno.id.clone();
+        comment.parent_id = comment.parent_id
+            .as_deref()
+            .and_then(|p| id_map.get(p).cloned());
+        insert_comment(conn, &comment)?;
+    }
+    Ok(())
+}
+
+// 注解及其评论树，同层回复按时间排序；父评论缺失的回复视为顶层评论
+pub fn get_annotation_thread(conn: &Connection, annotation_id: &str) -> Result<AnnotationThread, String> {
+    let annotation = get_annotation_by_id(conn, annotation_id)?
+        .ok_or_else(|| "Annotation not found".to_string())?;
+    let comments = get_comments_by_annotation(conn, annotation_id)?;
+
+    let ids: std::collections::HashSet<&str> = comments.iter().map(|c| c.id.as_str()).collect();
+    let mut children: std::collections::HashMap<Option<&str>, Vec<&CommentRecord>> = std::collections::HashMap::new();
+    for comment in &comments {
+        let parent = comment.parent_id.as_deref().filter(|p| ids.contains(p));
+        children.entry(parent).or_default().push(comment);
+    }
+
+    Ok(AnnotationThread {
+        comments: build_comment_nodes(&children, None),
+        annotation,
+    })
+}
+
+// comments 已按 created_at 排序，子列表保持该顺序
+fn build_comment_nodes(
+    children: &std::collections::HashMap<Option<&str>, Vec<&CommentRecord>>,
+    parent: Option<&str>,
+) -> Vec<CommentNode> {
+    children.get(&parent)
+        .map(|list| list.iter().map(|c| CommentNode {
+            comment: (*c).clone(),
+            replies: build_comment_nodes(children, Some(c.id.as_str())),
+        }).collect())
+        .unwrap_or_default()
+}
+
+// ============ 标签 ============
+
+// tags 列保存 JSON 字符串数组，无法解析时视为无标签
+pub fn parse_tags(tags_json: Option<&str>) -> Vec<String> {
+    tags_json
+        .and_then(|json| serde_json::from_str::<Vec<String>>(json).ok())
+        .unwrap_or_default()
+}
+
+// 设置注解标签：去除首尾空白、空标签与重复项
+pub fn set_annotation_tags(conn: &Connection, anno_id: &str, tags: &[String]) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if !tag.is_empty() && !normalized.iter().any(|t| t == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+
+    let tags_json = serde_json::to_string(&normalized).map_err(|e| e.to_string())?;
+    let changed = conn.execute(
+        "UPDATE annotations SET tags = ?, updated_at = ? WHERE id = ?",
+        params![tags_json, Utc::now().timestamp_millis(), anno_id],
+    ).map_err(|e| e.to_string())?;
+    if changed == 0 {
+        return Err("Annotation not found".to_string());
+    }
+
+    Ok(normalized)
+}
+
+// 文档内各注解的标签，键为注解 ID
+pub fn get_annotation_tags(conn: &Connection, doc_id: &str) -> Result<std::collections::HashMap<String, Vec<String>>, String> {
+    let mut stmt = conn.prepare("SELECT id, tags FROM annotations WHERE document_id = ?")
+        .map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([doc_id]).map_err(|e| e.to_string())?;
+
+    let mut tags = std::collections::HashMap::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        let id: String = row.get(0).map_err(|e| e.to_string())?;
+        let tags_json: Option<String> = row.get(1).map_err(|e| e.to_string())?;
+        tags.insert(id, parse_tags(tags_json.as_deref()));
+    }
+    Ok(tags)
+}
+
+// 所有注解中出现过的标签及使用次数，按次数降序、同次数按名称排序
+pub fn list_all_tags(conn: &Connection) -> Result<Vec<(String, usize)>, String> {
+    let mut stmt = conn.prepare("SELECT tags FROM annotations")
+        .map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+
+    let mut counts: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        let tags_json: Option<String> = row.get(0).map_err(|e| e.to_string())?;
+        for tag in parse_tags(tags_json.as_deref()) {
+            *counts.entry(tag).or_insert(0) += 1;
+        }
+    }
+
+    let mut tags: Vec<(String, usize)> = counts.into_iter().collect();
+    tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(tags)
+}
+
+// ============ 锚点解析 ============
+
+pub fn parse_anchors(anchor_data: &str) -> Vec<AnchorRecord> {
+    serde_json::from_str(anchor_data).unwrap_or_default()
+}
+
+// 原文锚点（containerPath 为空）覆盖的范围
+pub fn anchor_text_range(anchors: &[AnchorRecord]) -> Option<(usize, usize)> {
+    let text_anchors = anchors.iter().filter(|a| a.container_path.is_empty());
+    let start = text_anchors.clone().map(|a| a.start_offset).min()?;
+    let end = text_anchors.map(|a| a.end_offset).max()?;
+    if start > end {
+        return None;
+    }
+    Some((start, end))
+}
+
+// 定位注解在原文中的字节范围：优先使用原文锚点，否则按高亮文本查找
+pub fn locate_annotation(anno: &AnnotationRecord, content: &str) -> Option<(usize, usize)> {
+    let anchors = parse_anchors(&anno.anchor_data);
+    if let Some((start, end)) = anchor_text_range(&anchors) {
+        if content.get(start..end) == Some(anno.text.as_str()) {
+            return Some((start, end));
+        }
+    }
+
+    if anno.text.is_empty() {
+        return None;
+    }
+    content.find(&anno.text).map(|start| (start, start + anno.text.len()))
+}
+
+// 按原文位置排序；无法定位的注解排在最后并保持创建顺序
+pub fn sort_by_position(annotations: Vec<AnnotationRecord>, content: &str) -> Vec<(AnnotationRecord, Option<(usize, usize)>)> {
+    let mut located: Vec<(AnnotationRecord, Option<(usize, usize)>)> = annotations
+        .into_iter()
+        .map(|anno| {
+            let range = locate_annotation(&anno, content);
+            (anno, range)
+        })
+        .collect();
+    located.sort_by_key(|(anno, range)| (range.is_none(), range.map(|(start, _)| start), anno.created_at));
+    located
+}
+
+// 在新内容中重新定位注解：原文锚点仍匹配时不变，否则取上下文最吻合、离原位置最近的同文本出现处
+pub enum ReanchorOutcome {
+    Unchanged,
+    Relocated(String), // 新的 anchor_data
+    Unresolved,
+}
+
+pub fn reanchor_annotation(anno: &AnnotationRecord, content: &str) -> ReanchorOutcome {
+    let anchors = parse_anchors(&anno.anchor_data);
+    let text_range = anchor_text_range(&anchors);
+
+    if let Some((start, end)) = text_range {
+        if content.get(start..end) == Some(anno.text.as_str()) {
+            return ReanchorOutcome::Unchanged;
+        }
+    }
+    if anno.text.is_empty() {
+        return ReanchorOutcome::Unresolved;
+    }
+
+    match text_range {
+        // 原文锚点：优先选择上下文最吻合的出现处，其次就近
+        Some((old_start, _)) => content
+            .match_indices(anno.text.as_str())
+            .map(|(i, _)| i)
+            .min_by_key(|i| (std::cmp::Reverse(context_score(anno, content, *i)), i.abs_diff(old_start)))
+            .map(|start| ReanchorOutcome::Relocated(build_text_anchor(start, start + anno.text.len())))
+            .unwrap_or(ReanchorOutcome::Unresolved),
+        // DOM 锚点由前端恢复，这里只确认文本仍然存在
+        None if content.contains(anno.text.as_str()) => ReanchorOutcome::Unchanged,
+        None => ReanchorOutcome::Unresolved,
+    }
+}
+
+// 导出时记录的上下文字符数
+const ANCHOR_CONTEXT_CHARS: usize = 32;
+
+// 为缺少上下文的注解补上高亮两侧的原文片段，无法定位的保持不变
+pub fn with_anchor_context(annotations: Vec<AnnotationRecord>, content: &str) -> Vec<AnnotationRecord> {
+    annotations
+        .into_iter()
+        .map(|mut anno| {
+            if anno.context.is_none() {
+                if let Some((start, end)) = locate_annotation(&anno, content) {
+                    let before = &content[..start];
+                    let prefix_start = before.char_indices().rev().nth(ANCHOR_CONTEXT_CHARS - 1).map(|(i, _)| i).unwrap_or(0);
+                    let suffix: String = content[end..].chars().take(ANCHOR_CONTEXT_CHARS).collect();
+                    anno.context = Some(AnchorContext {
+                        prefix: before[prefix_start..].to_string(),
+                        suffix,
+                    });
+                }
+            }
+            anno
+        })
+        .collect()
+}
+
+// 候选位置两侧与记录的上下文连续吻合的字符数
+fn context_score(anno: &AnnotationRecord, content: &str, start: usize) -> usize {
+    let context = match &anno.context {
+        Some(context) => context,
+        None => return 0,
+    };
+    let end = start + anno.text.len();
+    let prefix_match = content[..start].chars().rev()
+        .zip(context.prefix.chars().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix_match = content[end..].chars()
+        .zip(context.suffix.chars())
+        .take_while(|(a, b)| a == b)
+        .count();
+    prefix_match + suffix_match
+}
+
+// 注解上下文片段：高亮部分用 [[ ]] 标记，两侧各保留 radius 个字符
+pub fn get_annotation_context(conn: &Connection, anno_id: &str, content: &str, radius: usize) -> Result<String, String> {
+    let anno = get_annotation_by_id(conn, anno_id)?
+        .ok_or_else(|| "Annotation not found".to_string())?;
+    let (start, end) = locate_annotation(&anno, content)
+        .ok_or_else(|| "Annotation anchor could not be resolved".to_string())?;
+
+    let before = &content[..start];
+    let after = &content[end..];
+    // 按字符而非字节计算半径，保证切片落在 UTF-8 边界上
+    let context_start = if radius == 0 {
+        start
+    } else {
+        before.char_indices().rev().nth(radius - 1).map(|(i, _)| i).unwrap_or(0)
+    };
+    let context_end = after.char_indices()
+        .nth(radius)
+        .map(|(i, _)| end + i)
+        .unwrap_or(content.len());
+
+    Ok(format!(
+        "{}{}[[{}]]{}{}",
+        if context_start > 0 { "…" } else { "" },
+        &content[context_start..start],
+        &content[start..end],
+        &content[end..context_end],
+        if context_end < content.len() { "…" } else { "" }
+    ))
+}
+
+// 字节偏移所在的行号（从 1 开始）
+pub fn line_of_offset(content: &str, offset: usize) -> usize {
+    let offset = offset.min(content.len());
+    content.as_bytes()[..offset].iter().filter(|&&b| b == b'\n').count() + 1
+}
+
+// ============ 批量重新锚定 ============
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ReanchorReport {
+    pub total: usize,
+    pub unchanged: usize,
+    pub relocated: usize,
+    pub unresolved: Vec<String>, // 无法定位的注解 ID
+    pub error: Option<String>,   // 文件读取失败或处理线程 panic 时的错误，此时文档未被修改
+}
+
+// (文档 ID, 路径, 新内容, 锚点更新, 报告)
+type ReanchorPlan = (String, String, Option<String>, Vec<(String, String)>, ReanchorReport);
+
+// 在工作线程中读取文件并计算锚点更新，不访问数据库
+fn plan_reanchor(id: &str, path: &str, annotations: &[AnnotationRecord]) -> ReanchorPlan {
+    let mut report = ReanchorReport { total: annotations.len(), ..Default::default() };
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            report.error = Some(e.to_string());
+            return (id.to_string(), path.to_string(), None, Vec::new(), report);
+        }
+    };
+
+    let mut updates = Vec::new();
+    for anno in annotations {
+        match reanchor_annotation(anno, &content) {
+            ReanchorOutcome::Unchanged => report.unchanged += 1,
+            ReanchorOutcome::Relocated(anchor_data) => {
+                report.relocated += 1;
+                updates.push((anno.id.clone(), anchor_data));
+            }
+            ReanchorOutcome::Unresolved => report.unresolved.push(anno.id.clone()),
+        }
+    }
+    (id.to_string(), path.to_string(), Some(content), updates, report)
+}
+
+// 重新读取 dir 下所有已入库文档并重新定位注解；文件读取与定位并行，数据库写入在单个事务中完成
+pub fn reanchor_project(conn: &Connection, dir: &str) -> Result<std::collections::HashMap<String, ReanchorReport>, String> {
+    let root = std::path::Path::new(dir);
+    let mut documents = Vec::new();
+    {
+        let mut stmt = conn.prepare("SELECT id, path FROM documents").map_err(|e| e.to_string())?;
+        let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+        while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            let id: String = row.get(0).map_err(|e| e.to_string())?;
+            let path: String = row.get(1).map_err(|e| e.to_string())?;
+            if std::path::Path::new(&path).starts_with(root) {
+                documents.push((id, path));
+            }
+        }
+    }
+
+    let mut jobs = Vec::with_capacity(documents.len());
+    for (id, path) in documents {
+        let annotations = get_annotations_by_doc(conn, &id)?;
+        jobs.push((id, path, annotations));
+    }
+
+    // 每批最多与 CPU 核数相同的线程，避免目录很大时一次创建过多线程；
+    // 线程 panic 时该文档记为失败，不写入任何修改
+    let workers = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut plans: Vec<ReanchorPlan> = Vec::with_capacity(jobs.len());
+    for chunk in jobs.chunks(workers) {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = chunk
+                .iter()
+                .map(|(id, path, annotations)| (id, path, annotations, scope.spawn(move || plan_reanchor(id, path, annotations))))
+                .collect();
+            for (id, path, annotations, handle) in handles {
+                plans.push(handle.join().unwrap_or_else(|_| {
+                    let report = ReanchorReport {
+                        total: annotations.len(),
+                        error: Some("Re-anchoring thread panicked".to_string()),
+                        ..Default::default()
+                    };
+                    (id.clone(), path.clone(), None, Vec::new(), report)
+                }));
+            }
+        });
+    }
+
+    let now = Utc::now().timestamp_millis();
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut reports = std::collections::HashMap::new();
+    for (id, path, content, updates, report) in plans {
+        if let Some(content) = content {
+            tx.execute(
+                "UPDATE documents SET content = ?, checksum = ?, last_modified = ? WHERE id = ?",
+                params![content, compute_checksum(&content), now, id],
+            ).map_err(|e| e.to_string())?;
+            for (anno_id, anchor_data) in updates {
+                tx.execute(
+                    "UPDATE annotations SET anchor_data = ?, updated_at = ? WHERE id = ?",
+                    params![anchor_data, now, anno_id],
+                ).map_err(|e| e.to_string())?;
+            }
+            write_reading_order(&tx, &id, &content)?;
+        }
+        reports.insert(path, report);
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(reports)
+}
+
+// ============ 版本对比 ============
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DriftedAnnotation {
+    pub id: String,
+    pub old_offset: Option<usize>, // 原锚点起点，DOM 锚点时为空
+    pub new_offset: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LineDiffSummary {
+    pub unchanged: usize,
+    pub added: usize,
+    pub removed: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct VersionComparison {
+    pub intact: Vec<String>,             // 锚点仍然匹配的注解 ID
+    pub drifted: Vec<DriftedAnnotation>, // 文本在新内容中的其他位置找到
+    pub lost: Vec<String>,               // 新内容中找不到原文本
+    pub diff: LineDiffSummary,
+}
+
+// 对比已保存内容与新内容：按重新锚定规则给注解分类，并统计逐行差异；不修改数据库
+pub fn compare_versions(conn: &Connection, doc_id: &str, new_content: &str) -> Result<VersionComparison, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = get_annotations_by_doc(conn, doc_id)?;
+
+    let mut comparison = VersionComparison {
+        diff: line_diff_summary(&doc.content, new_content),
+        ..Default::default()
+    };
+    // 旧位置取自 locate_annotation 对已存内容的定位结果，界面锚点的 containerPath 非空时同样适用
+    for (anno, old_range) in sort_by_position(annotations, &doc.content) {
+        match reanchor_annotation(&anno, new_content) {
+            ReanchorOutcome::Unchanged => comparison.intact.push(anno.id),
+            ReanchorOutcome::Relocated(anchor_data) => {
+                let old_offset = old_range.map(|(start, _)| start);
+                match anchor_text_range(&parse_anchors(&anchor_data)) {
+                    Some((new_offset, _)) => comparison.drifted.push(DriftedAnnotation { id: anno.id, old_offset, new_offset }),
+                    None => comparison.lost.push(anno.id),
+                }
+            }
+            ReanchorOutcome::Unresolved => comparison.lost.push(anno.id),
+        }
+    }
+
+    Ok(comparison)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AnchorDrift {
+    pub id: String,
+    pub text: String,
+    pub old_offset: Option<usize>,
+    pub new_offset: Option<usize>, // 为空表示在新内容中找不到
+    pub delta: Option<i64>,        // new_offset - old_offset
+    pub lost: bool,
+}
+
+// 每条注解在新内容中的位移，丢失的排在最前，其余按位移绝对值降序
+pub fn measure_anchor_drift(conn: &Connection, doc_id: &str, new_content: &str) -> Result<Vec<AnchorDrift>, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = get_annotations_by_doc(conn, doc_id)?;
+
+    let mut drifts: Vec<AnchorDrift> = annotations
+        .iter()
+        .map(|anno| {
+            let old_offset = locate_annotation(anno, &doc.content).map(|(start, _)| start);
+            let new_offset = relocated_offset(anno, new_content);
+            let delta = match (old_offset, new_offset) {
+                (Some(old), Some(new)) => Some(new as i64 - old as i64),
+                _ => None,
+            };
+            AnchorDrift {
+                id: anno.id.clone(),
+                text: anno.text.clone(),
+                old_offset,
+                new_offset,
+                delta,
+                lost: new_offset.is_none(),
+            }
+        })
+        .collect();
+
+    drifts.sort_by(|a, b| {
+        b.lost.cmp(&a.lost)
+            .then_with(|| b.delta.map(i64::unsigned_abs).cmp(&a.delta.map(i64::unsigned_abs)))
+    });
+    Ok(drifts)
+}
+
+// 注解在新内容中的起始字节位置，找不到时为 None
+fn relocated_offset(anno: &AnnotationRecord, new_content: &str) -> Option<usize> {
+    match reanchor_annotation(anno, new_content) {
+        ReanchorOutcome::Unchanged => locate_annotation(anno, new_content).map(|(start, _)| start),
+        ReanchorOutcome::Relocated(anchor_data) => anchor_text_range(&parse_anchors(&anchor_data)).map(|(start, _)| start),
+        ReanchorOutcome::Unresolved => None,
+    }
+}
+
+// 两个版本的逐行差异（新增 + / 删除 -），每条注解放在它在新版本中所在行的下方，找不到的列在末尾
+pub fn export_version_diff_html(conn: &Connection, doc_id: &str, old_content: &str, new_content: &str) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = get_annotations_by_doc(conn, doc_id)?;
+
+    let title = std::path::Path::new(&doc.path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    // 新版本行号 -> 该行上的注解
+    let mut by_line: std::collections::BTreeMap<usize, Vec<&AnnotationRecord>> = std::collections::BTreeMap::new();
+    let mut lost = Vec::new();
+    for anno in &annotations {
+        match relocated_offset(anno, new_content) {
+            Some(offset) => by_line.entry(new_content[..offset].matches('\n').count()).or_default().push(anno),
+            None => lost.push(anno),
+        }
+    }
+
+    let render_note = |anno: &AnnotationRecord| -> String {
+        let note = anno.note.as_deref().map(str::trim).filter(|n| !n.is_empty()).unwrap_or("");
+        format!(
+            r#"<div class="anno" data-anno-id="{}" style="border-left-color: {};"><blockquote>{}</blockquote><p>{}</p><span class="anno-author">{}</span></div>"#,
+            escape_html(&anno.id),
+            escape_html(&anno.highlight_color),
+            escape_html(&anno.text),
+            escape_html(note),
+            escape_html(&anno.user_name)
+        )
+    };
+
+    let diff = similar::TextDiff::from_lines(old_content, new_content);
+    let (mut added, mut removed) = (0, 0);
+    let mut rows_html = String::new();
+    for change in diff.iter_all_changes() {
+        let (class, marker) = match change.tag() {
+            similar::ChangeTag::Equal => ("equal", " "),
+            similar::ChangeTag::Insert => {
+                added += 1;
+                ("insert", "+")
+            }
+            similar::ChangeTag::Delete => {
+                removed += 1;
+                ("delete", "-")
+            }
+        };
+        let line_number = |index: Option<usize>| index.map(|i| (i + 1).to_string()).unwrap_or_default();
+        rows_html.push_str(&format!(
+            r#"
+            <tr class="line {}"><td class="ln">{}</td><td class="ln">{}</td><td class="marker">{}</td><td class="code">{}</td></tr>"#,
+            class,
+            line_number(change.old_index()),
+            line_number(change.new_index()),
+            marker,
+            escape_html(change.value().trim_end_matches(['\r', '\n']))
+        ));
+
+        if let Some(line_annotations) = change.new_index().and_then(|i| by_line.get(&i)) {
+            let notes: String = line_annotations.iter().map(|anno| render_note(anno)).collect();
+            rows_html.push_str(&format!(
+                r#"
+            <tr class="anno-row"><td colspan="3"></td><td>{}</td></tr>"#,
+                notes
+            ));
+        }
+    }
+
+    let lost_html = if lost.is_empty() {
+        String::new()
+    } else {
+        let notes: String = lost.iter().map(|anno| render_note(anno)).collect();
+        format!(r#"<section class="lost"><h2>未能定位的注解 ({})</h2>{}</section>"#, lost.len(), notes)
+    };
+
+    let html = format!(r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>{} - Version Diff</title>
+    <style>
+        body {{ font-family: system-ui, -apple-system, sans-serif; color: #222; margin: 24px; font-size: 14px; }}
+        .summary {{ color: #666; margin-bottom: 12px; }}
+        table.diff {{ border-collapse: collapse; width: 100%; font-family: "JetBrains Mono", Consolas, monospace; }}
+        .ln {{ width: 3em; color: #999; text-align: right; padding: 0 8px; user-select: none; }}
+        .marker {{ width: 1.5em; text-align: center; user-select: none; }}
+        .code {{ white-space: pre-wrap; word-break: break-word; padding: 0 8px; }}
+        .insert {{ background: #e6ffed; }}
+        .delete {{ background: #ffeef0; }}
+        .anno-row td {{ padding: 4px 8px 8px; }}
+        .anno {{ font-family: system-ui, -apple-system, sans-serif; border-left: 4px solid #ffd700; background: #fffbe6; padding: 6px 10px; margin: 4px 0; }}
+        .anno blockquote {{ margin: 0; font-style: italic; color: #555; }}
+        .anno p {{ margin: 4px 0 0; white-space: pre-wrap; }}
+        .anno-author {{ font-size: 0.85em; color: #777; }}
+        .lost {{ margin-top: 24px; }}
+    </style>
+</head>
+<body>
+    <h1>{}</h1>
+    <p class="summary">+{} / -{}</p>
+    <table class="diff">{}
+    </table>
+    {}
+</body>
+</html>"#,
+        escape_html(&title),
+        escape_html(&title),
+        added,
+        removed,
+        rows_html,
+        lost_html
+    );
+
+    Ok(html)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ConsistencyIssue {
+    pub id: String,
+    pub start: Option<usize>,   // 原文锚点范围，没有原文锚点时为空
+    pub end: Option<usize>,
+    pub expected: String,       // 注解中保存的高亮文本
+    pub actual: Option<String>, // 锚点在原文中切出的内容，越界、不在字符边界上或没有原文锚点时为空
+    pub located: bool,          // 是否仍能通过 locate_annotation 在原文中找到
+}
+
+// 检查注解能否在原文中定位，以及原文锚点 [start,end) 切出的内容是否与保存的文本一致
+// 界面锚点（containerPath 非空）没有原文范围，只要能定位即视为一致；无法定位的注解一律报告
+pub fn audit_annotation_consistency(conn: &Connection, doc_id: &str, content: &str) -> Result<Vec<ConsistencyIssue>, String> {
+    let annotations = get_annotations_by_doc(conn, doc_id)?;
+
+    let mut issues = Vec::new();
+    for anno in annotations {
+        let located = locate_annotation(&anno, content).is_some();
+        let range = anchor_text_range(&parse_anchors(&anno.anchor_data));
+        let actual = range.and_then(|(start, end)| content.get(start..end));
+        if located && (range.is_none() || actual == Some(anno.text.as_str())) {
+            continue;
+        }
+        issues.push(ConsistencyIssue {
+            id: anno.id,
+            start: range.map(|(start, _)| start),
+            end: range.map(|(_, end)| end),
+            expected: anno.text,
+            actual: actual.map(|s| s.to_string()),
+            located,
+        });
+    }
+    Ok(issues)
+}
+
+// 逐行差异统计，与 export_version_diff_html 使用同一种行差异算法
+fn line_diff_summary(old: &str, new: &str) -> LineDiffSummary {
+    let mut summary = LineDiffSummary::default();
+    for change in similar::TextDiff::from_lines(old, new).iter_all_changes() {
+        match change.tag() {
+            similar::ChangeTag::Equal => summary.unchanged += 1,
+            similar::ChangeTag::Insert => summary.added += 1,
+            similar::ChangeTag::Delete => summary.removed += 1,
+        }
+    }
+    summary
+}
+
+// ============ 单注解导出/导入 ============
+
+// viewport 存在时便签坐标按该视口转换为比例，便于在不同尺寸的屏幕上导入
+pub fn export_annotation(conn: &Connection, anno_id: &str, doc_path: &str, viewport: Option<Viewport>) -> Result<String, String> {
+    let mut annotation = get_annotation_by_id(conn, anno_id)?
+        .ok_or_else(|| "Annotation not found".to_string())?;
+    if let Some(viewport) = viewport {
+        viewport.validate()?;
+        annotation.note_position_x /= viewport.width;
+        annotation.note_position_y /= viewport.height;
+    }
+
+    let doc = get_document_by_path(conn, doc_path)?
+        .ok_or_else(|| "Document not found".to_string())?;
+
+    let package = BatchPackage {
+        version: "1.0".to_string(),
+        exported_at: Utc::now().timestamp_millis(),
+        source_document: Some(SourceDocumentInfo {
+            name: std::path::Path::new(&doc.path)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            checksum: doc.checksum.clone(),
+        }),
+        annotations: with_anchor_context(with_comments(conn, vec![annotation])?, &doc.content),
+        viewport,
+    };
+
+    serde_json::to_string_pretty(&package).map_err(|e| e.to_string())
+}
+
+// 按阅读顺序把文档注解拆分为每个最多 per_file 条的包，写入 <文档名>-<序号>.annpkg，返回各文件路径
+pub fn export_annotations_chunked(conn: &Connection, doc_id: &str, per_file: usize, dest_dir: &str, filter: &ExportFilter) -> Result<Vec<String>, String> {
+    if per_file == 0 {
+        return Err("per_file must be greater than 0".to_string());
+    }
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations: Vec<AnnotationRecord> = sort_by_position(filter.apply(get_annotations_by_doc(conn, doc_id)?)?, &doc.content)
+        .into_iter()
+        .map(|(anno, _)| anno)
+        .collect();
+    let annotations = with_anchor_context(with_comments(conn, annotations)?, &doc.content);
+
+    let path = std::path::Path::new(&doc.path);
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let stem = sanitize_file_component(&path.file_stem().unwrap_or_default().to_string_lossy());
+
+    let chunks: Vec<&[AnnotationRecord]> = annotations.chunks(per_file).collect();
+    let width = chunks.len().to_string().len();
+    let dir = std::path::Path::new(dest_dir);
+    let exported_at = Utc::now().timestamp_millis();
+
+    let mut paths = Vec::new();
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        let package = BatchPackage {
+            version: "1.0".to_string(),
+            exported_at,
+            source_document: Some(SourceDocumentInfo {
+                name: name.clone(),
+                checksum: doc.checksum.clone(),
+            }),
+            annotations: chunk.to_vec(),
+            viewport: None,
+        };
+        let json = serde_json::to_string_pretty(&package).map_err(|e| e.to_string())?;
+        let file_name = format!("{}-{:0width$}.annpkg", stem, index + 1, width = width);
+        let written = write_export_file(&dir.join(file_name).to_string_lossy(), json.as_bytes())?;
+        paths.push(written);
+    }
+    Ok(paths)
+}
+
+// 包内为相对坐标时按 viewport 还原为像素，未传入 viewport 时使用导出时的参考视口
+pub fn import_annotation(json: &str, viewport: Option<Viewport>) -> Result<Vec<AnnotationRecord>, String> {
+    let package: AnnotationPackage = serde_json::from_str(json)
+        .map_err(|e| e.to_string())?;
+
+    let annotations = match package {
+        AnnotationPackage::Batch(mut batch) => {
+            if batch.version != "1.0" {
+                return Err("Unsupported version".to_string());
+            }
+            if let Some(reference) = batch.viewport {
+                let target = viewport.unwrap_or(reference);
+                target.validate()?;
+                for anno in &mut batch.annotations {
+                    anno.note_position_x *= target.width;
+                    anno.note_position_y *= target.height;
+                }
+            }
+            batch.annotations
+        }
+        AnnotationPackage::Single(single) => {
+            if single.version != "1.0" {
+                return Err("Unsupported version".to_string());
+            }
+            vec![single.annotation]
+        }
+    };
+
+    // 生成新 ID，避免冲突
+    let mut result = Vec::new();
+    for mut anno in annotations {
+        anno.id = Uuid::new_v4().to_string();
+        result.push(anno);
+    }
+
+    Ok(result)
+}
+
+// 导入确认前的预览：解析包并返回前 limit 条注解（已重新生成 ID），不写入数据库
+pub fn preview_import(package_json: &str, limit: usize) -> Result<Vec<AnnotationRecord>, String> {
+    let mut annotations = import_annotation(package_json, None)?;
+    annotations.truncate(limit);
+    Ok(annotations)
+}
+
+pub fn merge_imported_annotation(conn: &Connection, annotation: &AnnotationRecord, doc_id: &str) -> Result<(), String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let mut annotation = annotation.clone();
+    annotation.document_id = doc_id.to_string();
+    annotation.created_at = Utc::now().timestamp_millis();
+    if let ReanchorOutcome::Relocated(anchor_data) = reanchor_annotation(&annotation, &doc.content) {
+        annotation.anchor_data = anchor_data;
+    }
+
+    add_annotation(conn, &annotation)?;
+    recreate_comments(conn, &annotation)
+}
+
+// 为导入包推荐本地目标文档：校验和一致得 1.0，否则按文件名相似度打分
+pub fn suggest_import_target(package_json: &str, candidate_dir: &str) -> Result<Vec<(String, f64)>, String> {
+    let package: AnnotationPackage = serde_json::from_str(package_json)
+        .map_err(|e| e.to_string())?;
+    let source = match package {
+        AnnotationPackage::Batch(batch) => batch.source_document,
+        AnnotationPackage::Single(single) => single.source_document,
+    }.ok_or_else(|| "Package has no source document".to_string())?;
+
+    let entries = fs::read_dir(candidate_dir).map_err(|e| e.to_string())?;
+    let mut candidates = Vec::new();
+
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let checksum_matches = fs::read_to_string(&path)
+            .map(|content| compute_checksum(&content) == source.checksum)
+            .unwrap_or(false);
+
+        // 名称相似度上限低于 1.0，保证校验和匹配始终排在最前
+        let score = if checksum_matches {
+            1.0
+        } else {
+            name_similarity(&file_name, &source.name) * 0.99
+        };
+
+        if score > 0.0 {
+            candidates.push((path.to_string_lossy().to_string(), score));
+        }
+    }
+
+    candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    Ok(candidates)
+}
+
+// 未能导入的条目：position 为 CSV 行号或高亮序号（从 1 开始）
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SkippedImport {
+    pub position: usize,
+    pub text: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub skipped: Vec<SkippedImport>,
+}
+
+impl ImportReport {
+    fn skip(&mut self, position: usize, text: &str, reason: &str) {
+        self.skipped.push(SkippedImport {
+            position,
+            text: text.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+}
+
+// 从 CSV 导入注解（列：text, note, highlight_color, highlight_type），按文本在原文中定位
+pub fn import_from_csv(conn: &Connection, csv: &str, doc_path: &str) -> Result<ImportReport, String> {
+    let doc = get_document_by_path(conn, doc_path)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let user = get_or_create_user(conn, "admin".to_string())?;
+    let editor = load_settings()?.editor;
+
+    let mut rows = parse_csv(csv);
+    // 可选表头
+    let has_header = rows.first()
+        .and_then(|r| r.first())
+        .map(|c| c.trim().eq_ignore_ascii_case("text"))
+        .unwrap_or(false);
+    if has_header {
+        rows.remove(0);
+    }
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut report = ImportReport::default();
+    let mut next_occurrence: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+
+    for (index, row) in rows.iter().enumerate() {
+        // 报告中的行号对应原文件（含表头）
+        let line = index + 1 + usize::from(has_header);
+        let cell = |i: usize| row.get(i).map(|c| c.trim()).filter(|c| !c.is_empty());
+        let text = match cell(0) {
+            Some(t) => t,
+            None => {
+                report.skip(line, "", "empty text");
+                continue;
+            }
+        };
+
+        let color = match resolve_highlight_color(cell(2).unwrap_or(&editor.default_highlight_color)) {
+            Ok(color) => color,
+            Err(_) => {
+                report.skip(line, text, &format!("invalid highlight color: {}", cell(2).unwrap_or_default()));
+                continue;
+            }
+        };
+        let highlight_type = match HighlightType::parse(cell(3).unwrap_or(&editor.default_highlight_type)) {
+            Ok(highlight_type) => highlight_type,
+            Err(_) => {
+                report.skip(line, text, &format!("invalid highlight type: {}", cell(3).unwrap_or_default()));
+                continue;
+            }
+        };
+
+        // 同一文本出现在多行时依次对应原文中的下一处出现位置
+        let from = next_occurrence.get(text).copied().unwrap_or(0);
+        let start = match doc.content[from..].find(text) {
+            Some(i) => from + i,
+            None if from > 0 => {
+                report.skip(line, text, "no further occurrence in document");
+                continue;
+            }
+            None => {
+                report.skip(line, text, "text not found in document");
+                continue;
+            }
+        };
+        next_occurrence.insert(text, start + text.len());
+
+        let anno = build_annotation(
+            &doc.id,
+            &user,
+            text,
+            cell(1).map(|n| n.to_string()),
+            &color,
+            highlight_type.as_str(),
+            (start, start + text.len()),
+        );
+        insert_annotation(&tx, &anno)?;
+        report.imported += 1;
+    }
+    write_reading_order(&tx, &doc.id, &doc.content)?;
+
+    tx.commit().map_err(|e| e.to_string())?;
+
+    if !report.skipped.is_empty() {
+        log_warn(&format!("CSV import: {} rows skipped", report.skipped.len()));
+    }
+    Ok(report)
+}
+
+// 从手写 markdown 导入：==...== 为高亮文本，紧随其后的 [^label] 脚注内容作为笔记
+pub fn import_from_markdown_highlights(conn: &Connection, md: &str, doc_path: &str) -> Result<ImportReport, String> {
+    let doc = get_document_by_path(conn, doc_path)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let user = get_or_create_user(conn, "admin".to_string())?;
+    let editor = load_settings()?.editor;
+    let footnotes = parse_footnotes(md);
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut report = ImportReport::default();
+    let mut search_from = 0;
+
+    for (index, (text, label)) in extract_markdown_highlights(md).into_iter().enumerate() {
+        // 按出现顺序向后查找，找不到时再从头查找
+        let start = doc.content[search_from..].find(&text)
+            .map(|i| i + search_from)
+            .or_else(|| doc.content.find(&text));
+        let start = match start {
+            Some(start) => start,
+            None => {
+                report.skip(index + 1, &text, "text not found in document");
+                continue;
+            }
+        };
+        search_from = start + text.len();
+
+        let note = label.and_then(|l| footnotes.get(&l).cloned());
+        let anno = build_annotation(
+            &doc.id,
+            &user,
+            &text,
+            note,
+            &editor.default_highlight_color,
+            &editor.default_highlight_type,
+            (start, start + text.len()),
+        );
+        insert_annotation(&tx, &anno)?;
+        report.imported += 1;
+    }
+    write_reading_order(&tx, &doc.id, &doc.content)?;
+
+    tx.commit().map_err(|e| e.to_string())?;
+
+    if !report.skipped.is_empty() {
+        log_warn(&format!("Markdown import: {} highlights skipped", report.skipped.len()));
+    }
+    Ok(report)
+}
+
+// 提取单行内的 ==高亮==，返回 (文本, 紧随其后的脚注标签)
+fn extract_markdown_highlights(md: &str) -> Vec<(String, Option<String>)> {
+    let mut highlights = Vec::new();
+    // 脚注定义行不参与高亮提取
+    for line in md.lines().filter(|l| !l.starts_with("[^")) {
+        let mut rest = line;
+        while let Some(open) = rest.find("==") {
+            let after_open = &rest[open + 2..];
+            let close = match after_open.find("==") {
+                Some(close) => close,
+                None => break,
+            };
+            let text = after_open[..close].trim();
+            rest = &after_open[close + 2..];
+
+            let label = rest.strip_prefix("[^")
+                .and_then(|r| r.find(']').map(|end| r[..end].to_string()));
+            if let Some(label) = &label {
+                rest = &rest[label.len() + 3..];
+            }
+            if !text.is_empty() {
+                highlights.push((text.to_string(), label));
+            }
+        }
+    }
+    highlights
+}
+
+// 解析 [^label]: 内容，缩进的后续行视为同一脚注
+fn parse_footnotes(md: &str) -> std::collections::HashMap<String, String> {
+    let mut footnotes = std::collections::HashMap::new();
+    let mut current: Option<(String, String)> = None;
+
+    for line in md.lines() {
+        let definition = line.strip_prefix("[^")
+            .and_then(|r| r.split_once("]:"))
+            .map(|(label, body)| (label.to_string(), body.trim().to_string()));
+
+        if let Some(definition) = definition {
+            if let Some((label, body)) = current.replace(definition) {
+                footnotes.insert(label, body);
+            }
+        } else if let Some((_, body)) = current.as_mut().filter(|_| line.starts_with("    ") || line.starts_with('\t')) {
+            body.push('\n');
+            body.push_str(line.trim());
+        } else if let Some((label, body)) = current.take() {
+            footnotes.insert(label, body);
+        }
+    }
+    if let Some((label, body)) = current {
+        footnotes.insert(label, body);
+    }
+    footnotes
+}
+
+// 导入 W3C Web Annotation（单个对象、数组或 AnnotationPage），按 TextQuoteSelector 在原文中定位
+pub fn import_web_annotations(conn: &Connection, json: &str, doc_path: &str) -> Result<usize, String> {
+    let doc = get_document_by_path(conn, doc_path)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let user = get_or_create_user(conn, "admin".to_string())?;
+    let editor = load_settings()?.editor;
+
+    let root: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let items: Vec<&serde_json::Value> = if let Some(items) = root.as_array() {
+        items.iter().collect()
+    } else if let Some(items) = root.get("items").and_then(|v| v.as_array()) {
+        items.iter().collect()
+    } else if let Some(items) = root.pointer("/first/items").and_then(|v| v.as_array()) {
+        // AnnotationCollection 内嵌第一页
+        items.iter().collect()
+    } else {
+        vec![&root]
+    };
+
+    let mut annotations = Vec::new();
+    let mut skipped = 0;
+    for item in items {
+        let (exact, prefix) = match web_annotation_quote(item) {
+            Some(quote) => quote,
+            None => {
+                skipped += 1;
+                continue;
+            }
+        };
+
+        // 有前缀时优先用前缀消歧
+        let start = prefix
+            .filter(|p| !p.is_empty())
+            .and_then(|p| doc.content.find(&format!("{}{}", p, exact)).map(|i| i + p.len()))
+            .or_else(|| doc.content.find(exact));
+        let start = match start {
+            Some(start) => start,
+            None => {
+                skipped += 1;
+                continue;
+            }
+        };
+
+        // 有作者时解析为对应的本地用户，保证 user_id 与 user_name 一致
+        let creator = match item.pointer("/creator/name").and_then(|v| v.as_str()).filter(|n| !n.trim().is_empty()) {
+            Some(name) => find_or_create_user_by_name(conn, name)?,
+            None => user.clone(),
+        };
+        annotations.push(build_annotation(
+            &doc.id,
+            &creator,
+            exact,
+            web_annotation_note(item),
+            &editor.default_highlight_color,
+            &editor.default_highlight_type,
+            (start, start + exact.len()),
+        ));
+    }
+
+    if skipped > 0 {
+        log_warn(&format!("Web annotation import: {} items skipped, no locatable TextQuoteSelector", skipped));
+    }
+    merge_imported_annotations(conn, &annotations, &doc.id)
+}
+
+// 取 target.selector 中的 TextQuoteSelector，返回 (exact, prefix)
+fn web_annotation_quote(item: &serde_json::Value) -> Option<(&str, Option<&str>)> {
+    let targets = json_as_list(item.get("target")?);
+    targets.into_iter()
+        .filter_map(|target| target.get("selector"))
+        .flat_map(json_as_list)
+        .find(|selector| selector.get("type").and_then(|t| t.as_str()) == Some("TextQuoteSelector"))
+        .and_then(|selector| {
+            let exact = selector.get("exact")?.as_str().filter(|e| !e.is_empty())?;
+            Some((exact, selector.get("prefix").and_then(|p| p.as_str())))
+        })
+}
+
+// body 可以是字符串、TextualBody 或其数组，多个正文以换行拼接；标签类正文忽略
+fn web_annotation_note(item: &serde_json::Value) -> Option<String> {
+    let body = item.get("body")?;
+    let parts: Vec<&str> = json_as_list(body)
+        .into_iter()
+        .filter(|b| b.get("purpose").and_then(|p| p.as_str()) != Some("tagging"))
+        .filter_map(|b| b.as_str().or_else(|| b.get("value").and_then(|v| v.as_str())))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("\n"))
+    }
+}
+
+fn json_as_list(value: &serde_json::Value) -> Vec<&serde_json::Value> {
+    match value.as_array() {
+        Some(list) => list.iter().collect(),
+        None => vec![value],
+    }
+}
+
+// 简易 CSV 解析：支持双引号包裹、"" 转义以及引号内换行
+fn parse_csv(csv: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = csv.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                '"' => in_quotes = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_quotes = true,
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => field.push(c),
+        }
+    }
+
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    rows
+}
+
+// 构造原文锚点（containerPath 为空，偏移量为原文字节偏移）
+pub fn build_text_anchor(start: usize, end: usize) -> String {
+    let anchor = AnchorRecord {
+        start_offset: start,
+        end_offset: end,
+        ..Default::default()
+    };
+    serde_json::to_string(&vec![anchor]).unwrap_or_default()
+}
+
+// 由后端创建注解时使用的默认字段
+pub fn build_annotation(
+    doc_id: &str,
+    user: &UserRecord,
+    text: &str,
+    note: Option<String>,
+    highlight_color: &str,
+    highlight_type: &str,
+    range: (usize, usize),
+) -> AnnotationRecord {
+    let now = Utc::now().timestamp_millis();
+    AnnotationRecord {
+        id: Uuid::new_v4().to_string(),
+        document_id: doc_id.to_string(),
+        user_id: user.id.clone(),
+        user_name: user.name.clone(),
+        text: text.to_string(),
+        note,
+        note_visible: false,
+        note_position_x: 0.0,
+        note_position_y: 0.0,
+        note_width: 280.0,
+        note_height: 180.0,
+        highlight_color: highlight_color.to_string(),
+        highlight_type: highlight_type.to_string(),
+        anchor_data: build_text_anchor(range.0, range.1),
+        created_at: now,
+        updated_at: now,
+        tags: Vec::new(),
+        comments: Vec::new(),
+        context: None,
+    }
+}
+
+// 由原文区间直接创建注解，颜色与类型缺省时取编辑器设置
+pub fn create_annotation(
+    conn: &Connection,
+    doc_path: &str,
+    start: usize,
+    end: usize,
+    content: &str,
+    note: Option<String>,
+    color: Option<String>,
+) -> Result<AnnotationRecord, String> {
+    if start >= end || end > content.len() {
+        return Err(format!("Invalid range {}..{} for content of length {}", start, end, content.len()));
+    }
+    if !content.is_char_boundary(start) || !content.is_char_boundary(end) {
+        return Err(format!("Range {}..{} is not on a character boundary", start, end));
+    }
+
+    // 与 add_annotation 命令一致：颜色可以是十六进制或预设名称，非法时在入库前拒绝
+    let editor = load_settings()?.editor;
+    let color = resolve_highlight_color(color.as_deref().unwrap_or(&editor.default_highlight_color))?;
+
+    // 前端传入的是编辑器中的当前内容，文档尚未入库时以此内容保存，之后的注解都挂在该文档上
+    let doc = match get_document_by_path(conn, doc_path)? {
+        Some(doc) => doc,
+        None => save_document(conn, doc_path, content)?,
+    };
+    let user = get_or_create_user(conn, "admin".to_string())?;
+
+    let anno = build_annotation(
+        &doc.id,
+        &user,
+        &content[start..end],
+        note.filter(|n| !n.trim().is_empty()),
+        &color,
+        &editor.default_highlight_type,
+        (start, end),
+    );
+    add_annotation(conn, &anno)?;
+    Ok(anno)
+}
+
+// 导入去重方式：text 仅按高亮文本去重；anchor 额外把锚点重叠超过阈值的注解视为重复
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupMode {
+    #[default]
+    Text,
+    Anchor,
+}
+
+impl DedupMode {
+    pub fn parse(value: &str) -> Result<DedupMode, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(DedupMode::Text),
+            "anchor" => Ok(DedupMode::Anchor),
+            _ => Err(format!("Invalid dedup strategy: {}", value)),
+        }
+    }
+}
+
+// 锚点模式的默认重叠阈值：重叠部分占较短一方的比例
+pub const DEFAULT_OVERLAP_THRESHOLD: f64 = 0.5;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MergeReport {
+    pub imported: usize,
+    pub skipped_text: Vec<String>,    // 文本重复而跳过的注解文本
+    pub skipped_overlap: Vec<String>, // 锚点重叠而跳过的注解文本
+}
+
+// 导入前把外部的 user_id 换成同名本地用户（不存在时创建），评论作者同样处理；名称为空的保持原样
+pub fn resolve_import_users(conn: &Connection, annotations: Vec<AnnotationRecord>) -> Result<Vec<AnnotationRecord>, String> {
+    let mut resolved: std::collections::HashMap<String, UserRecord> = std::collections::HashMap::new();
+    let mut resolve = |name: &str| -> Result<Option<UserRecord>, String> {
+        let key = name.trim().to_lowercase();
+        if key.is_empty() {
+            return Ok(None);
+        }
+        if let Some(user) = resolved.get(&key) {
+            return Ok(Some(user.clone()));
+        }
+        let user = find_or_create_user_by_name(conn, name)?;
+        resolved.insert(key, user.clone());
+        Ok(Some(user))
+    };
+
+    let mut result = Vec::with_capacity(annotations.len());
+    for mut anno in annotations {
+        if let Some(user) = resolve(&anno.user_name)? {
+            anno.user_id = user.id;
+            anno.user_name = user.name;
+        }
+        for comment in &mut anno.comments {
+            if let Some(user) = resolve(&comment.user_name)? {
+                comment.user_id = user.id;
+                comment.user_name = user.name;
+            }
+        }
+        result.push(anno);
+    }
+    Ok(result)
+}
+
+// 批量导入并去重
+pub fn merge_imported_annotations(conn: &Connection, annotations: &[AnnotationRecord], doc_id: &str) -> Result<usize, String> {
+    merge_imported_annotations_with_mode(conn, annotations, doc_id, DedupMode::Text, DEFAULT_OVERLAP_THRESHOLD)
+        .map(|report| report.imported)
+}
+
+pub fn merge_imported_annotations_with_mode(
+    conn: &Connection,
+    annotations: &[AnnotationRecord],
+    doc_id: &str,
+    mode: DedupMode,
+    overlap_threshold: f64,
+) -> Result<MergeReport, String> {
+    if !(0.0..=1.0).contains(&overlap_threshold) {
+        return Err(format!("Overlap threshold must be between 0 and 1: {}", overlap_threshold));
+    }
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let now = Utc::now().timestamp_millis();
+    let mut report = MergeReport::default();
+
+    // 获取现有的注解文本与锚点（用于去重）
+    let mut existing_texts = std::collections::HashSet::new();
+    let mut existing_anchors = Vec::new();
+    {
+        let mut stmt = conn.prepare("SELECT text, anchor_data FROM annotations WHERE document_id = ?")
+            .map_err(|e| e.to_string())?;
+        let mut rows = stmt.query([doc_id]).map_err(|e| e.to_string())?;
+        while let Ok(Some(row)) = rows.next() {
+            if let Ok(text) = row.get::<_, String>(0) {
+                existing_texts.insert(text);
+            }
+            if let Ok(anchor_data) = row.get::<_, String>(1) {
+                existing_anchors.push(anchor_data);
+            }
+        }
+    }
+
+    for mut anno in annotations.iter().cloned() {
+        // 去重：检查文本是否已存在
+        if existing_texts.contains(&anno.text) {
+            report.skipped_text.push(anno.text);
+            continue;
+        }
+        // 文档已修改时借助上下文重新定位
+        if let ReanchorOutcome::Relocated(anchor_data) = reanchor_annotation(&anno, &doc.content) {
+            anno.anchor_data = anchor_data;
+        }
+        if mode == DedupMode::Anchor
+            && existing_anchors.iter().any(|a| anchor_overlap_ratio(a, &anno.anchor_data) > overlap_threshold)
+        {
+            report.skipped_overlap.push(anno.text);
+            continue;
+        }
+
+        // 生成新 ID
+        anno.id = Uuid::new_v4().to_string();
+        anno.document_id = doc_id.to_string();
+        anno.created_at = now;
+        anno.updated_at = now;
+
+        insert_annotation(conn, &anno)?;
+        recreate_comments(conn, &anno)?;
+        // 同批次内的注解之间同样去重
+        existing_texts.insert(anno.text.clone());
+        existing_anchors.push(anno.anchor_data.clone());
+        report.imported += 1;
+    }
+    write_reading_order(conn, doc_id, &doc.content)?;
+
+    Ok(report)
+}
+
+// 目录批量导入中单个文件的结果
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PackageImportResult {
+    pub file: String,
+    pub imported: usize,
+    pub skipped_text: usize,
+    pub skipped_overlap: usize,
+    pub error: Option<String>, // 无法读取或不是注解包时的原因，此文件不导入任何注解
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ImportSummary {
+    pub total_imported: usize,
+    pub files: Vec<PackageImportResult>,
+}
+
+// 导入目录下所有 .json / .annoti 注解包（按文件名顺序），单个文件失败不影响其他文件
+pub fn import_packages_from_dir(conn: &Connection, dir: &str, doc_path: &str, strategy: &str) -> Result<ImportSummary, String> {
+    let mode = DedupMode::parse(strategy)?;
+    let doc = get_document_by_path(conn, doc_path)?
+        .ok_or_else(|| "Document not found".to_string())?;
+
+    let mut paths: Vec<std::path::PathBuf> = fs::read_dir(dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("json") || ext.eq_ignore_ascii_case("annoti"))
+        })
+        .collect();
+    paths.sort();
+
+    let mut summary = ImportSummary::default();
+    for path in paths {
+        let mut result = PackageImportResult {
+            file: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+
+        let annotations = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| import_annotation(&json, None));
+        match annotations.and_then(|annotations| {
+            merge_imported_annotations_with_mode(conn, &annotations, &doc.id, mode, DEFAULT_OVERLAP_THRESHOLD)
+        }) {
+            Ok(report) => {
+                result.imported = report.imported;
+                result.skipped_text = report.skipped_text.len();
+                result.skipped_overlap = report.skipped_overlap.len();
+                summary.total_imported += report.imported;
+            }
+            Err(e) => result.error = Some(e),
+        }
+        summary.files.push(result);
+    }
+
+    Ok(summary)
+}
+
+// 两组锚点的重叠比例：同一文本节点上的重叠长度之和除以较短一方的总长度，范围 0.0–1.0
+fn anchor_overlap_ratio(a: &str, b: &str) -> f64 {
+    if a == b {
+        return 1.0;
+    }
+    let (a, b) = (parse_anchors(a), parse_anchors(b));
+    let total = |anchors: &[AnchorRecord]| -> usize {
+        anchors.iter().map(|x| x.end_offset.saturating_sub(x.start_offset)).sum()
+    };
+    let shorter = total(&a).min(total(&b));
+    if shorter == 0 {
+        return 0.0;
+    }
+
+    let overlap: usize = a.iter()
+        .flat_map(|x| b.iter().map(move |y| (x, y)))
+        .filter(|(x, y)| x.container_path == y.container_path && x.text_node_index == y.text_node_index)
+        .map(|(x, y)| x.end_offset.min(y.end_offset).saturating_sub(x.start_offset.max(y.start_offset)))
+        .sum();
+    (overlap as f64 / shorter as f64).min(1.0)
+}
+
+// ============ 注解 Slug ============
+
+// slug 取高亮文本的前几个词
+const SLUG_WORDS: usize = 5;
+const SLUG_MAX_CHARS: usize = 40;
+
+// 文本前几个词（保留各语言的字母与数字）加 ID 哈希前缀，同一注解始终得到相同结果
+fn base_annotation_slug(anno: &AnnotationRecord) -> String {
+    let mut words: Vec<String> = Vec::new();
+    for word in anno.text.split_whitespace().take(SLUG_WORDS) {
+        let word: String = word.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect();
+        if !word.is_empty() {
+            words.push(word);
+        }
+    }
+    let text: String = words.join("-").chars().take(SLUG_MAX_CHARS).collect();
+    let text = text.trim_end_matches('-');
+    let hash = &compute_checksum(&anno.id)[..6];
+
+    if text.is_empty() {
+        format!("note-{}", hash)
+    } else {
+        format!("{}-{}", text, hash)
+    }
+}
+
+// 为一组注解生成互不相同的 slug，按创建时间顺序分配，重复时追加 -2、-3…
+pub fn annotation_slugs(annotations: &[AnnotationRecord]) -> std::collections::HashMap<String, String> {
+    let mut ordered: Vec<&AnnotationRecord> = annotations.iter().collect();
+    ordered.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+
+    let mut used = std::collections::HashSet::new();
+    let mut slugs = std::collections::HashMap::new();
+    for anno in ordered {
+        let base = base_annotation_slug(anno);
+        let mut slug = base.clone();
+        let mut suffix = 2;
+        while !used.insert(slug.clone()) {
+            slug = format!("{}-{}", base, suffix);
+            suffix += 1;
+        }
+        slugs.insert(anno.id.clone(), slug);
+    }
+    slugs
+}
+
+// 注解在其所属文档内的 slug，与 HTML 导出中便签的元素 ID 一致
+pub fn get_annotation_slug(conn: &Connection, id: &str) -> Result<String, String> {
+    let anno = get_annotation_by_id(conn, id)?
+        .ok_or_else(|| "Annotation not found".to_string())?;
+    let annotations = get_annotations_by_doc(conn, &anno.document_id)?;
+    annotation_slugs(&annotations)
+        .remove(id)
+        .ok_or_else(|| "Annotation not found".to_string())
+}
+
+// ============ 导出过滤 ============
+
+// 校验高亮颜色格式：#rgb、#rrggbb 或 #rrggbbaa
+pub fn validate_color(color: &str) -> Result<(), String> {
+    let hex = color.strip_prefix('#').unwrap_or("");
+    if matches!(hex.len(), 3 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        Err(format!("Invalid color: {}", color))
+    }
+}
+
+pub fn get_color_presets() -> Result<Vec<ColorPreset>, String> {
+    Ok(load_settings()?.editor.color_presets)
+}
+
+// 高亮颜色可以是十六进制值或预设名称（忽略大小写），统一解析为十六进制
+pub fn resolve_highlight_color(color: &str) -> Result<String, String> {
+    if validate_color(color).is_ok() {
+        return Ok(color.to_string());
+    }
+    get_color_presets()?
+        .into_iter()
+        .find(|p| p.name.eq_ignore_ascii_case(color.trim()))
+        .map(|p| p.hex)
+        .ok_or_else(|| format!("Invalid color: {}", color))
+}
+
+// 按高亮颜色过滤注解，颜色列表为空时保留全部；颜色统一解析为 RGB 比较，#FD0、#ffdd00 与 #FFDD00FF 视为同一颜色
+pub fn filter_by_colors(annotations: Vec<AnnotationRecord>, colors: &[String]) -> Result<Vec<AnnotationRecord>, String> {
+    if colors.is_empty() {
+        return Ok(annotations);
+    }
+    let wanted = colors
+        .iter()
+        .map(|color| parse_hex_color(color).ok_or_else(|| format!("Invalid color: {}", color)))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(annotations
+        .into_iter()
+        .filter(|anno| parse_hex_color(&anno.highlight_color).is_some_and(|rgb| wanted.contains(&rgb)))
+        .collect())
+}
+
+// 按作者名过滤注解（忽略大小写），作者列表为空时保留全部
+pub fn filter_by_authors(annotations: Vec<AnnotationRecord>, authors: &[String]) -> Vec<AnnotationRecord> {
+    if authors.is_empty() {
+        return annotations;
+    }
+    let authors: Vec<String> = authors.iter().map(|a| a.trim().to_lowercase()).collect();
+    annotations
+        .into_iter()
+        .filter(|anno| authors.contains(&anno.user_name.trim().to_lowercase()))
+        .collect()
+}
+
+// 导出时的注解过滤条件，前端未传入的字段为空，表示不过滤
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ExportFilter {
+    pub colors: Vec<String>,  // 仅导出这些高亮颜色，为空时导出全部
+    pub authors: Vec<String>, // 仅导出这些作者的注解（忽略大小写），为空时导出全部
+}
+
+impl ExportFilter {
+    pub fn apply(&self, annotations: Vec<AnnotationRecord>) -> Result<Vec<AnnotationRecord>, String> {
+        Ok(filter_by_authors(filter_by_colors(annotations, &self.colors)?, &self.authors))
+    }
+}
+
+// ============ HTML 导出 ============
+
+// HTML 导出选项，前端未传入的字段使用默认值
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct HtmlExportOptions {
+    pub print_friendly: bool, // 打印时高亮改为仅下划线，并隐藏悬浮按钮
+    pub debug_timing: bool,   // 返回各阶段耗时，否则仅打印到控制台
+    #[serde(flatten)]
+    pub filter: ExportFilter, // 按颜色与作者过滤，字段与其他导出一致
+    pub custom_css: Option<String>, // 追加在内置样式之后，用于覆盖颜色与字体
+    pub show_legend: bool,          // 在正文前列出用到的高亮颜色及其说明
+    pub show_qr: bool,              // 每张便签附带指向 annoti://open 的二维码，便于从纸质稿跳回应用
+}
+
+// HTML 导出各阶段耗时（毫秒）
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HtmlExportTiming {
+    pub load_annotations_ms: f64,
+    pub inject_highlights_ms: f64,
+    pub render_ms: f64,
+    pub annotation_count: usize,
+}
+
+pub fn export_as_html(conn: &Connection, doc_id: &str, anno_ids: &[String], content: &str, options: &HtmlExportOptions) -> Result<(String, Option<HtmlExportTiming>), String> {
+    let started = Instant::now();
+    let doc = {
+        let mut stmt = conn.prepare("SELECT id, path FROM documents WHERE id = ?")
+            .map_err(|e| e.to_string())?;
+        let mut rows = stmt.query([doc_id]).map_err(|e| e.to_string())?;
+        if let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            Some(DocumentRecord {
+                id: row.get(0).map_err(|e| e.to_string())?,
+                path: row.get(1).map_err(|e| e.to_string())?,
+                content: content.to_string(),
+                checksum: String::new(),
+                last_modified: 0,
+                created_at: 0,
+            })
+        } else {
+            None
+        }
+    }.ok_or_else(|| "Document not found".to_string())?;
+
+    let mut annotations = Vec::new();
+    for anno_id in anno_ids {
+        if let Some(anno) = get_annotation_by_id(conn, anno_id)? {
+            annotations.push(anno);
+        }
+    }
+    let annotations = options.filter.apply(annotations)?;
+    let annotations = with_comments(conn, annotations)?;
+    let loaded = Instant::now();
+
+    // 便签与注解数据注入
+    let (notes_html, payload) = build_note_layer(&annotations, options.show_qr.then_some(doc.id.as_str()));
+    let legend_html = if options.show_legend {
+        build_color_legend(&annotations)?
+    } else {
+        String::new()
+    };
+    let injected = Instant::now();
+
+    // 直接使用前端传来的已渲染 HTML，不再重复解析
+    let html = generate_readonly_html(&doc.path, &doc.content, &notes_html, &legend_html, &payload, options);
+    let rendered = Instant::now();
+
+    let timing = HtmlExportTiming {
+        load_annotations_ms: elapsed_ms(started, loaded),
+        inject_highlights_ms: elapsed_ms(loaded, injected),
+        render_ms: elapsed_ms(injected, rendered),
+        annotation_count: annotations.len(),
+    };
+
+    if options.debug_timing {
+        Ok((html, Some(timing)))
+    } else {
+        log_info(&format!(
+            "HTML 导出耗时: 加载 {:.2}ms, 注入 {:.2}ms, 渲染 {:.2}ms ({} 条注解)",
+            timing.load_annotations_ms, timing.inject_highlights_ms, timing.render_ms, timing.annotation_count
+        ));
+        Ok((html, None))
+    }
+}
+
+fn elapsed_ms(from: Instant, to: Instant) -> f64 {
+    to.duration_since(from).as_secs_f64() * 1000.0
+}
+
+#[allow(dead_code)]
+fn markdown_to_html(markdown: &str) -> String {
+    // 简化版：实际应集成 marked 或 pulldown-cmark
+    let mut html = markdown
+        .replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("# ", "<h1>")
+        .replace("\n## ", "</h1>\n<h2>")
+        .replace("\n### ", "</h2>\n<h3>")
+        .replace("\n#### ", "</h3>\n<h4>")
+        .replace("\n##### ", "</h4>\n<h5>")
+        .replace("\n###### ", "</h5>\n<h6>")
+        .replace("\n", "<br>\n");
+
+    // 简单的代码块
+    if let Some(start) = html.find("```") {
+        if let Some(end) = html[start+3..].find("```") {
+            let code_start = start + 3;
+            let code_end = start + 3 + end;
+            let code = &html[code_start..code_end];
+            let before = &html[..code_start];
+            let after = &html[code_end + 3..];
+            html = format!("{}<pre><code>{}</code></pre>{}", before, code, after);
+        }
+    }
+
+    // 简单的粗体和斜体
+    html = html.replace("**", "<strong>").replace("*", "<em>");
+
+    // 简单的列表
+    html = html.replace("- ", "<li>");
+
+    html
+}
+
+// 生成便签层与注解数据，对应高亮注入阶段
+// qr_doc_id 存在时在每张便签底部附加深链接二维码
+fn build_note_layer(annotations: &[AnnotationRecord], qr_doc_id: Option<&str>) -> (String, String) {
+    let mut notes_html = String::new();
+    let slugs = annotation_slugs(annotations);
+
+    for anno in annotations {
+        let empty_note = String::new();
+        let note_text = anno.note.as_ref().unwrap_or(&empty_note);
+        let mut style = format!(
+            "left: {:.0}px; top: {:.0}px; width: {:.0}px; height: {:.0}px;",
+            anno.note_position_x, anno.note_position_y,
+            anno.note_width, anno.note_height
+        );
+        // 便签随高亮色着色，无法解析的颜色沿用默认黄色
+        let mut header_style = String::new();
+        if let Some((background, header, foreground)) = note_tint(&anno.highlight_color) {
+            style.push_str(&format!(" background: {};", background));
+            header_style = format!(r#" style="background: {}; color: {};""#, header, foreground);
+        }
+
+        notes_html.push_str(&format!(r#"
+        <div class="sticky-note" id="{}" data-anno-id="{}" style="{}">
+            <div class="note-header"{}>
+                <span class="note-author">{}</span>
+                <button class="note-close" onclick="closeNote('{}')">&times;</button>
+            </div>
+            <div class="note-content">{}</div>{}
+        </div>
+        "#,
+            slugs[&anno.id], anno.id, style, header_style,
+            escape_html(&anno.user_name),
+            anno.id,
+            escape_html(note_text),
+            qr_doc_id.map(|doc_id| annotation_qr_html(doc_id, &anno.id)).unwrap_or_default()
+        ));
+    }
+
+    let payload = serde_json::to_string(&annotations).unwrap_or_default();
+
+    (notes_html, payload)
+}
+
+// 深链接二维码（内联 SVG）；生成失败时省略，不影响导出
+fn annotation_qr_html(doc_id: &str, anno_id: &str) -> String {
+    let url = format!("annoti://open?doc={}&anno={}", encode_uri_component(doc_id), encode_uri_component(anno_id));
+    let Ok(code) = qrcode::QrCode::new(url.as_bytes()) else {
+        return String::new();
+    };
+    let svg = code
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(72, 72)
+        .quiet_zone(false)
+        .build();
+    // 去掉 XML 声明，只保留 <svg> 元素
+    let svg = svg.find("<svg").map(|i| &svg[i..]).unwrap_or(&svg);
+    format!(r#"
+            <div class="note-qr" title="{}">{}</div>"#, escape_html(&url), svg)
+}
+
+// URL 查询参数编码，保留 RFC 3986 非保留字符
+fn encode_uri_component(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+// 颜色图例：按首次出现顺序列出用到的颜色，说明取自导出设置的 color_labels，其次为预设名称
+fn build_color_legend(annotations: &[AnnotationRecord]) -> Result<String, String> {
+    let settings = load_settings()?;
+    let mut colors: Vec<String> = Vec::new();
+    for anno in annotations {
+        let color = anno.highlight_color.to_lowercase();
+        if !colors.contains(&color) {
+            colors.push(color);
+        }
+    }
+    if colors.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut items = String::new();
+    for color in &colors {
+        let label = settings.export.color_labels.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(color))
+            .map(|(_, label)| label.clone())
+            .or_else(|| settings.editor.color_presets.iter()
+                .find(|p| p.hex.eq_ignore_ascii_case(color))
+                .map(|p| p.name.clone()))
+            .unwrap_or_default();
+        items.push_str(&format!(
+            r#"<li><span class="legend-swatch" style="background: {0};"></span><code>{0}</code> {1}</li>"#,
+            escape_html(color), escape_html(&label)
+        ));
+    }
+    Ok(format!(r#"<ul class="color-legend">{}</ul>"#, items))
+}
+
+fn generate_readonly_html(_doc_name: &str, content: &str, notes_html: &str, legend_html: &str, payload: &str, options: &HtmlExportOptions) -> String {
+    // 打印样式：高亮只保留下划线以节省墨水
+    let print_css = if options.print_friendly {
+        r#"
+        @media print {
+            .doc-highlight { background: none !important; border-bottom: 2px solid #333; }
+            .reopen-btn { display: none !important; }
+        }"#
+    } else {
+        ""
+    };
+
+    // 自定义样式单独放在一个 style 块中，保证覆盖内置规则
+    let custom_css = match options.custom_css.as_deref().map(sanitize_custom_css) {
+        Some(css) if !css.trim().is_empty() => format!("\n    <style id=\"custom-css\">\n{}\n    </style>", css),
+        _ => String::new(),
+    };
+
+    // 注意：使用 format! 和 HTML 手动拼接，避免 script 中 {} 出现问题
+    let html = format!(r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>Annotated</title>
+    <style>
+        * {{ margin: 0; padding: 0; box-sizing: border-box; }}
+        body {{ font-family: system-ui, -apple-system, sans-serif; background: #242424; color: #ddd; font-size: 16px !important; line-height: 1.6 !important; position: relative; }}
+        .container {{ max-width: 900px; margin: 0 auto; padding: 20px; }}
+        .container h1 {{ font-size: 2em !important; color: #fff !important; margin: 1em 0 0.5em !important; }}
+        .container h2 {{ font-size: 1.5em !important; color: #fff !important; margin: 1em 0 0.5em !important; }}
+        .container h3 {{ font-size: 1.25em !important; color: #fff !important; margin: 1em 0 0.5em !important; }}
+        .container h4 {{ font-size: 1.1em !important; color: #fff !important; margin: 1em 0 0.5em !important; }}
+        .container h5 {{ font-size: 1em !important; color: #fff !important; margin: 1em 0 0.5em !important; }}
+        .container h6 {{ font-size: 0.9em !important; color: #aaa !important; margin: 1em 0 0.5em !important; }}
+        .container p {{ font-size: 1em !important; margin: 0.8em 0 !important; }}
+        .container ul, .container ol {{ font-size: 1em !important; margin: 0.8em 0 !important; padding-left: 2em; }}
+        .container li {{ font-size: 1em !important; margin: 0.3em 0; }}
+        .container blockquote {{ font-size: 1em !important; margin: 0.8em 0; padding-left: 1em; border-left: 3px solid #444; color: #999; }}
+        .markdown-body {{ position: relative; }}
+        .markdown-body pre {{ background: #1a1a1a; padding: 1em; overflow-x: auto; border-radius: 4px; }}
+        .markdown-body code {{ background: #1a1a1a; padding: 0.2em 0.4em; border-radius: 3px; }}
+        .doc-highlight {{
+            background: rgba(255, 215, 0, 0.3);
+            border-bottom: 2px solid gold;
             cursor: pointer;
             padding: 2px 0;
         }}
@@ -701,6 +4732,7 @@ fn generate_readonly_html(_doc_name: &str, content: &str, annotations: &[Annotat
         .note-close {{
             background: none;
             border: none;
+            color: inherit;
             font-size: 18px;
             cursor: pointer;
             padding: 0 4px;
@@ -708,6 +4740,8 @@ fn generate_readonly_html(_doc_name: &str, content: &str, annotations: &[Annotat
         }}
         .note-close:hover {{ opacity: 1; }}
         .note-content {{ padding: 10px; font-size: 14px; white-space: pre-wrap; }}
+        .note-qr {{ padding: 0 10px 10px; text-align: right; }}
+        .note-qr svg {{ width: 72px; height: 72px; }}
         .reopen-btn {{
             position: fixed;
             bottom: 20px;
@@ -724,119 +4758,2325 @@ fn generate_readonly_html(_doc_name: &str, content: &str, annotations: &[Annotat
             z-index: 2000;
         }}
         .reopen-btn:hover {{ background: #ffed4a; }}
+        .color-legend {{ list-style: none; display: flex; flex-wrap: wrap; gap: 12px; margin-bottom: 1em; font-size: 14px; }}
+        .color-legend li {{ display: flex; align-items: center; gap: 6px; }}
+        .legend-swatch {{ display: inline-block; width: 14px; height: 14px; border-radius: 3px; }}{}
+    </style>{}
+</head>
+<body>
+    <div class="container">
+        <h1>Annotated</h1>
+        {}
+        <div class="markdown-body">{}</div>
+    </div>
+    {}
+
+    <button class="reopen-btn" onclick="showAllNotes()" title="显示所有便签">📝</button>
+
+    <script type="application/json" id="ann-payload">
+{}
+    </script>
+
+    <script>
+        const annotations = JSON.parse(document.getElementById('ann-payload').textContent);
+
+        // 点击高亮滚动到便签
+        document.querySelectorAll('.doc-highlight').forEach(function(el) {{
+            el.addEventListener('click', function() {{
+                const id = el.dataset.annoId;
+                const note = document.querySelector('.sticky-note[data-anno-id="' + id + '"]');
+                if (note) {{
+                    note.style.display = 'block';
+                    note.scrollIntoView({{ behavior: 'smooth', block: 'center' }});
+                    note.style.opacity = '1';
+                }}
+            }});
+        }});
+
+        // 通过 #slug 链接打开时显示对应便签
+        if (location.hash) {{
+            const target = document.getElementById(decodeURIComponent(location.hash.slice(1)));
+            if (target && target.classList.contains('sticky-note')) {{
+                target.style.display = 'block';
+                target.scrollIntoView({{ block: 'center' }});
+            }}
+        }}
+
+        function closeNote(id) {{
+            const note = document.querySelector('.sticky-note[data-anno-id="' + id + '"]');
+            if (note) note.style.display = 'none';
+        }}
+
+        function showNote(id) {{
+            const note = document.querySelector('.sticky-note[data-anno-id="' + id + '"]');
+            if (note) {{
+                note.style.display = 'block';
+                note.style.opacity = '1';
+            }}
+        }}
+
+        function showAllNotes() {{
+            document.querySelectorAll('.sticky-note').forEach(function(note) {{
+                note.style.display = 'block';
+                note.style.opacity = '1';
+            }});
+        }}
+
+        // 拖拽功能
+        document.querySelectorAll('.sticky-note').forEach(function(note) {{
+            let isDragging = false;
+            let startX, startY, origX, origY;
+
+            note.querySelector('.note-header').addEventListener('mousedown', function(e) {{
+                isDragging = true;
+                startX = e.clientX;
+                startY = e.clientY;
+                origX = note.offsetLeft;
+                origY = note.offsetTop;
+                note.style.zIndex = 1001;
+            }});
+
+            document.addEventListener('mousemove', function(e) {{
+                if (!isDragging) return;
+                const dx = e.clientX - startX;
+                const dy = e.clientY - startY;
+                note.style.left = (origX + dx) + 'px';
+                note.style.top = (origY + dy) + 'px';
+            }});
+
+            document.addEventListener('mouseup', function() {{
+                isDragging = false;
+                note.style.zIndex = 1000;
+            }});
+        }});
+    </script>
+</body>
+</html>"#,
+        print_css,
+        custom_css,
+        legend_html,
+        content,
+        notes_html,
+        payload
+    );
+
+    html
+}
+
+// 转义自定义样式中的 '<'，防止通过 </style> 提前闭合标签注入脚本
+// CSS 中 '<' 只会出现在字符串或注释里，转义为 \3C 不影响样式效果
+fn sanitize_custom_css(css: &str) -> String {
+    css.replace('<', "\\3C ")
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+}
+
+// ============ 按格式导出 ============
+
+// 按格式名生成导出内容，不写入文件；anno_ids 仅用于 HTML，其他格式导出文档中通过过滤条件的全部注解
+pub fn export_by_format(conn: &Connection, format: &str, doc_id: &str, anno_ids: &[String], content: &str, filter: &ExportFilter) -> Result<String, String> {
+    match format.trim().to_ascii_lowercase().as_str() {
+        "html" => {
+            let options = HtmlExportOptions { filter: filter.clone(), ..HtmlExportOptions::default() };
+            export_as_html(conn, doc_id, anno_ids, content, &options).map(|(html, _)| html)
+        }
+        "md" | "markdown" | "obsidian" => export_as_obsidian(conn, doc_id, content, filter),
+        "cornell" => export_as_cornell(conn, doc_id, content, filter),
+        "mermaid" => export_as_mermaid(conn, doc_id, filter),
+        "index-cards" => export_as_index_cards(conn, doc_id, filter),
+        "srt" => export_as_positioned_notes(conn, doc_id, filter),
+        "github-review" => export_as_github_review(conn, doc_id, filter),
+        "heatmap" => export_heatmap_json(conn, doc_id, filter),
+        "geojson" => export_note_positions(conn, doc_id, filter),
+        "bib" | "bibtex" => export_as_bibtex(conn, doc_id, filter),
+        "kanban" => export_as_kanban(conn, doc_id, filter),
+        other => Err(format!("Unsupported export format: {}", other)),
+    }
+}
+
+// 应用内预览，不写入文件：文本格式直接返回内容，二进制格式（EPUB、PDF）返回 base64 data URI
+pub fn preview_export(conn: &Connection, format: &str, doc_id: &str, anno_ids: &[String], content: &str, filter: &ExportFilter) -> Result<String, String> {
+    let (mime, bytes) = match format.trim().to_ascii_lowercase().as_str() {
+        "epub" => ("application/epub+zip", build_epub(conn, doc_id, content, filter)?),
+        "pdf" => ("application/pdf", build_documents_pdf(conn, &[doc_id.to_string()], filter)?),
+        _ => return export_by_format(conn, format, doc_id, anno_ids, content, filter),
+    };
+    Ok(format!("data:{};base64,{}", mime, BASE64.encode(bytes)))
+}
+
+// 生成导出内容并写入目标路径：先写临时文件再重命名，失败时不会留下不完整的文件
+pub fn export_and_save(conn: &Connection, doc_id: &str, anno_ids: &[String], content: &str, dest_path: &str, format: &str, filter: &ExportFilter) -> Result<String, String> {
+    let output = export_by_format(conn, format, doc_id, anno_ids, content, filter)?;
+    let path = write_export_file(dest_path, output.as_bytes())?;
+    Ok(path)
+}
+
+// 每位作者单独导出一个文件 <文档名>-<作者>.<扩展名>，返回写入的路径；仅支持可按作者过滤的格式
+pub fn export_authors_to_dir(conn: &Connection, doc_id: &str, dest_dir: &str, format: &str) -> Result<Vec<String>, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let format = format.trim().to_ascii_lowercase();
+    let extension = match format.as_str() {
+        "cornell" => "html",
+        "mermaid" => "mmd",
+        "srt" => "srt",
+        "github-review" => "json",
+        other => return Err(format!("Unsupported per-author export format: {}", other)),
+    };
+
+    // 作者按名称忽略大小写去重，与 filter_by_authors 的匹配规则一致
+    let mut authors: Vec<String> = Vec::new();
+    for anno in get_annotations_by_doc(conn, doc_id)? {
+        if !authors.iter().any(|a| a.trim().to_lowercase() == anno.user_name.trim().to_lowercase()) {
+            authors.push(anno.user_name);
+        }
+    }
+
+    let stem = std::path::Path::new(&doc.path)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let dir = std::path::Path::new(dest_dir);
+    fs::create_dir_all(resolve_export_path(dest_dir)?).map_err(|e| e.to_string())?;
+
+    let mut used_names: std::collections::HashSet<String> = std::collections::HashSet::new();
+    let mut paths = Vec::new();
+    for author in authors {
+        let filter = ExportFilter { authors: vec![author.clone()], ..ExportFilter::default() };
+        let output = match format.as_str() {
+            "cornell" => export_as_cornell(conn, doc_id, &doc.content, &filter)?,
+            "mermaid" => export_as_mermaid(conn, doc_id, &filter)?,
+            "srt" => export_as_positioned_notes(conn, doc_id, &filter)?,
+            _ => export_as_github_review(conn, doc_id, &filter)?,
+        };
+
+        // 清理后同名的作者追加序号
+        let base = format!("{}-{}", sanitize_file_component(&stem), sanitize_file_component(&author));
+        let mut name = format!("{}.{}", base, extension);
+        let mut suffix = 2;
+        while !used_names.insert(name.to_lowercase()) {
+            name = format!("{}-{}.{}", base, suffix, extension);
+            suffix += 1;
+        }
+
+        let path = write_export_file(&dir.join(&name).to_string_lossy(), output.as_bytes())?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+// 替换文件名中各平台不允许的字符
+fn sanitize_file_component(name: &str) -> String {
+    let cleaned: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*') { '_' } else { c })
+        .collect();
+    let cleaned = cleaned.trim_matches(['.', ' ']).to_string();
+    if cleaned.is_empty() { "unnamed".to_string() } else { cleaned }
+}
+
+// 解析导出路径并原子写入，返回最终路径
+fn write_export_file(dest_path: &str, bytes: &[u8]) -> Result<String, String> {
+    let target = resolve_export_path(dest_path)?;
+
+    let mut temp_name = target.file_name()
+        .ok_or_else(|| format!("Invalid export path: {}", dest_path))?
+        .to_os_string();
+    temp_name.push(".tmp");
+    let temp = target.with_file_name(temp_name);
+
+    if let Err(e) = fs::write(&temp, bytes).and_then(|_| fs::rename(&temp, &target)) {
+        let _ = fs::remove_file(&temp);
+        return Err(e.to_string());
+    }
+
+    Ok(target.to_string_lossy().to_string())
+}
+
+// ============ 导出记录 ============
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExportRecord {
+    pub id: String,
+    pub document_id: Option<String>,
+    pub format: String,
+    pub path: String,
+    pub created_at: i64,
+}
+
+// 记录导出并清除文件已不存在的旧记录；由命令层在导出完成后以写连接调用
+pub fn record_export(conn: &Connection, doc_id: Option<&str>, format: &str, path: &str) -> Result<(), String> {
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    tx.execute(
+        "INSERT INTO exports (id, document_id, format, path, created_at) VALUES (?, ?, ?, ?, ?)",
+        params![Uuid::new_v4().to_string(), doc_id, format, path, Utc::now().timestamp_millis()],
+    ).map_err(|e| e.to_string())?;
+
+    let missing: Vec<String> = read_export_records(&tx)?
+        .into_iter()
+        .filter(|record| !std::path::Path::new(&record.path).exists())
+        .map(|record| record.id)
+        .collect();
+    for id in &missing {
+        tx.execute("DELETE FROM exports WHERE id = ?", params![id])
+            .map_err(|e| e.to_string())?;
+    }
+    tx.commit().map_err(|e| e.to_string())
+}
+
+fn read_export_records(conn: &Connection) -> Result<Vec<ExportRecord>, String> {
+    let mut stmt = conn.prepare("SELECT id, document_id, format, path, created_at FROM exports ORDER BY created_at DESC")
+        .map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+
+    let mut records = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        records.push(ExportRecord {
+            id: row.get(0).map_err(|e| e.to_string())?,
+            document_id: row.get(1).map_err(|e| e.to_string())?,
+            format: row.get(2).map_err(|e| e.to_string())?,
+            path: row.get(3).map_err(|e| e.to_string())?,
+            created_at: row.get(4).map_err(|e| e.to_string())?,
+        });
+    }
+    Ok(records)
+}
+
+// 最近的导出记录（新的在前）；只读查询，文件已不存在的记录被跳过，在下次记录导出时清除
+pub fn list_recent_exports(conn: &Connection, limit: usize) -> Result<Vec<ExportRecord>, String> {
+    Ok(read_export_records(conn)?
+        .into_iter()
+        .filter(|record| std::path::Path::new(&record.path).exists())
+        .take(limit)
+        .collect())
+}
+
+// ============ GitHub Review 导出 ============
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReviewComment {
+    pub path: String,
+    pub line: Option<usize>, // 无法确定行号时为 null，作为整体评论
+    pub body: String,
+}
+
+pub fn export_as_github_review(conn: &Connection, doc_id: &str, filter: &ExportFilter) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+
+    let mut line_comments = Vec::new();
+    let mut general_comments = Vec::new();
+
+    for anno in &annotations {
+        let body = match anno.note.as_deref() {
+            Some(note) if !note.trim().is_empty() => note.to_string(),
+            _ => format!("> {}", anno.text),
+        };
+
+        // 优先使用锚点中的行号，否则根据偏移量计算
+        let line = parse_anchors(&anno.anchor_data)
+            .iter()
+            .find_map(|a| a.line)
+            .or_else(|| locate_annotation(anno, &doc.content).map(|(start, _)| line_of_offset(&doc.content, start)));
+
+        let comment = ReviewComment {
+            path: doc.path.clone(),
+            line,
+            body,
+        };
+        if line.is_some() {
+            line_comments.push(comment);
+        } else {
+            general_comments.push(comment);
+        }
+    }
+
+    line_comments.sort_by_key(|c| c.line);
+    line_comments.extend(general_comments);
+
+    serde_json::to_string_pretty(&line_comments).map_err(|e| e.to_string())
+}
+
+// ============ 康奈尔笔记导出 ============
+
+pub fn export_as_cornell(conn: &Connection, doc_id: &str, content: &str, filter: &ExportFilter) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+    let ordered = sort_by_position(annotations, content);
+
+    let title = std::path::Path::new(&doc.path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    let mut rows_html = String::new();
+    let mut authors: Vec<String> = Vec::new();
+    let mut noted = 0;
+
+    for (anno, _) in &ordered {
+        let cue = match anno.note.as_deref() {
+            Some(note) if !note.trim().is_empty() => {
+                noted += 1;
+                escape_html(note.trim())
+            }
+            _ => String::new(),
+        };
+        if !authors.contains(&anno.user_name) {
+            authors.push(anno.user_name.clone());
+        }
+
+        rows_html.push_str(&format!(r#"
+            <div class="cornell-row">
+                <div class="cornell-cue">{}<div class="cornell-author">{}</div></div>
+                <div class="cornell-note"><mark style="background: {}40;">{}</mark></div>
+            </div>"#,
+            cue,
+            escape_html(&anno.user_name),
+            escape_html(&anno.highlight_color),
+            escape_html(&anno.text)
+        ));
+    }
+
+    let html = format!(r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>{} - Cornell Notes</title>
+    <style>
+        * {{ margin: 0; padding: 0; box-sizing: border-box; }}
+        body {{ font-family: system-ui, -apple-system, sans-serif; color: #222; background: #fff; font-size: 14px; line-height: 1.6; }}
+        .cornell {{ max-width: 900px; margin: 0 auto; padding: 24px; }}
+        .cornell h1 {{ font-size: 1.6em; margin-bottom: 16px; border-bottom: 2px solid #333; padding-bottom: 8px; }}
+        .cornell-header {{ display: grid; grid-template-columns: 30% 70%; font-weight: bold; border-bottom: 1px solid #333; }}
+        .cornell-header div {{ padding: 6px 12px; }}
+        .cornell-row {{ display: grid; grid-template-columns: 30% 70%; border-bottom: 1px solid #ccc; page-break-inside: avoid; }}
+        .cornell-cue {{ padding: 10px 12px; border-right: 2px solid #333; white-space: pre-wrap; }}
+        .cornell-author {{ margin-top: 6px; font-size: 0.85em; color: #777; }}
+        .cornell-note {{ padding: 10px 12px; }}
+        .cornell-note mark {{ color: inherit; padding: 1px 2px; }}
+        .cornell-summary {{ margin-top: 24px; padding: 12px; border-top: 2px solid #333; }}
+        .cornell-summary h2 {{ font-size: 1.1em; margin-bottom: 8px; }}
     </style>
 </head>
 <body>
-    <div class="container">
-        <h1>Annotated</h1>
-        <div class="markdown-body">{}</div>
+    <div class="cornell">
+        <h1>{}</h1>
+        <div class="cornell-header"><div>Cues</div><div>Notes</div></div>
+        {}
+        <div class="cornell-summary">
+            <h2>Summary</h2>
+            <p>{} highlights, {} with notes. Contributors: {}.</p>
+        </div>
     </div>
-    {}
+</body>
+</html>"#,
+        escape_html(&title),
+        escape_html(&title),
+        rows_html,
+        ordered.len(),
+        noted,
+        escape_html(&authors.join(", "))
+    );
+
+    Ok(html)
+}
+
+// ============ 索引卡片导出 ============
+
+// 每条注解一张 5×3 英寸卡片：正面为高亮原文，背面为笔记；打印时正反面各占一页，便于双面打印
+pub fn export_as_index_cards(conn: &Connection, doc_id: &str, filter: &ExportFilter) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+
+    let title = std::path::Path::new(&doc.path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    let mut cards_html = String::new();
+    for (index, (anno, _)) in sort_by_position(annotations, &doc.content).iter().enumerate() {
+        let note = anno.note.as_deref().map(str::trim).filter(|n| !n.is_empty()).unwrap_or("");
+        cards_html.push_str(&format!(r#"
+        <section class="card" data-anno-id="{}">
+            <div class="card-face card-front" style="border-left-color: {};">
+                <span class="card-number">{}</span>
+                <blockquote>{}</blockquote>
+            </div>
+            <div class="card-face card-back">
+                <span class="card-number">{}</span>
+                <p>{}</p>
+                <span class="card-author">{}</span>
+            </div>
+        </section>"#,
+            escape_html(&anno.id),
+            escape_html(&anno.highlight_color),
+            index + 1,
+            escape_html(&anno.text),
+            index + 1,
+            escape_html(note),
+            escape_html(&anno.user_name)
+        ));
+    }
+
+    let html = format!(r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>{} - Index Cards</title>
+    <style>
+        * {{ margin: 0; padding: 0; box-sizing: border-box; }}
+        body {{ font-family: system-ui, -apple-system, sans-serif; color: #222; background: #eee; font-size: 14px; line-height: 1.5; }}
+        .card {{ display: flex; gap: 16px; justify-content: center; padding: 12px; }}
+        .card-face {{ position: relative; width: 5in; height: 3in; padding: 0.3in; background: #fff; border: 1px solid #ccc; overflow: hidden; display: flex; flex-direction: column; justify-content: center; }}
+        .card-front {{ border-left: 8px solid #ffd700; }}
+        .card-front blockquote {{ font-size: 1.2em; font-style: italic; white-space: pre-wrap; }}
+        .card-back p {{ white-space: pre-wrap; }}
+        .card-number {{ position: absolute; top: 8px; right: 12px; font-size: 0.8em; color: #999; }}
+        .card-author {{ position: absolute; bottom: 8px; right: 12px; font-size: 0.8em; color: #777; }}
+        @page {{ size: 5in 3in; margin: 0; }}
+        @media print {{
+            body {{ background: none; }}
+            .card {{ display: block; padding: 0; }}
+            .card-face {{ border: none; page-break-after: always; break-after: page; page-break-inside: avoid; break-inside: avoid; }}
+        }}
+    </style>
+</head>
+<body>{}
+</body>
+</html>"#,
+        escape_html(&title),
+        cards_html
+    );
+
+    Ok(html)
+}
+
+// ============ Mermaid 思维导图导出 ============
+
+const MERMAID_LABEL_MAX_CHARS: usize = 40;
+
+pub fn export_as_mermaid(conn: &Connection, doc_id: &str, filter: &ExportFilter) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+
+    let doc_name = std::path::Path::new(&doc.path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    // 按作者分组，保持首次出现的顺序
+    let mut groups: Vec<(String, Vec<&AnnotationRecord>)> = Vec::new();
+    for anno in &annotations {
+        match groups.iter_mut().find(|(name, _)| name == &anno.user_name) {
+            Some((_, annos)) => annos.push(anno),
+            None => groups.push((anno.user_name.clone(), vec![anno])),
+        }
+    }
+
+    let mut lines = vec![
+        "mindmap".to_string(),
+        format!("  root((\"{}\"))", escape_mermaid(&doc_name)),
+    ];
+    let mut node_index = 0;
+    for (author_index, (author, annos)) in groups.iter().enumerate() {
+        lines.push(format!("    author{}[\"{}\"]", author_index + 1, escape_mermaid(author)));
+        for anno in annos {
+            node_index += 1;
+            let label = match anno.note.as_deref() {
+                Some(note) if !note.trim().is_empty() => note,
+                _ => anno.text.as_str(),
+            };
+            lines.push(format!("      note{}[\"{}\"]", node_index, escape_mermaid(&truncate_chars(label, MERMAID_LABEL_MAX_CHARS))));
+        }
+    }
+
+    Ok(lines.join("\n"))
+}
+
+// Mermaid 标签内的引号、换行等会破坏语法
+fn escape_mermaid(s: &str) -> String {
+    s.replace('"', "#quot;")
+        .replace('`', "'")
+        .replace(['\r', '\n'], " ")
+}
+
+// 按字符截断，超出部分以省略号结尾
+pub fn truncate_chars(s: &str, max_chars: usize) -> String {
+    let s = s.trim();
+    if s.chars().count() <= max_chars {
+        return s.to_string();
+    }
+    let truncated: String = s.chars().take(max_chars).collect();
+    format!("{}…", truncated.trim_end())
+}
+
+// ============ Obsidian 导出 ============
+
+// 正文中可定位且互不重叠的高亮用 ==text== 标记，笔记以 > [!note] callout 插在所在段落之后
+pub fn export_as_obsidian(conn: &Connection, doc_id: &str, content: &str, filter: &ExportFilter) -> Result<String, String> {
+    if get_document_by_id(conn, doc_id)?.is_none() {
+        return Err("Document not found".to_string());
+    }
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+
+    // (插入位置, 同位置排序键, 文本)：先闭合高亮，再插入 callout，最后开启新高亮
+    let mut insertions: Vec<(usize, u8, String)> = Vec::new();
+    let mut unplaced = Vec::new();
+    let mut last_end = 0;
+
+    for (anno, range) in sort_by_position(annotations, content) {
+        let callout = obsidian_callout(&anno);
+        let (start, end) = match range {
+            Some(range) => range,
+            None => {
+                unplaced.push(callout);
+                continue;
+            }
+        };
+
+        // 跨段落或与前一高亮重叠时不做行内标记，仅保留 callout
+        if start >= last_end && !content[start..end].contains("\n\n") {
+            insertions.push((start, 2, "==".to_string()));
+            insertions.push((end, 0, "==".to_string()));
+            last_end = end;
+        }
+
+        let paragraph_end = content[end..].find("\n\n").map(|i| end + i).unwrap_or(content.len());
+        insertions.push((paragraph_end, 1, format!("\n\n{}", callout)));
+    }
+    insertions.sort_by_key(|(pos, order, _)| (*pos, *order));
+
+    let mut output = String::with_capacity(content.len() + insertions.len() * 32);
+    let mut cursor = 0;
+    for (pos, _, text) in insertions {
+        output.push_str(&content[cursor..pos]);
+        output.push_str(&text);
+        cursor = pos;
+    }
+    output.push_str(&content[cursor..]);
+
+    if !unplaced.is_empty() {
+        output.push_str("\n\n---\n\n");
+        output.push_str(&unplaced.join("\n\n"));
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+fn obsidian_callout(anno: &AnnotationRecord) -> String {
+    let body = match anno.note.as_deref() {
+        Some(note) if !note.trim().is_empty() => note.trim(),
+        _ => anno.text.as_str(),
+    };
+
+    let mut callout = format!("> [!note] {}", anno.user_name);
+    for line in body.lines() {
+        callout.push_str("\n> ");
+        callout.push_str(line);
+    }
+    callout
+}
+
+// ============ EPUB 导出 ============
+
+// 正文中脚注标记的占位符（私用区字符），段落渲染完成后替换为链接
+const EPUB_MARKER_START: char = '\u{E000}';
+const EPUB_MARKER_END: char = '\u{E001}';
+
+// 正文按段落渲染，注解作为尾注：在高亮结束处插入上标编号，链接到尾注章节
+pub fn export_as_epub(conn: &Connection, doc_id: &str, content: &str, dest_path: &str, filter: &ExportFilter) -> Result<String, String> {
+    let epub = build_epub(conn, doc_id, content, filter)?;
+    let path = write_export_file(dest_path, &epub)?;
+    Ok(path)
+}
+
+fn build_epub(conn: &Connection, doc_id: &str, content: &str, filter: &ExportFilter) -> Result<Vec<u8>, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+
+    let title = std::path::Path::new(&doc.path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let language = detect_language(content);
+
+    // 先给可定位的注解按原文顺序编号，无法定位的排在最后且不在正文中标记
+    let ordered = sort_by_position(annotations, content);
+    let mut markers: Vec<(usize, usize)> = Vec::new();
+    let mut authors: Vec<String> = Vec::new();
+    let mut endnotes = String::new();
+    for (index, (anno, range)) in ordered.iter().enumerate() {
+        let number = index + 1;
+        if let Some((_, end)) = range {
+            markers.push((*end, number));
+        }
+        if !authors.contains(&anno.user_name) {
+            authors.push(anno.user_name.clone());
+        }
+
+        let back_link = if range.is_some() {
+            format!("<a href=\"content.xhtml#ref-{0}\">{0}</a>", number)
+        } else {
+            number.to_string()
+        };
+        let note = match anno.note.as_deref() {
+            Some(note) if !note.trim().is_empty() => format!(" {}", escape_html(note.trim()).replace('\n', "<br/>")),
+            _ => String::new(),
+        };
+        endnotes.push_str(&format!(
+            "<p class=\"endnote\" id=\"note-{}\">{}. <q>{}</q>{} <span class=\"author\">— {}</span></p>\n",
+            number, back_link, escape_html(&anno.text), note, escape_html(&anno.user_name)
+        ));
+    }
+
+    // 插入占位符后整体转义，再按段落渲染；编号按起点排序，插入需按终点排序
+    markers.sort_by_key(|(end, number)| (*end, *number));
+    let mut marked = String::with_capacity(content.len() + markers.len() * 8);
+    let mut cursor = 0;
+    for (pos, number) in &markers {
+        marked.push_str(&content[cursor..*pos]);
+        marked.push(EPUB_MARKER_START);
+        marked.push_str(&number.to_string());
+        marked.push(EPUB_MARKER_END);
+        cursor = *pos;
+    }
+    marked.push_str(&content[cursor..]);
+
+    let mut body = render_epub_paragraphs(&marked);
+    for (_, number) in &markers {
+        body = body.replace(
+            &format!("{}{}{}", EPUB_MARKER_START, number, EPUB_MARKER_END),
+            &format!("<sup><a id=\"ref-{0}\" href=\"notes.xhtml#note-{0}\">{0}</a></sup>", number),
+        );
+    }
+
+    let content_page = epub_xhtml(&title, &language, &body);
+    let notes_page = epub_xhtml("Notes", &language, &format!("<h1>Notes</h1>\n{}", endnotes));
+
+    let mut builder = EpubBuilder::new(ZipLibrary::new().map_err(|e| e.to_string())?)
+        .map_err(|e| e.to_string())?;
+    builder
+        .metadata("title", title.as_str()).map_err(|e| e.to_string())?
+        .metadata("author", authors.join(", ")).map_err(|e| e.to_string())?
+        .metadata("lang", language.as_str()).map_err(|e| e.to_string())?
+        .metadata("generator", "Annoti").map_err(|e| e.to_string())?;
+    builder
+        .add_content(EpubContent::new("content.xhtml", content_page.as_bytes())
+            .title(title.as_str())
+            .reftype(ReferenceType::Text))
+        .map_err(|e| e.to_string())?
+        .add_content(EpubContent::new("notes.xhtml", notes_page.as_bytes())
+            .title("Notes")
+            .reftype(ReferenceType::Notes))
+        .map_err(|e| e.to_string())?;
+
+    let mut epub = Vec::new();
+    builder.generate(&mut epub).map_err(|e| e.to_string())?;
+    Ok(epub)
+}
+
+// 简化的 Markdown 段落渲染：# 开头的行作为标题，其余按空行分段，段内换行保留
+fn render_epub_paragraphs(markdown: &str) -> String {
+    let mut html = String::new();
+    for block in markdown.split("\n\n") {
+        let block = block.trim_matches('\n');
+        if block.trim().is_empty() {
+            continue;
+        }
+
+        let hashes = block.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&hashes) && block[hashes..].starts_with(' ') && !block.contains('\n') {
+            html.push_str(&format!("<h{0}>{1}</h{0}>\n", hashes, escape_html(block[hashes..].trim())));
+        } else {
+            let lines: Vec<String> = block.lines().map(escape_html).collect();
+            html.push_str(&format!("<p>{}</p>\n", lines.join("<br/>\n")));
+        }
+    }
+    html
+}
+
+fn epub_xhtml(title: &str, language: &str, body: &str) -> String {
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" lang="{0}" xml:lang="{0}">
+<head>
+<meta http-equiv="Content-Type" content="text/html; charset=utf-8"/>
+<title>{1}</title>
+</head>
+<body>
+{2}
+</body>
+</html>
+"#, language, escape_html(title), body)
+}
+
+// ============ 项目 PDF 导出 ============
+
+// A4 页面尺寸与页边距（pt）
+const PDF_PAGE_WIDTH: f32 = 595.0;
+const PDF_PAGE_HEIGHT: f32 = 842.0;
+const PDF_MARGIN: f32 = 56.0;
+const PDF_LEADING: f32 = 1.45;
+const PDF_TITLE_SIZE: f32 = 18.0;
+const PDF_HEADING_SIZE: f32 = 13.0;
+const PDF_BODY_SIZE: f32 = 11.0;
+const PDF_FOOTER_SIZE: f32 = 9.0;
+const PDF_FONT: pdf_writer::Name = pdf_writer::Name(b"F1");
+
+type PdfColor = (u8, u8, u8);
+
+// 一段连续的文字及其高亮底色
+#[derive(Clone, Debug, PartialEq)]
+struct PdfRun {
+    text: String,
+    highlight: Option<PdfColor>,
+}
+
+impl PdfRun {
+    fn plain(text: impl Into<String>) -> Self {
+        PdfRun { text: text.into(), highlight: None }
+    }
+}
+
+// 一个排版块（标题、正文的一行或一条笔记），折行后可能占多行
+struct PdfBlock {
+    size: f32,
+    runs: Vec<PdfRun>,
+}
+
+impl PdfBlock {
+    fn new(size: f32, runs: Vec<PdfRun>) -> Self {
+        PdfBlock { size, runs }
+    }
+
+    fn blank() -> Self {
+        PdfBlock::new(PDF_BODY_SIZE, Vec::new())
+    }
+}
+
+// 折行后的一行
+struct PdfLine {
+    size: f32,
+    runs: Vec<PdfRun>,
+    link: Option<usize>, // 目录行链接到的页码（从 0 开始）
+}
+
+// 将 dir 下已入库的文档按路径顺序合并为一个 PDF：开头为目录，每个文档另起一页，
+// 高亮以注解颜色作底色并标注编号，文档末尾列出对应笔记
+pub fn export_project_pdf(conn: &Connection, dir: &str, dest_path: &str, filter: &ExportFilter) -> Result<String, String> {
+    let root = std::path::Path::new(dir);
+    let mut documents = Vec::new();
+    {
+        let mut stmt = conn.prepare("SELECT id, path FROM documents ORDER BY path")
+            .map_err(|e| e.to_string())?;
+        let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+        while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            let id: String = row.get(0).map_err(|e| e.to_string())?;
+            let path: String = row.get(1).map_err(|e| e.to_string())?;
+            if std::path::Path::new(&path).starts_with(root) {
+                documents.push(id);
+            }
+        }
+    }
+    if documents.is_empty() {
+        return Err(format!("No documents found in {}", dir));
+    }
+
+    let pdf = build_documents_pdf(conn, &documents, filter)?;
+    let path = write_export_file(dest_path, &pdf)?;
+    Ok(path)
+}
+
+// 单文档预览与整个项目共用的 PDF 流程：每个文档排版为独立的若干页，前面加上带链接的目录
+pub fn build_documents_pdf(conn: &Connection, doc_ids: &[String], filter: &ExportFilter) -> Result<Vec<u8>, String> {
+    let mut body_pages: Vec<Vec<PdfLine>> = Vec::new();
+    let mut toc = Vec::new();
+    for doc_id in doc_ids {
+        let doc = get_document_by_id(conn, doc_id)?
+            .ok_or_else(|| "Document not found".to_string())?;
+        let title = std::path::Path::new(&doc.path)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+        let ordered = sort_by_position(annotations, &doc.content);
+
+        toc.push((title.clone(), body_pages.len()));
+        body_pages.extend(paginate_pdf_lines(layout_pdf_blocks(document_pdf_blocks(&title, &doc.content, &ordered))));
+    }
+
+    // 目录每个条目占一行，先按条目数算出目录页数，再换算各文档的起始页
+    let toc_lines = |offset: usize| {
+        let mut lines = layout_pdf_blocks(vec![PdfBlock::new(PDF_TITLE_SIZE, vec![PdfRun::plain("Contents")]), PdfBlock::blank()]);
+        for (index, (title, first_page)) in toc.iter().enumerate() {
+            let page = offset + first_page;
+            lines.push(PdfLine {
+                size: PDF_BODY_SIZE,
+                runs: vec![PdfRun::plain(toc_entry(index + 1, title, page + 1))],
+                link: Some(page),
+            });
+        }
+        lines
+    };
+    let toc_page_count = paginate_pdf_lines(toc_lines(0)).len();
+
+    let pages: Vec<Vec<PdfLine>> = paginate_pdf_lines(toc_lines(toc_page_count)).into_iter().chain(body_pages).collect();
+    Ok(write_pdf(&pages))
+}
+
+// 目录条目：标题过长时截断，页码右对齐
+fn toc_entry(number: usize, title: &str, page: usize) -> String {
+    let max_width = PDF_PAGE_WIDTH - 2.0 * PDF_MARGIN;
+    let page = page.to_string();
+    let mut label = format!("{}. {}", number, title);
+    let reserved = pdf_text_width(&page, PDF_BODY_SIZE) + 4.0 * pdf_text_width(".", PDF_BODY_SIZE);
+    while pdf_text_width(&label, PDF_BODY_SIZE) > max_width - reserved {
+        label.pop();
+    }
+    let dots = ((max_width - pdf_text_width(&label, PDF_BODY_SIZE) - pdf_text_width(&page, PDF_BODY_SIZE))
+        / pdf_text_width(".", PDF_BODY_SIZE)) as usize;
+    format!("{}{}{}", label, ".".repeat(dots.saturating_sub(1)), page)
+}
+
+// 文档的排版块：标题、逐行正文（# 标题行使用较大字号）与笔记列表
+// 高亮底色取便签的标题栏颜色；与前一处高亮重叠的注解只标注编号
+fn document_pdf_blocks(title: &str, content: &str, ordered: &[(AnnotationRecord, Option<(usize, usize)>)]) -> Vec<PdfBlock> {
+    let mut highlights: Vec<(usize, usize, PdfColor)> = Vec::new();
+    let mut markers: Vec<(usize, usize)> = Vec::new();
+    let mut notes = Vec::new();
+    let mut covered_until = 0;
+    for (index, (anno, range)) in ordered.iter().enumerate() {
+        let number = index + 1;
+        let color = note_tint(&anno.highlight_color)
+            .and_then(|(_, header, _)| parse_hex_color(&header))
+            .unwrap_or((255, 236, 128));
+        if let Some((start, end)) = *range {
+            if start >= covered_until {
+                highlights.push((start, end, color));
+                covered_until = end;
+            }
+            markers.push((end, number));
+        }
+
+        let note = match anno.note.as_deref() {
+            Some(note) if !note.trim().is_empty() => format!(" {}", collapse_whitespace(note)),
+            _ => String::new(),
+        };
+        notes.push(PdfBlock::new(PDF_BODY_SIZE, vec![
+            PdfRun { text: format!("[{}]", number), highlight: Some(color) },
+            PdfRun::plain(format!(" \u{201C}{}\u{201D}{} \u{2014} {}", collapse_whitespace(&anno.text), note, anno.user_name)),
+        ]));
+    }
+    markers.sort();
+
+    let mut blocks = vec![PdfBlock::new(PDF_TITLE_SIZE, vec![PdfRun::plain(title)]), PdfBlock::blank()];
+    let mut line_start = 0;
+    for raw in content.split('\n') {
+        let line = raw.trim_end_matches('\r');
+        let line_end = line_start + line.len();
+        let hashes = line.chars().take_while(|&c| c == '#').count();
+        let (text_start, size) = if (1..=6).contains(&hashes) && line[hashes..].starts_with(' ') {
+            (line_start + hashes + 1, PDF_HEADING_SIZE)
+        } else {
+            (line_start, PDF_BODY_SIZE)
+        };
+
+        // 在高亮起止与编号位置切分
+        let mut cuts: Vec<usize> = highlights.iter()
+            .flat_map(|(start, end, _)| [*start, *end])
+            .chain(markers.iter().map(|(pos, _)| *pos))
+            .filter(|pos| *pos > text_start && *pos < line_end)
+            .collect();
+        cuts.push(line_end);
+        cuts.sort();
+        cuts.dedup();
+
+        let mut runs = Vec::new();
+        let mut cursor = text_start;
+        for cut in cuts {
+            if cut > cursor {
+                let highlight = highlights.iter()
+                    .find(|(start, end, _)| *start <= cursor && cursor < *end)
+                    .map(|(_, _, color)| *color);
+                runs.push(PdfRun { text: content[cursor..cut].replace('\t', "    "), highlight });
+                cursor = cut;
+            }
+            for (_, number) in markers.iter().filter(|(pos, _)| *pos == cut) {
+                runs.push(PdfRun::plain(format!("[{}]", number)));
+            }
+        }
+        blocks.push(PdfBlock::new(size, runs));
+        line_start += raw.len() + 1;
+    }
+
+    if !notes.is_empty() {
+        blocks.push(PdfBlock::blank());
+        blocks.push(PdfBlock::new(PDF_HEADING_SIZE, vec![PdfRun::plain("Notes")]));
+        blocks.extend(notes);
+    }
+    blocks
+}
+
+// 按版心宽度折行：拉丁字符优先在空格处断开，CJK 等全角字符逐字断开；折行后相邻同色的字合并为一段
+fn layout_pdf_blocks(blocks: Vec<PdfBlock>) -> Vec<PdfLine> {
+    let max_width = PDF_PAGE_WIDTH - 2.0 * PDF_MARGIN;
+    let mut lines = Vec::new();
+    for block in blocks {
+        let chars: Vec<(char, Option<PdfColor>)> = block.runs.iter()
+            .flat_map(|run| run.text.chars().map(move |c| (c, run.highlight)))
+            .collect();
+
+        let mut rows: Vec<Vec<(char, Option<PdfColor>)>> = vec![Vec::new()];
+        let mut width = 0.0;
+        for (c, highlight) in chars {
+            let char_width = pdf_char_width(c) * block.size;
+            let row = rows.last_mut().expect("rows is never empty");
+            if width + char_width > max_width && !row.is_empty() {
+                let carry = match row.iter().rposition(|(c, _)| *c == ' ') {
+                    Some(pos) if pos > 0 => row.split_off(pos + 1),
+                    _ => Vec::new(),
+                };
+                width = carry.iter().map(|(c, _)| pdf_char_width(*c) * block.size).sum();
+                rows.push(carry);
+            }
+            if let Some(row) = rows.last_mut() {
+                row.push((c, highlight));
+            }
+            width += char_width;
+        }
+
+        for row in rows {
+            let mut runs: Vec<PdfRun> = Vec::new();
+            for (c, highlight) in row {
+                match runs.last_mut() {
+                    Some(run) if run.highlight == highlight => run.text.push(c),
+                    _ => runs.push(PdfRun { text: c.to_string(), highlight }),
+                }
+            }
+            lines.push(PdfLine { size: block.size, runs, link: None });
+        }
+    }
+    lines
+}
+
+// 字宽（em）：与 CID 字体的 /W 设置一致，ASCII 为半角，其余按全角计算
+fn pdf_char_width(c: char) -> f32 {
+    if c.is_ascii() { 0.5 } else { 1.0 }
+}
+
+fn pdf_text_width(text: &str, size: f32) -> f32 {
+    text.chars().map(|c| pdf_char_width(c) * size).sum()
+}
+
+fn paginate_pdf_lines(lines: Vec<PdfLine>) -> Vec<Vec<PdfLine>> {
+    let usable = PDF_PAGE_HEIGHT - 2.0 * PDF_MARGIN;
+    let mut pages: Vec<Vec<PdfLine>> = vec![Vec::new()];
+    let mut used = 0.0;
+    for line in lines {
+        let height = line.size * PDF_LEADING;
+        if used + height > usable && pages.last().is_some_and(|page| !page.is_empty()) {
+            pages.push(Vec::new());
+            used = 0.0;
+        }
+        used += height;
+        if let Some(page) = pages.last_mut() {
+            page.push(line);
+        }
+    }
+    pages
+}
+
+// 文本以 UTF-16BE 写入，配合 UniGB-UTF16-H 编码
+fn pdf_utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect()
+}
+
+// 生成 PDF。字体使用阅读器内置的 Adobe-GB1 字体 STSong-Light，无需嵌入即可显示中英文
+fn write_pdf(pages: &[Vec<PdfLine>]) -> Vec<u8> {
+    use pdf_writer::types::{ActionType, AnnotationType, CidFontType, FontFlags, SystemInfo};
+    use pdf_writer::{Content, Name, Pdf, Rect, Ref, Str};
+
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let font_id = Ref::new(3);
+    let cid_font_id = Ref::new(4);
+    let descriptor_id = Ref::new(5);
+    let page_ids: Vec<Ref> = (0..pages.len()).map(|i| Ref::new(6 + 2 * i as i32)).collect();
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id).kids(page_ids.iter().copied()).count(pages.len() as i32);
+    pdf.type0_font(font_id)
+        .base_font(Name(b"STSong-Light"))
+        .encoding_predefined(Name(b"UniGB-UTF16-H"))
+        .descendant_font(cid_font_id);
+    pdf.cid_font(cid_font_id)
+        .subtype(CidFontType::Type0)
+        .base_font(Name(b"STSong-Light"))
+        .system_info(SystemInfo { registry: Str(b"Adobe"), ordering: Str(b"GB1"), supplement: 4 })
+        .font_descriptor(descriptor_id)
+        .default_width(1000.0)
+        .widths()
+        .same(1, 95, 500.0);
+    pdf.font_descriptor(descriptor_id)
+        .name(Name(b"STSong-Light"))
+        .flags(FontFlags::SYMBOLIC | FontFlags::SERIF)
+        .bbox(Rect::new(-25.0, -254.0, 1000.0, 880.0))
+        .italic_angle(0.0)
+        .ascent(880.0)
+        .descent(-120.0)
+        .cap_height(880.0)
+        .stem_v(93.0);
+
+    for (index, page) in pages.iter().enumerate() {
+        let page_id = page_ids[index];
+        let content_id = Ref::new(page_id.get() + 1);
+        let mut content = Content::new();
+        let mut links = Vec::new();
+        let mut used = 0.0;
+        for line in page {
+            used += line.size * PDF_LEADING;
+            let baseline = PDF_PAGE_HEIGHT - PDF_MARGIN - used + line.size * (PDF_LEADING - 1.0);
+            let mut x = PDF_MARGIN;
+            for run in &line.runs {
+                let width = pdf_text_width(&run.text, line.size);
+                if let Some((r, g, b)) = run.highlight {
+                    content.set_fill_rgb(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+                    content.rect(x, baseline - line.size * 0.25, width, line.size * 1.2);
+                    content.fill_nonzero();
+                }
+                content.set_fill_rgb(0.13, 0.13, 0.13);
+                content.begin_text();
+                content.set_font(PDF_FONT, line.size);
+                content.next_line(x, baseline);
+                content.show(Str(&pdf_utf16(&run.text)));
+                content.end_text();
+                x += width;
+            }
+            if let Some(target) = line.link {
+                let rect = Rect::new(PDF_MARGIN, baseline - line.size * 0.25, PDF_PAGE_WIDTH - PDF_MARGIN, baseline + line.size);
+                links.push((rect, page_ids[target]));
+            }
+        }
+
+        // 页脚居中页码
+        let number = (index + 1).to_string();
+        content.set_fill_rgb(0.4, 0.4, 0.4);
+        content.begin_text();
+        content.set_font(PDF_FONT, PDF_FOOTER_SIZE);
+        content.next_line((PDF_PAGE_WIDTH - pdf_text_width(&number, PDF_FOOTER_SIZE)) / 2.0, PDF_MARGIN / 2.0);
+        content.show(Str(&pdf_utf16(&number)));
+        content.end_text();
+        pdf.stream(content_id, &content.finish());
+
+        let mut page_writer = pdf.page(page_id);
+        page_writer
+            .parent(page_tree_id)
+            .media_box(Rect::new(0.0, 0.0, PDF_PAGE_WIDTH, PDF_PAGE_HEIGHT))
+            .contents(content_id);
+        page_writer.resources().fonts().pair(PDF_FONT, font_id);
+        let mut annotations = page_writer.annotations();
+        for (rect, target) in links {
+            let mut link = annotations.push();
+            link.subtype(AnnotationType::Link).rect(rect).border(0.0, 0.0, 0.0, None);
+            link.action()
+                .action_type(ActionType::GoTo)
+                .destination()
+                .page(target)
+                .xyz(0.0, PDF_PAGE_HEIGHT, None);
+        }
+    }
+
+    pdf.finish()
+}
+
+// ============ 模板导出 ============
+
+// 模板中 {{header}}...{{/header}} 与 {{footer}}...{{/footer}} 只渲染一次，其余部分对每条注解渲染后换行拼接
+// 注解占位符：{index} {text} {note} {author} {date} {color}；页眉页脚占位符：{document} {count} {date}
+pub fn export_with_template(conn: &Connection, doc_id: &str, template: &str, filter: &ExportFilter) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+
+    let (header, rest) = take_template_section(template, "header")?;
+    let (footer, body) = take_template_section(&rest, "footer")?;
+    let body = body.trim_matches('\n');
+
+    let format_date = |ms: i64| Local.timestamp_millis_opt(ms)
+        .single()
+        .map(|dt| dt.format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+    let document = std::path::Path::new(&doc.path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let summary = [
+        ("document", document),
+        ("count", annotations.len().to_string()),
+        ("date", format_date(Utc::now().timestamp_millis())),
+    ];
+
+    let mut sections = Vec::new();
+    if let Some(header) = header {
+        sections.push(render_template(header.trim_matches('\n'), &summary)?);
+    }
+    for (index, (anno, _)) in sort_by_position(annotations, &doc.content).into_iter().enumerate() {
+        let values = [
+            ("index", (index + 1).to_string()),
+            ("text", anno.text.clone()),
+            ("note", anno.note.clone().unwrap_or_default()),
+            ("author", anno.user_name.clone()),
+            ("date", format_date(anno.created_at)),
+            ("color", anno.highlight_color.clone()),
+        ];
+        sections.push(render_template(body, &values)?);
+    }
+    if let Some(footer) = footer {
+        sections.push(render_template(footer.trim_matches('\n'), &summary)?);
+    }
+
+    let mut output = sections.join("\n");
+    output.push('\n');
+    Ok(output)
+}
+
+// 取出 {{name}}...{{/name}} 区段，返回 (区段内容, 去掉区段后的模板)
+fn take_template_section(template: &str, name: &str) -> Result<(Option<String>, String), String> {
+    let open = format!("{{{{{}}}}}", name);
+    let close = format!("{{{{/{}}}}}", name);
+    let start = match template.find(&open) {
+        Some(start) => start,
+        None => return Ok((None, template.to_string())),
+    };
+    let inner_start = start + open.len();
+    let inner_end = template[inner_start..].find(&close)
+        .map(|i| inner_start + i)
+        .ok_or_else(|| format!("Unclosed template section: {}", open))?;
+
+    let section = template[inner_start..inner_end].to_string();
+    let rest = format!("{}{}", &template[..start], &template[inner_end + close.len()..]);
+    Ok((Some(section), rest))
+}
+
+// 替换 {name} 占位符；未知占位符返回错误，不构成占位符的花括号原样保留
+fn render_template(template: &str, values: &[(&str, String)]) -> Result<String, String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        output.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let name_len = after.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+        if name_len > 0 && after[name_len..].starts_with('}') {
+            let name = &after[..name_len];
+            let value = values.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value)
+                .ok_or_else(|| format!("Unknown template placeholder: {{{}}}", name))?;
+            output.push_str(value);
+            rest = &after[name_len + 1..];
+        } else {
+            output.push('{');
+            rest = after;
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+// ============ 按标签导出 ============
+
+const UNTAGGED: &str = "untagged";
+
+// 每个标签一段 Markdown，多标签注解在各标签下重复出现，无标签的归入 "untagged"
+pub fn export_by_tag(conn: &Connection, doc_id: &str, filter: &ExportFilter) -> Result<std::collections::HashMap<String, String>, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+    let tags = get_annotation_tags(conn, doc_id)?;
+
+    let mut sections: std::collections::HashMap<String, String> = std::collections::HashMap::new();
+    for (anno, _) in sort_by_position(annotations, &doc.content) {
+        let mut entry = format!("> {}\n", anno.text.trim().replace('\n', "\n> "));
+        if let Some(note) = anno.note.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+            entry.push_str(&format!("\n{}\n", note));
+        }
+        entry.push_str(&format!("\n— {}\n", anno.user_name));
+
+        let anno_tags = tags.get(&anno.id).filter(|t| !t.is_empty()).cloned()
+            .unwrap_or_else(|| vec![UNTAGGED.to_string()]);
+        for tag in anno_tags {
+            let section = sections.entry(tag.clone())
+                .or_insert_with(|| format!("## {}\n", tag));
+            section.push('\n');
+            section.push_str(&entry);
+        }
+    }
+
+    Ok(sections)
+}
+
+// ============ 看板导出 ============
+
+const KANBAN_BACKLOG: &str = "Backlog";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KanbanCard {
+    pub id: String,
+    pub text: String,
+    pub note: Option<String>,
+    pub author: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KanbanColumn {
+    pub title: String,
+    pub cards: Vec<KanbanCard>,
+}
+
+// 每个标签一列（按名称排序），无标签的注解放在最前面的 Backlog 列；多标签的注解在每个标签列中各出现一次
+pub fn export_as_kanban(conn: &Connection, doc_id: &str, filter: &ExportFilter) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+    let tags = get_annotation_tags(conn, doc_id)?;
+
+    let mut backlog = Vec::new();
+    let mut columns: std::collections::BTreeMap<String, Vec<KanbanCard>> = std::collections::BTreeMap::new();
+    for (anno, _) in sort_by_position(annotations, &doc.content) {
+        let card = KanbanCard {
+            id: anno.id.clone(),
+            text: anno.text.clone(),
+            note: anno.note.as_deref().map(str::trim).filter(|n| !n.is_empty()).map(|n| n.to_string()),
+            author: anno.user_name.clone(),
+        };
+        match tags.get(&anno.id).filter(|t| !t.is_empty()) {
+            Some(anno_tags) => {
+                for tag in anno_tags {
+                    columns.entry(tag.clone()).or_default().push(card.clone());
+                }
+            }
+            None => backlog.push(card),
+        }
+    }
+
+    let board: Vec<KanbanColumn> = std::iter::once(KanbanColumn { title: KANBAN_BACKLOG.to_string(), cards: backlog })
+        .chain(columns.into_iter().map(|(title, cards)| KanbanColumn { title, cards }))
+        .collect();
+    serde_json::to_string_pretty(&board).map_err(|e| e.to_string())
+}
+
+// ============ 定位笔记导出（类 SRT） ============
+
+pub fn export_as_positioned_notes(conn: &Connection, doc_id: &str, filter: &ExportFilter) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+
+    let mut blocks = Vec::new();
+    for (index, (anno, range)) in sort_by_position(annotations, &doc.content).into_iter().enumerate() {
+        let position = match range {
+            Some((start, end)) => format!("{} --> {}", start, end),
+            None => "? --> ?".to_string(),
+        };
+        let body = match anno.note.as_deref() {
+            Some(note) if !note.trim().is_empty() => note.trim().to_string(),
+            _ => format!("> {}", anno.text),
+        };
+        blocks.push(format!("{}\n{}\n{}\n", index + 1, position, body));
+    }
+
+    Ok(blocks.join("\n"))
+}
+
+// ============ 热力数据导出 ============
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HeatPoint {
+    pub offset: usize,
+    pub length: usize,
+    pub color: String,
+    pub weight: f64,
+}
+
+// 每条可定位的注解一个点：offset/length 为原文字节区间，weight 为 1 加上每百字笔记计 1
+pub fn export_heatmap_json(conn: &Connection, doc_id: &str, filter: &ExportFilter) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+
+    let points: Vec<HeatPoint> = sort_by_position(annotations, &doc.content)
+        .into_iter()
+        .filter_map(|(anno, range)| {
+            let (start, end) = range?;
+            let note_chars = anno.note.as_deref().map(|n| n.trim().chars().count()).unwrap_or(0);
+            Some(HeatPoint {
+                offset: start,
+                length: end - start,
+                color: anno.highlight_color,
+                weight: 1.0 + note_chars as f64 / 100.0,
+            })
+        })
+        .collect();
+
+    serde_json::to_string(&points).map_err(|e| e.to_string())
+}
+
+// ============ BibTeX 导出 ============
+
+// 每条注解生成一个 @misc 条目，键为文档名 slug 加注解序号（按原文位置排序，从 1 开始）
+pub fn export_as_bibtex(conn: &Connection, doc_id: &str, filter: &ExportFilter) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+
+    let file_name = std::path::Path::new(&doc.path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let stem = std::path::Path::new(&file_name)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let slug = slugify(&stem);
+    let slug = if slug.is_empty() { "document".to_string() } else { slug };
+
+    let mut entries = Vec::new();
+    for (index, (anno, _)) in sort_by_position(annotations, &doc.content).into_iter().enumerate() {
+        let year = Utc.timestamp_millis_opt(anno.created_at)
+            .single()
+            .map(|dt| dt.format("%Y").to_string())
+            .unwrap_or_default();
+
+        let mut fields = vec![
+            ("title", format!("Annotation {} on {}", index + 1, file_name)),
+            ("author", anno.user_name.clone()),
+            ("year", year),
+            ("annotation", anno.text.clone()),
+        ];
+        if let Some(note) = anno.note.as_deref().filter(|n| !n.trim().is_empty()) {
+            fields.push(("note", note.to_string()));
+        }
+
+        let body: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("  {} = {{{}}}", key, escape_bibtex(value)))
+            .collect();
+        entries.push(format!("@misc{{{}-{},\n{}\n}}", slug, index + 1, body.join(",\n")));
+    }
+
+    let mut output = entries.join("\n\n");
+    output.push('\n');
+    Ok(output)
+}
+
+// 转义 BibTeX 特殊字符，换行折叠为空格
+fn escape_bibtex(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '{' | '}' | '&' | '%' | '$' | '#' | '_' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '\r' => {}
+            '\n' => out.push(' '),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// ============ 锚点描述导出 ============
+
+// 与内部 anchor_data 格式无关的锚点描述，供外部工具使用；位置均以字符（Unicode 标量）计，行列从 1 开始
+// 无法在当前内容中定位的注解，位置与上下文字段为 null
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AnchorDescriptor {
+    pub id: String,
+    pub exact: String,
+    pub start: Option<usize>,
+    pub end: Option<usize>,
+    pub start_line: Option<usize>,
+    pub start_column: Option<usize>,
+    pub end_line: Option<usize>,
+    pub end_column: Option<usize>,
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AnchorExport {
+    pub version: String,
+    pub document: String,
+    pub checksum: String,
+    pub offset_unit: String,
+    pub anchors: Vec<AnchorDescriptor>,
+}
+
+pub fn export_anchors(conn: &Connection, doc_id: &str, filter: &ExportFilter) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+
+    // 字节位置 -> (字符位置, 行, 列)
+    let position = |byte: usize| -> (usize, usize, usize) {
+        let before = &doc.content[..byte];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        (
+            before.chars().count(),
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    };
+
+    let mut anchors = Vec::new();
+    for (anno, range) in sort_by_position(annotations, &doc.content) {
+        let mut descriptor = AnchorDescriptor {
+            id: anno.id.clone(),
+            exact: anno.text.clone(),
+            start: None,
+            end: None,
+            start_line: None,
+            start_column: None,
+            end_line: None,
+            end_column: None,
+            prefix: None,
+            suffix: None,
+        };
+        if let Some((start, end)) = range {
+            let (start_char, start_line, start_column) = position(start);
+            let (end_char, end_line, end_column) = position(end);
+            descriptor.start = Some(start_char);
+            descriptor.end = Some(end_char);
+            descriptor.start_line = Some(start_line);
+            descriptor.start_column = Some(start_column);
+            descriptor.end_line = Some(end_line);
+            descriptor.end_column = Some(end_column);
+            if let Some(context) = with_anchor_context(vec![anno], &doc.content).pop().and_then(|a| a.context) {
+                descriptor.prefix = Some(context.prefix);
+                descriptor.suffix = Some(context.suffix);
+            }
+        }
+        anchors.push(descriptor);
+    }
+
+    let export = AnchorExport {
+        version: "1.0".to_string(),
+        document: std::path::Path::new(&doc.path)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        checksum: doc.checksum.clone(),
+        offset_unit: "char".to_string(),
+        anchors,
+    };
+    serde_json::to_string_pretty(&export).map_err(|e| e.to_string())
+}
+
+// ============ LLM 导出 ============
+
+// 引文默认截断长度（字符数）
+pub const DEFAULT_LLM_QUOTE_LIMIT: usize = 500;
+
+// 面向大模型的紧凑文本：每条注解只保留引文、笔记、作者、日期与标签，不含 ID 和位置；条目之间以 --- 分隔
+pub fn export_for_llm(conn: &Connection, doc_id: &str, max_quote_chars: usize, filter: &ExportFilter) -> Result<String, String> {
+    let doc = get_document_by_id(conn, doc_id)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+    let tags = get_annotation_tags(conn, doc_id)?;
+
+    let file_name = std::path::Path::new(&doc.path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    let mut output = format!("DOCUMENT: {}\nANNOTATIONS: {}\n", file_name, annotations.len());
+    for (index, (anno, _)) in sort_by_position(annotations, &doc.content).into_iter().enumerate() {
+        let date = Utc.timestamp_millis_opt(anno.created_at)
+            .single()
+            .map(|dt| dt.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        let anno_tags = tags.get(&anno.id).cloned().unwrap_or_default();
+
+        output.push_str("---\n");
+        output.push_str(&format!("[{}] {}, {}", index + 1, anno.user_name, date));
+        if !anno_tags.is_empty() {
+            output.push_str(&format!(" #{}", anno_tags.join(" #")));
+        }
+        output.push('\n');
+        output.push_str(&format!("QUOTE: {}\n", truncate_chars(&collapse_whitespace(&anno.text), max_quote_chars)));
+        if let Some(note) = anno.note.as_deref().map(collapse_whitespace).filter(|n| !n.is_empty()) {
+            output.push_str(&format!("NOTE: {}\n", note));
+        }
+    }
+    Ok(output)
+}
+
+// 连续空白（含换行）折叠为单个空格
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// ============ SQLite 导出 ============
+
+// 导出为独立的 SQLite 文件：单张反规范化的 annotations 表，附带文档路径与用户名，便于外部分析
+pub fn export_annotations_sqlite(conn: &Connection, dest_path: &str) -> Result<String, String> {
+    let dest = resolve_export_path(dest_path)?;
+    if dest == get_db_path() {
+        return Err("Destination must not be the application database".to_string());
+    }
+
+    // 先写入临时文件，成功后再替换目标，失败时不破坏已有的导出
+    let mut temp_name = dest.file_name()
+        .ok_or_else(|| format!("Invalid export path: {}", dest_path))?
+        .to_os_string();
+    temp_name.push(".tmp");
+    let temp = dest.with_file_name(temp_name);
+    if temp.exists() {
+        fs::remove_file(&temp).map_err(|e| e.to_string())?;
+    }
+
+    let written = Connection::open(&temp)
+        .map_err(|e| e.to_string())
+        .and_then(|out| write_sqlite_export(conn, &out));
+    if let Err(e) = written.and_then(|_| fs::rename(&temp, &dest).map_err(|e| e.to_string())) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    Ok(dest.to_string_lossy().to_string())
+}
+
+fn write_sqlite_export(conn: &Connection, out: &Connection) -> Result<(), String> {
+    out.execute_batch(r#"
+        CREATE TABLE annotations (
+            id TEXT PRIMARY KEY,
+            document_id TEXT NOT NULL,
+            document_path TEXT,
+            user_id TEXT NOT NULL,
+            user_name TEXT NOT NULL,
+            text TEXT NOT NULL,
+            note TEXT,
+            highlight_color TEXT,
+            highlight_type TEXT,
+            anchor_data TEXT,
+            comment_count INTEGER,
+            created_at INTEGER,
+            updated_at INTEGER
+        );
+        CREATE INDEX idx_annotations_document_path ON annotations(document_path);
+    "#).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn.prepare("
+        SELECT a.id, a.document_id, d.path, a.user_id, COALESCE(u.name, a.user_name), a.text, a.note,
+               a.highlight_color, a.highlight_type, a.anchor_data,
+               (SELECT COUNT(*) FROM comments c WHERE c.annotation_id = a.id),
+               a.created_at, a.updated_at
+        FROM annotations a
+        LEFT JOIN documents d ON d.id = a.document_id
+        LEFT JOIN users u ON u.id = a.user_id
+        ORDER BY d.path, a.created_at
+    ").map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+
+    let tx = out.unchecked_transaction().map_err(|e| e.to_string())?;
+    {
+        let mut insert = tx.prepare("
+            INSERT INTO annotations (id, document_id, document_path, user_id, user_name, text, note,
+                                     highlight_color, highlight_type, anchor_data, comment_count, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ").map_err(|e| e.to_string())?;
+        while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            insert.execute(params![
+                row.get::<_, String>(0).map_err(|e| e.to_string())?,
+                row.get::<_, String>(1).map_err(|e| e.to_string())?,
+                row.get::<_, Option<String>>(2).map_err(|e| e.to_string())?,
+                row.get::<_, String>(3).map_err(|e| e.to_string())?,
+                row.get::<_, String>(4).map_err(|e| e.to_string())?,
+                row.get::<_, String>(5).map_err(|e| e.to_string())?,
+                row.get::<_, Option<String>>(6).map_err(|e| e.to_string())?,
+                row.get::<_, String>(7).map_err(|e| e.to_string())?,
+                row.get::<_, String>(8).map_err(|e| e.to_string())?,
+                row.get::<_, String>(9).map_err(|e| e.to_string())?,
+                row.get::<_, i64>(10).map_err(|e| e.to_string())?,
+                row.get::<_, i64>(11).map_err(|e| e.to_string())?,
+                row.get::<_, i64>(12).map_err(|e| e.to_string())?,
+            ]).map_err(|e| e.to_string())?;
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())
+}
+
+// ============ 便签位置导出（GeoJSON） ============
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NoteFeatureCollection {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub features: Vec<NoteFeature>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NoteFeature {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub geometry: NotePoint,
+    pub properties: NoteProperties,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NotePoint {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub coordinates: [f64; 2],
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NoteProperties {
+    pub id: String,
+    pub author: String,
+    pub note: String,
+    pub text: String,
+    pub color: String,
+    pub width: f64,
+    pub height: f64,
+}
+
+// 每条有笔记的注解一个点，坐标为便签位置（像素），与锚点偏移无关
+pub fn export_note_positions(conn: &Connection, doc_id: &str, filter: &ExportFilter) -> Result<String, String> {
+    if get_document_by_id(conn, doc_id)?.is_none() {
+        return Err("Document not found".to_string());
+    }
+    let mut annotations = filter.apply(get_annotations_by_doc(conn, doc_id)?)?;
+    annotations.sort_by_key(|a| a.created_at);
+
+    let features = annotations
+        .into_iter()
+        .filter_map(|anno| {
+            let note = anno.note.as_deref().map(str::trim).filter(|n| !n.is_empty())?.to_string();
+            Some(NoteFeature {
+                kind: "Feature".to_string(),
+                geometry: NotePoint {
+                    kind: "Point".to_string(),
+                    coordinates: [anno.note_position_x, anno.note_position_y],
+                },
+                properties: NoteProperties {
+                    id: anno.id,
+                    author: anno.user_name,
+                    note,
+                    text: anno.text,
+                    color: anno.highlight_color,
+                    width: anno.note_width,
+                    height: anno.note_height,
+                },
+            })
+        })
+        .collect();
+
+    let collection = NoteFeatureCollection {
+        kind: "FeatureCollection".to_string(),
+        features,
+    };
+    serde_json::to_string_pretty(&collection).map_err(|e| e.to_string())
+}
+
+// ============ 统计 ============
+
+// 高亮文本词频：英文等按空白/标点分词并转小写，CJK 按单字计数
+pub fn get_highlight_word_frequencies(conn: &Connection, doc_id: Option<&str>, top_n: usize) -> Result<Vec<(String, usize)>, String> {
+    let texts: Vec<String> = match doc_id {
+        Some(id) => get_annotations_by_doc(conn, id)?
+            .into_iter()
+            .map(|a| a.text)
+            .collect(),
+        None => {
+            let mut stmt = conn.prepare("SELECT text FROM annotations")
+                .map_err(|e| e.to_string())?;
+            let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+            let mut texts = Vec::new();
+            while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+                texts.push(row.get(0).map_err(|e| e.to_string())?);
+            }
+            texts
+        }
+    };
+
+    let mut counts: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    for text in &texts {
+        for token in tokenize_words(text) {
+            *counts.entry(token).or_insert(0) += 1;
+        }
+    }
+
+    let mut frequencies: Vec<(String, usize)> = counts.into_iter().collect();
+    // 次数降序，次数相同时按字典序保证结果稳定
+    frequencies.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    frequencies.truncate(top_n);
+    Ok(frequencies)
+}
+
+// 高亮覆盖率：合并所有可定位注解的区间后，按字符数计算占全文的比例
+pub fn get_annotation_coverage(conn: &Connection, doc_id: &str, content: &str) -> Result<f64, String> {
+    let total = content.chars().count();
+    if total == 0 {
+        return Ok(0.0);
+    }
+
+    let mut ranges: Vec<(usize, usize)> = get_annotations_by_doc(conn, doc_id)?
+        .iter()
+        .filter_map(|anno| locate_annotation(anno, content))
+        .collect();
+    ranges.sort_unstable();
+
+    let mut covered = 0;
+    let mut current: Option<(usize, usize)> = None;
+    for (start, end) in ranges {
+        current = match current {
+            Some((s, e)) if start <= e => Some((s, e.max(end))),
+            Some((s, e)) => {
+                covered += content[s..e].chars().count();
+                Some((start, end))
+            }
+            None => Some((start, end)),
+        };
+    }
+    if let Some((s, e)) = current {
+        covered += content[s..e].chars().count();
+    }
+
+    Ok(covered as f64 / total as f64)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DayActivity {
+    pub date: String, // 本地时区日期 YYYY-MM-DD
+    pub created: usize,
+    pub updated: usize,
+}
+
+// 按本地日期统计区间内新建与修改的注解数量，无活动的日期补 0
+pub fn get_activity_timeline(conn: &Connection, from_ms: i64, to_ms: i64) -> Result<Vec<DayActivity>, String> {
+    if from_ms > to_ms {
+        return Err("from_ms must not be after to_ms".to_string());
+    }
+    let local_date = |ms: i64| Local.timestamp_millis_opt(ms).single().map(|dt| dt.date_naive());
+    let first = local_date(from_ms).ok_or_else(|| format!("Invalid timestamp: {}", from_ms))?;
+    let last = local_date(to_ms).ok_or_else(|| format!("Invalid timestamp: {}", to_ms))?;
+
+    let mut days: std::collections::BTreeMap<NaiveDate, (usize, usize)> = std::collections::BTreeMap::new();
+    let mut day = first;
+    while day <= last {
+        days.insert(day, (0, 0));
+        day = match day.succ_opt() {
+            Some(next) => next,
+            None => break,
+        };
+    }
+
+    let mut stmt = conn.prepare("
+        SELECT created_at, updated_at FROM annotations
+        WHERE created_at BETWEEN ?1 AND ?2 OR updated_at BETWEEN ?1 AND ?2
+    ").map_err(|e| e.to_string())?;
+    let mut rows = stmt.query(params![from_ms, to_ms]).map_err(|e| e.to_string())?;
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        let created_at: i64 = row.get(0).map_err(|e| e.to_string())?;
+        let updated_at: i64 = row.get(1).map_err(|e| e.to_string())?;
+
+        if (from_ms..=to_ms).contains(&created_at) {
+            if let Some(entry) = local_date(created_at).and_then(|d| days.get_mut(&d)) {
+                entry.0 += 1;
+            }
+        }
+        // 创建时写入的 updated_at 不算作修改
+        if updated_at > created_at && (from_ms..=to_ms).contains(&updated_at) {
+            if let Some(entry) = local_date(updated_at).and_then(|d| days.get_mut(&d)) {
+                entry.1 += 1;
+            }
+        }
+    }
+
+    Ok(days
+        .into_iter()
+        .map(|(date, (created, updated))| DayActivity {
+            date: date.format("%Y-%m-%d").to_string(),
+            created,
+            updated,
+        })
+        .collect())
+}
+
+// 文档相似度：基于词 shingle 的 Jaccard 系数，范围 0.0–1.0
+pub fn document_similarity(conn: &Connection, path_a: &str, path_b: &str) -> Result<f64, String> {
+    let doc_a = get_document_by_path(conn, path_a)?
+        .ok_or_else(|| format!("Document not found: {}", path_a))?;
+    let doc_b = get_document_by_path(conn, path_b)?
+        .ok_or_else(|| format!("Document not found: {}", path_b))?;
+
+    Ok(jaccard_similarity(&shingles(&doc_a.content), &shingles(&doc_b.content)))
+}
+
+const SHINGLE_SIZE: usize = 3;
+
+fn shingles(text: &str) -> std::collections::HashSet<Vec<String>> {
+    let tokens = tokenize_words(text);
+    if tokens.len() <= SHINGLE_SIZE {
+        // 过短的文本整体作为一个 shingle
+        return if tokens.is_empty() {
+            std::collections::HashSet::new()
+        } else {
+            std::iter::once(tokens).collect()
+        };
+    }
+    tokens.windows(SHINGLE_SIZE).map(|w| w.to_vec()).collect()
+}
+
+fn jaccard_similarity(a: &std::collections::HashSet<Vec<String>>, b: &std::collections::HashSet<Vec<String>>) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let intersection = a.intersection(b).count();
+    let union = a.len() + b.len() - intersection;
+    intersection as f64 / union as f64
+}
+
+fn tokenize_words(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+
+    for c in text.chars() {
+        if is_cjk(c) {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+            tokens.push(c.to_string());
+        } else if c.is_alphanumeric() {
+            current.extend(c.to_lowercase());
+        } else if !current.is_empty() {
+            tokens.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+pub fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x4E00..=0x9FFF     // CJK 统一表意文字
+        | 0x3400..=0x4DBF   // 扩展 A
+        | 0xF900..=0xFAFF   // 兼容表意文字
+        | 0x3040..=0x30FF   // 平假名、片假名
+        | 0xAC00..=0xD7AF   // 韩文音节
+    )
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DocumentFreshness {
+    pub document_id: String,
+    pub path: String,
+    pub annotation_count: usize,
+    pub last_annotation_at: Option<i64>, // 最近一次注解修改时间，没有注解时为空
+    pub days_since: Option<i64>,
+}
+
+// 各文档注解的活跃程度，最久未更新的排在前面，没有注解的文档排在最后
+pub fn get_document_freshness(conn: &Connection) -> Result<Vec<DocumentFreshness>, String> {
+    let mut stmt = conn.prepare("
+        SELECT d.id, d.path, COUNT(a.id), MAX(COALESCE(a.updated_at, a.created_at))
+        FROM documents d
+        LEFT JOIN annotations a ON a.document_id = d.id
+        GROUP BY d.id, d.path
+    ").map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+
+    let now = Utc::now().timestamp_millis();
+    let mut results = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        let count: i64 = row.get(2).map_err(|e| e.to_string())?;
+        let last_annotation_at: Option<i64> = row.get(3).map_err(|e| e.to_string())?;
+        results.push(DocumentFreshness {
+            document_id: row.get(0).map_err(|e| e.to_string())?,
+            path: row.get(1).map_err(|e| e.to_string())?,
+            annotation_count: count as usize,
+            last_annotation_at,
+            days_since: last_annotation_at.map(|at| (now - at).max(0) / 86_400_000),
+        });
+    }
+
+    results.sort_by_key(|f| (f.last_annotation_at.is_none(), f.last_annotation_at));
+    Ok(results)
+}
+
+// ============ 辅助函数 ============
+
+pub fn compute_checksum(content: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(content.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+// 按固定大小的缓冲区流式计算文件 SHA-256，结果与 compute_checksum 对相同字节一致
+pub fn compute_file_checksum(path: &str) -> Result<String, String> {
+    use std::io::Read;
+
+    let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
 
-    <button class="reopen-btn" onclick="showAllNotes()" title="显示所有便签">📝</button>
+// 解析 #rgb / #rrggbb / #rrggbbaa，忽略透明度
+pub fn parse_hex_color(color: &str) -> Option<(u8, u8, u8)> {
+    let hex = color.trim().strip_prefix('#')?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |s: &str| u8::from_str_radix(s, 16).ok();
+    match hex.len() {
+        3 => {
+            let (r, g, b) = (channel(&hex[0..1])?, channel(&hex[1..2])?, channel(&hex[2..3])?);
+            Some((r * 17, g * 17, b * 17))
+        }
+        6 | 8 => Some((channel(&hex[0..2])?, channel(&hex[2..4])?, channel(&hex[4..6])?)),
+        _ => None,
+    }
+}
 
-    <script type="application/json" id="ann-payload">
-{}
-    </script>
+// 与白色按比例混合得到浅色，ratio 为白色占比 0.0–1.0
+fn lighten_color((r, g, b): (u8, u8, u8), ratio: f64) -> (u8, u8, u8) {
+    let mix = |c: u8| (c as f64 + (255.0 - c as f64) * ratio).round() as u8;
+    (mix(r), mix(g), mix(b))
+}
 
-    <script>
-        const annotations = JSON.parse(document.getElementById('ann-payload').textContent);
+// WCAG 相对亮度
+pub fn relative_luminance((r, g, b): (u8, u8, u8)) -> f64 {
+    let linear = |c: u8| {
+        let c = c as f64 / 255.0;
+        if c <= 0.03928 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+}
 
-        // 点击高亮滚动到便签
-        document.querySelectorAll('.doc-highlight').forEach(function(el) {{
-            el.addEventListener('click', function() {{
-                const id = el.dataset.annoId;
-                const note = document.querySelector('.sticky-note[data-anno-id="' + id + '"]');
-                if (note) {{
-                    note.style.display = 'block';
-                    note.scrollIntoView({{ behavior: 'smooth', block: 'center' }});
-                    note.style.opacity = '1';
-                }}
-            }});
-        }});
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ContrastResult {
+    pub ratio: f64,
+    pub aa: bool,        // 正文 ≥ 4.5
+    pub aa_large: bool,  // 大号文字 ≥ 3.0
+    pub aaa: bool,       // 正文 ≥ 7.0
+    pub aaa_large: bool, // 大号文字 ≥ 4.5
+}
 
-        function closeNote(id) {{
-            const note = document.querySelector('.sticky-note[data-anno-id="' + id + '"]');
-            if (note) note.style.display = 'none';
-        }}
+// WCAG 对比度：(较亮 + 0.05) / (较暗 + 0.05)，范围 1–21
+pub fn check_color_contrast(background: &str, foreground: &str) -> Result<ContrastResult, String> {
+    let bg = parse_hex_color(background).ok_or_else(|| format!("Invalid color: {}", background))?;
+    let fg = parse_hex_color(foreground).ok_or_else(|| format!("Invalid color: {}", foreground))?;
+    let (l1, l2) = (relative_luminance(bg), relative_luminance(fg));
+    let ratio = (l1.max(l2) + 0.05) / (l1.min(l2) + 0.05);
+
+    // 判定使用未取整的比值，返回值保留两位小数
+    Ok(ContrastResult {
+        ratio: (ratio * 100.0).round() / 100.0,
+        aa: ratio >= 4.5,
+        aa_large: ratio >= 3.0,
+        aaa: ratio >= 7.0,
+        aaa_large: ratio >= 4.5,
+    })
+}
 
-        function showNote(id) {{
-            const note = document.querySelector('.sticky-note[data-anno-id="' + id + '"]');
-            if (note) {{
-                note.style.display = 'block';
-                note.style.opacity = '1';
-            }}
-        }}
+fn to_hex_color((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
 
-        function showAllNotes() {{
-            document.querySelectorAll('.sticky-note').forEach(function(note) {{
-                note.style.display = 'block';
-                note.style.opacity = '1';
-            }});
-        }}
+// 便签配色：(正文背景, 标题栏背景, 标题栏前景色)，由高亮色淡化得到；正文足够浅，沿用默认深色文字
+pub fn note_tint(highlight_color: &str) -> Option<(String, String, String)> {
+    let base = parse_hex_color(highlight_color)?;
+    let header = lighten_color(base, 0.45);
+    let background = lighten_color(base, 0.85);
+    // 淡化后亮度仍偏低时改用浅色文字
+    let foreground = if relative_luminance(header) < 0.18 { "#fff" } else { "#333" };
+    Some((to_hex_color(background), to_hex_color(header), foreground.to_string()))
+}
 
-        // 拖拽功能
-        document.querySelectorAll('.sticky-note').forEach(function(note) {{
-            let isDragging = false;
-            let startX, startY, origX, origY;
+// 转为小写 ASCII slug：非字母数字字符折叠为单个 '-'，去掉首尾 '-'
+pub fn slugify(s: &str) -> String {
+    let mut slug = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
+}
 
-            note.querySelector('.note-header').addEventListener('mousedown', function(e) {{
-                isDragging = true;
-                startX = e.clientX;
-                startY = e.clientY;
-                origX = note.offsetLeft;
-                origY = note.offsetTop;
-                note.style.zIndex = 1001;
-            }});
+// 基于编辑距离的名称相似度（忽略大小写），范围 0.0–1.0
+pub fn name_similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let max_len = a.len().max(b.len());
+    if max_len == 0 {
+        return 1.0;
+    }
 
-            document.addEventListener('mousemove', function(e) {{
-                if (!isDragging) return;
-                const dx = e.clientX - startX;
-                const dy = e.clientY - startY;
-                note.style.left = (origX + dx) + 'px';
-                note.style.top = (origY + dy) + 'px';
-            }});
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
 
-            document.addEventListener('mouseup', function() {{
-                isDragging = false;
-                note.style.zIndex = 1000;
-            }});
-        }});
-    </script>
-</body>
-</html>"#,
-        content,
-        notes_html,
-        payload
-    );
+    1.0 - prev[b.len()] as f64 / max_len as f64
+}
 
-    html
+// ============ 引用完整性 ============
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ForeignKeyViolation {
+    pub table: String,
+    pub rowid: Option<i64>,
+    pub parent: String,
+    pub fkid: i64,
 }
 
-fn escape_html(s: &str) -> String {
-    s.replace("&", "&amp;")
-        .replace("<", "&lt;")
-        .replace(">", "&gt;")
-        .replace("\"", "&quot;")
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct IntegrityIssues {
+    pub foreign_key_violations: Vec<ForeignKeyViolation>,
+    pub annotations_missing_user: Vec<String>,
+    pub annotations_missing_document: Vec<String>,
+    pub comments_missing_annotation: Vec<String>,
 }
 
-// ============ 辅助函数 ============
+// 修复策略：reassign 为孤立注解补建占位用户/文档，delete 直接删除孤立注解
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IntegrityFixStrategy {
+    Reassign,
+    Delete,
+}
 
-pub fn compute_checksum(content: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(content.as_bytes());
-    format!("{:x}", hasher.finalize())
+pub fn check_referential_integrity(conn: &Connection) -> Result<IntegrityIssues, String> {
+    let mut issues = IntegrityIssues::default();
+
+    // foreign_key_check 不依赖 foreign_keys 开关
+    let mut stmt = conn.prepare("PRAGMA foreign_key_check").map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        let violation = ForeignKeyViolation {
+            table: row.get(0).map_err(|e| e.to_string())?,
+            rowid: row.get(1).map_err(|e| e.to_string())?,
+            parent: row.get(2).map_err(|e| e.to_string())?,
+            fkid: row.get(3).map_err(|e| e.to_string())?,
+        };
+        issues.foreign_key_violations.push(violation);
+    }
+
+    issues.annotations_missing_user = query_ids(conn,
+        "SELECT id FROM annotations WHERE user_id NOT IN (SELECT id FROM users)")?;
+    issues.annotations_missing_document = query_ids(conn,
+        "SELECT id FROM annotations WHERE document_id NOT IN (SELECT id FROM documents)")?;
+    issues.comments_missing_annotation = query_ids(conn,
+        "SELECT id FROM comments WHERE annotation_id NOT IN (SELECT id FROM annotations)")?;
+
+    Ok(issues)
+}
+
+// 修复孤立引用，返回受影响的行数；孤立评论无法补建注解，总是删除
+pub fn fix_referential_integrity(conn: &Connection, strategy: IntegrityFixStrategy) -> Result<usize, String> {
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let now = Utc::now().timestamp_millis();
+    let mut fixed = 0;
+
+    match strategy {
+        IntegrityFixStrategy::Reassign => {
+            // 按原 ID 补建用户，保留注解上的作者名
+            fixed += tx.execute(
+                "INSERT OR IGNORE INTO users (id, name, created_at)
+                 SELECT user_id, MIN(user_name), ? FROM annotations
+                 WHERE user_id NOT IN (SELECT id FROM users) GROUP BY user_id",
+                params![now],
+            ).map_err(|e| e.to_string())?;
+            // 占位文档内容为空，路径使用 missing:// 前缀避免与真实文件冲突
+            fixed += tx.execute(
+                "INSERT OR IGNORE INTO documents (id, path, content, checksum, last_modified, created_at)
+                 SELECT DISTINCT document_id, 'missing://' || document_id, '', ?, ?, ? FROM annotations
+                 WHERE document_id NOT IN (SELECT id FROM documents)",
+                params![compute_checksum(""), now, now],
+            ).map_err(|e| e.to_string())?;
+        }
+        IntegrityFixStrategy::Delete => {
+            fixed += tx.execute(
+                "DELETE FROM annotations
+                 WHERE user_id NOT IN (SELECT id FROM users)
+                    OR document_id NOT IN (SELECT id FROM documents)",
+                [],
+            ).map_err(|e| e.to_string())?;
+        }
+    }
+
+    fixed += tx.execute(
+        "DELETE FROM comments WHERE annotation_id NOT IN (SELECT id FROM annotations)",
+        [],
+    ).map_err(|e| e.to_string())?;
+
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(fixed)
+}
+
+fn query_ids(conn: &Connection, sql: &str) -> Result<Vec<String>, String> {
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+    let mut ids = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        ids.push(row.get(0).map_err(|e| e.to_string())?);
+    }
+    Ok(ids)
 }
 
 // ============ 迁移 ============
 
-pub fn migrate_sidecar_files(conn: &Connection, base_dir: &str) -> Result<(), String> {
-    let entries = fs::read_dir(base_dir)
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct SidecarMigrationReport {
+    pub migrated: usize,
+    pub errors: usize,
+    pub undecodable: Vec<String>, // 无法识别编码的 .ann 文件
+}
+
+// 识别 .ann 文件编码：BOM > 无 BOM 的 UTF-16（JSON 首字符为 ASCII）> UTF-8 > GBK，均失败时返回 None
+fn decode_sidecar_bytes(bytes: &[u8]) -> Option<String> {
+    if let Some((encoding, bom_len)) = encoding_rs::Encoding::for_bom(bytes) {
+        return encoding
+            .decode_without_bom_handling_and_without_replacement(&bytes[bom_len..])
+            .map(|s| s.into_owned());
+    }
+    if bytes.len() >= 2 && bytes.len().is_multiple_of(2) {
+        let encoding = match (bytes[0], bytes[1]) {
+            (0, b) if b != 0 => Some(encoding_rs::UTF_16BE),
+            (b, 0) if b != 0 => Some(encoding_rs::UTF_16LE),
+            _ => None,
+        };
+        if let Some(encoding) = encoding {
+            return encoding
+                .decode_without_bom_handling_and_without_replacement(bytes)
+                .map(|s| s.into_owned());
+        }
+    }
+    if let Ok(s) = std::str::from_utf8(bytes) {
+        return Some(s.to_string());
+    }
+    encoding_rs::GBK
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .map(|s| s.into_owned())
+}
+
+// 导入一个 .ann 文件中的注解到对应文档，返回 (成功数, 失败数)
+fn import_sidecar_content(conn: &Connection, doc_path: &str, content: &str) -> Result<(usize, usize), String> {
+    let annotations: Vec<serde_json::Value> = serde_json::from_str(content)
         .map_err(|e| e.to_string())?;
 
+    // 确保文档已存在
+    if get_document_by_path(conn, doc_path)?.is_none() {
+        let doc_content = fs::read_to_string(doc_path).map_err(|e| e.to_string())?;
+        save_document(conn, doc_path, &doc_content)?;
+    }
+
+    let doc = get_document_by_path(conn, doc_path)?
+        .ok_or_else(|| "Document not found".to_string())?;
+    let user = get_or_create_user(conn, "migrated".to_string())?;
+
     let mut migrated = 0;
     let mut errors = 0;
 
+    // 导入每个注解
+    for anno_json in annotations {
+        let mut anno: AnnotationRecord = serde_json::from_value(anno_json)
+            .map_err(|e| e.to_string())?;
+
+        // 设置正确的关联
+        anno.id = Uuid::new_v4().to_string();
+        anno.document_id = doc.id.clone();
+        anno.user_id = user.id.clone();
+        anno.user_name = user.name.clone();
+        anno.highlight_color = "#ffd700".to_string();
+        anno.highlight_type = "underline".to_string();
+
+        if let Err(e) = insert_annotation(conn, &anno) {
+            errors += 1;
+            log_error(&format!("Error importing annotation: {}", e));
+            continue;
+        }
+
+        migrated += 1;
+    }
+    write_reading_order(conn, &doc.id, &doc.content)?;
+
+    Ok((migrated, errors))
+}
+
+// 单独导入一个 .ann 文件（支持 UTF-8、UTF-16 与 GBK），对应文档为去掉 .ann 后缀的路径
+pub fn import_sidecar_file(conn: &Connection, path: &str) -> Result<usize, String> {
+    let doc_path = path
+        .strip_suffix(".ann")
+        .ok_or_else(|| "Not an .ann file".to_string())?;
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let content = decode_sidecar_bytes(&bytes)
+        .ok_or_else(|| format!("UnknownEncoding: {}", path))?;
+
+    let (migrated, errors) = import_sidecar_content(conn, doc_path, &content)?;
+    if errors > 0 {
+        log_warn(&format!("Sidecar import: {} annotations failed in {}", errors, path));
+    }
+    Ok(migrated)
+}
+
+pub fn migrate_sidecar_files(conn: &Connection, base_dir: &str) -> Result<SidecarMigrationReport, String> {
+    let entries = fs::read_dir(base_dir)
+        .map_err(|e| e.to_string())?;
+
+    let mut report = SidecarMigrationReport::default();
+
     for entry in entries {
         let path = entry.map_err(|e| e.to_string())?.path();
 
@@ -856,60 +7096,32 @@ pub fn migrate_sidecar_files(conn: &Connection, base_dir: &str) -> Result<(), St
             None => continue,
         };
 
-        // 读取注解文件
-        let content = match fs::read_to_string(&path) {
-            Ok(c) => c,
+        // 读取注解文件，旧版工具可能以 GBK 或 UTF-16 保存
+        let bytes = match fs::read(&path) {
+            Ok(b) => b,
             Err(_) => {
-                errors += 1;
+                report.errors += 1;
                 continue;
             }
         };
-
-        // 解析注解
-        let annotations: Vec<serde_json::Value> = match serde_json::from_str(&content) {
-            Ok(v) => v,
-            Err(_) => {
-                errors += 1;
+        let content = match decode_sidecar_bytes(&bytes) {
+            Some(c) => c,
+            None => {
+                log_warn(&format!("Migration: unknown encoding in {}", ann_path));
+                report.undecodable.push(ann_path.to_string());
                 continue;
             }
         };
 
-        // 确保文档已存在
-        if let Ok(Some(_)) = get_document_by_path(conn, &doc_path) {
-            // 文档已存在
-        } else {
-            // 读取文档内容并保存
-            if let Ok(doc_content) = fs::read_to_string(&doc_path) {
-                let _ = save_document(conn, &doc_path, &doc_content);
-            } else {
-                errors += 1;
-                continue;
+        match import_sidecar_content(conn, &doc_path, &content) {
+            Ok((migrated, errors)) => {
+                report.migrated += migrated;
+                report.errors += errors;
             }
-        }
-
-        let doc = get_document_by_path(conn, &doc_path)?.unwrap();
-        let user = get_or_create_user(conn, "migrated".to_string())?;
-
-        // 导入每个注解
-        for anno_json in annotations {
-            let mut anno: AnnotationRecord = serde_json::from_value(anno_json)
-                .map_err(|e| e.to_string())?;
-
-            // 设置正确的关联
-            anno.id = Uuid::new_v4().to_string();
-            anno.document_id = doc.id.clone();
-            anno.user_id = user.id.clone();
-            anno.user_name = user.name.clone();
-            anno.highlight_color = "#ffd700".to_string();
-            anno.highlight_type = "underline".to_string();
-
-            if let Err(e) = add_annotation(conn, &anno) {
-                errors += 1;
-                println!("Error importing annotation: {}", e);
+            Err(_) => {
+                report.errors += 1;
                 continue;
             }
-
-            migrated += 1;
         }
 
         // 备份原始文件
@@ -917,8 +7129,11 @@ pub fn migrate_sidecar_files(conn: &Connection, base_dir: &str) -> Result<(), St
         let _ = fs::rename(&path, &backup_path);
     }
 
-    println!("Migration complete: {} annotations migrated, {} errors", migrated, errors);
-    Ok(())
+    log_info(&format!(
+        "Migration complete: {} annotations migrated, {} errors, {} undecodable files",
+        report.migrated, report.errors, report.undecodable.len()
+    ));
+    Ok(report)
 }
 
 // ============ 设置操作 ============
@@ -940,14 +7155,20 @@ pub fn load_settings() -> Result<SettingsRecord, String> {
                 default_highlight_type: "underline".to_string(),
                 font_size: 16,
                 font_family: "system-ui".to_string(),
+                max_document_bytes: default_max_document_bytes(),
+                color_presets: default_color_presets(),
+                presentation_mode: false,
             },
             export: ExportSettingsRecord {
                 default_format: "html".to_string(),
                 show_notes_by_default: true,
+                default_dir: None,
+                color_labels: std::collections::BTreeMap::new(),
             },
             i18n: I18nSettingsRecord {
                 language: "zh-CN".to_string(),
             },
+            database: DatabaseSettingsRecord::default(),
         };
 
         save_settings(&default_settings)?;
@@ -961,10 +7182,69 @@ pub fn load_settings() -> Result<SettingsRecord, String> {
 pub fn save_settings(settings: &SettingsRecord) -> Result<(), String> {
     let path = get_settings_path();
     let content = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
-    fs::write(&path, content).map_err(|e| e.to_string())?;
+    // 先写临时文件再改名，同时读取设置的一方不会读到写了一半的文件
+    let tmp = path.with_extension(format!("json.{}.tmp", Uuid::new_v4()));
+    fs::write(&tmp, content).map_err(|e| e.to_string())?;
+    fs::rename(&tmp, &path).map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        e.to_string()
+    })?;
     Ok(())
 }
 
+pub fn get_default_export_dir() -> Result<std::path::PathBuf, String> {
+    Ok(load_settings()?
+        .export
+        .default_dir
+        .filter(|d| !d.trim().is_empty())
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| get_app_data_dir().join("exports")))
+}
+
+pub fn set_default_export_dir(dir: Option<String>) -> Result<(), String> {
+    let mut settings = load_settings()?;
+    settings.export.default_dir = dir.filter(|d| !d.trim().is_empty());
+    save_settings(&settings)
+}
+
+// 相对路径按默认导出目录解析，绝对路径保持不变；确保父目录存在
+pub fn resolve_export_path(path: &str) -> Result<std::path::PathBuf, String> {
+    let path = std::path::Path::new(path);
+    let resolved = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        get_default_export_dir()?.join(path)
+    };
+
+    if let Some(parent) = resolved.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    Ok(resolved)
+}
+
+// 可移植设置：去掉本机用户 ID 与绝对路径，便于放入版本管理
+pub fn export_portable_settings() -> Result<String, String> {
+    let mut settings = load_settings()?;
+    settings.user.id = String::new();
+    settings.export.default_dir = settings.export.default_dir
+        .filter(|dir| !std::path::Path::new(dir).is_absolute());
+    serde_json::to_string_pretty(&settings).map_err(|e| e.to_string())
+}
+
+// 导入可移植设置，保留本机用户 ID；导入内容未指定导出目录时沿用本机设置
+pub fn import_portable_settings(json: &str) -> Result<SettingsRecord, String> {
+    let local = load_settings()?;
+    let mut settings: SettingsRecord = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+    settings.user.id = local.user.id;
+    if settings.export.default_dir.is_none() {
+        settings.export.default_dir = local.export.default_dir;
+    }
+
+    save_settings(&settings)?;
+    Ok(settings)
+}
+
 pub fn update_user_name_in_settings(new_name: &str) -> Result<(), String> {
     let mut settings = load_settings()?;
     settings.user.name = new_name.to_string();
@@ -972,6 +7252,54 @@ pub fn update_user_name_in_settings(new_name: &str) -> Result<(), String> {
     Ok(())
 }
 
+// 让 settings.json 中的用户与数据库用户一致：以数据库的 id 和名称为准；
+// 数据库中还没有用户时按设置中的 id 和名称创建
+pub fn reconcile_user(conn: &Connection) -> Result<UserRecord, String> {
+    let mut settings = load_settings()?;
+
+    let existing = {
+        let mut stmt = conn.prepare("SELECT id, name, created_at FROM users LIMIT 1")
+            .map_err(|e| e.to_string())?;
+        let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+        match rows.next().map_err(|e| e.to_string())? {
+            Some(row) => Some(UserRecord {
+                id: row.get(0).map_err(|e| e.to_string())?,
+                name: row.get(1).map_err(|e| e.to_string())?,
+                created_at: row.get(2).map_err(|e| e.to_string())?,
+            }),
+            None => None,
+        }
+    };
+
+    let user = match existing {
+        Some(user) => user,
+        None => {
+            let user = UserRecord {
+                id: settings.user.id.clone(),
+                name: settings.user.name.clone(),
+                created_at: Utc::now().timestamp_millis(),
+            };
+            conn.execute(
+                "INSERT INTO users (id, name, created_at) VALUES (?, ?, ?)",
+                params![user.id, user.name, user.created_at],
+            ).map_err(|e| e.to_string())?;
+            user
+        }
+    };
+
+    if settings.user.id != user.id || settings.user.name != user.name {
+        log_warn(&format!(
+            "User drift: settings {} ({}) -> database {} ({})",
+            settings.user.id, settings.user.name, user.id, user.name
+        ));
+        settings.user.id = user.id.clone();
+        settings.user.name = user.name.clone();
+        save_settings(&settings)?;
+    }
+
+    Ok(user)
+}
+
 // ============ UI 设置操作 ============
 
 pub fn get_ui_settings_path() -> std::path::PathBuf {
@@ -992,13 +7320,51 @@ pub fn load_ui_settings() -> Result<Option<serde_json::Value>, String> {
     serde_json::from_str(&content).map_err(|e| e.to_string())
 }
 
+// 串行化 UI 设置文件的读改写，避免单键更新覆盖并发写入
+static UI_SETTINGS_LOCK: Mutex<()> = Mutex::new(());
+
 pub fn save_ui_settings(settings: &serde_json::Value) -> Result<(), String> {
+    let _guard = UI_SETTINGS_LOCK.lock().map_err(|e| e.to_string())?;
+    write_ui_settings(settings)
+}
+
+// 先写临时文件再重命名，读取方不会看到写了一半的文件
+fn write_ui_settings(settings: &serde_json::Value) -> Result<(), String> {
     let path = get_ui_settings_path();
     let content = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
-    fs::write(&path, content).map_err(|e| e.to_string())?;
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, content).map_err(|e| e.to_string())?;
+    fs::rename(&temp, &path).map_err(|e| e.to_string())?;
     Ok(())
 }
 
+// 读取单个 UI 设置项，未保存过时返回默认值
+pub fn get_ui_setting(key: &str) -> Result<Option<serde_json::Value>, String> {
+    let _guard = UI_SETTINGS_LOCK.lock().map_err(|e| e.to_string())?;
+    let saved = load_ui_settings()?;
+    Ok(saved
+        .as_ref()
+        .and_then(|settings| settings.get(key))
+        .cloned()
+        .or_else(|| default_ui_settings().remove(key)))
+}
+
+// 只修改单个 UI 设置项，其余字段保持文件中的原值
+pub fn set_ui_setting(key: &str, value: serde_json::Value) -> Result<(), String> {
+    if key.trim().is_empty() {
+        return Err("UI setting key cannot be empty".to_string());
+    }
+
+    let _guard = UI_SETTINGS_LOCK.lock().map_err(|e| e.to_string())?;
+    let mut settings = match load_ui_settings()? {
+        Some(serde_json::Value::Object(settings)) => settings,
+        Some(_) => return Err("UI settings file is not a JSON object".to_string()),
+        None => serde_json::Map::new(),
+    };
+    settings.insert(key.to_string(), value);
+    write_ui_settings(&serde_json::Value::Object(settings))
+}
+
 // ============ 排版配置操作 ============
 
 pub fn get_typography_path() -> std::path::PathBuf {
@@ -1007,3 +7373,145 @@ pub fn get_typography_path() -> std::path::PathBuf {
     path.push("typography.yaml");
     path
 }
+
+// 内置默认排版配置，与随应用分发的 typography.yaml 一致
+pub const DEFAULT_TYPOGRAPHY_YAML: &str = include_str!("../typography.yaml");
+
+// 用内置默认值覆盖 typography.yaml（即使原文件已损坏），先写临时文件再重命名；返回默认配置原文供前端刷新
+pub fn reset_typography_config() -> Result<String, String> {
+    let path = get_typography_path();
+    let temp = path.with_extension("yaml.tmp");
+    fs::write(&temp, DEFAULT_TYPOGRAPHY_YAML).map_err(|e| e.to_string())?;
+    fs::rename(&temp, &path).map_err(|e| e.to_string())?;
+    Ok(DEFAULT_TYPOGRAPHY_YAML.to_string())
+}
+
+// ============ 合并配置 ============
+
+#[derive(Serialize, Deserialize)]
+pub struct EffectiveConfig {
+    pub settings: SettingsRecord,
+    pub ui: serde_json::Value,
+    pub typography: TypographySource,
+}
+
+// typography.yaml 由前端解析，这里只提供原文；文件不存在时 is_default 为 true，由前端使用内置默认值
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TypographySource {
+    pub path: String,
+    pub content: String,
+    pub is_default: bool,
+}
+
+// 与前端 UISettingsRecord 的默认值保持一致
+fn default_ui_settings() -> serde_json::Map<String, serde_json::Value> {
+    let mut ui = serde_json::Map::new();
+    ui.insert("theme".to_string(), "light".into());
+    ui.insert("window_width".to_string(), 800.into());
+    ui.insert("window_height".to_string(), 600.into());
+    ui.insert("window_maximized".to_string(), false.into());
+    ui.insert("sidebar_visible".to_string(), true.into());
+    ui.insert("sidebar_width".to_string(), 30.into());
+    ui.insert("sidebar_experimental".to_string(), false.into());
+    ui
+}
+
+// 合并 settings.json、ui_settings.json 与 typography.yaml，分别置于 settings / ui / typography 下
+pub fn get_effective_config() -> Result<String, String> {
+    let settings = load_settings()?;
+
+    let mut ui = default_ui_settings();
+    if let Some(serde_json::Value::Object(saved)) = load_ui_settings()? {
+        ui.extend(saved);
+    }
+
+    let typography_path = get_typography_path();
+    let typography = if typography_path.exists() {
+        TypographySource {
+            path: typography_path.to_string_lossy().to_string(),
+            content: fs::read_to_string(&typography_path).map_err(|e| e.to_string())?,
+            is_default: false,
+        }
+    } else {
+        TypographySource {
+            path: typography_path.to_string_lossy().to_string(),
+            content: String::new(),
+            is_default: true,
+        }
+    };
+
+    let config = EffectiveConfig {
+        settings,
+        ui: serde_json::Value::Object(ui),
+        typography,
+    };
+    serde_json::to_string_pretty(&config).map_err(|e| e.to_string())
+}
+
+// ============ 配置导入 ============
+
+// 从另一个应用数据目录复制 settings.json、ui_settings.json 与 typography.yaml；
+// 先全部读取并校验，再写入临时文件后逐一替换，任一文件校验失败时不修改当前配置
+pub fn import_config_from_dir(source_dir: &str) -> Result<(), String> {
+    let source = std::path::Path::new(source_dir);
+    if !source.is_dir() {
+        return Err(format!("Directory not found: {}", source_dir));
+    }
+
+    let targets = [
+        ("settings.json", get_settings_path()),
+        ("ui_settings.json", get_ui_settings_path()),
+        ("typography.yaml", get_typography_path()),
+    ];
+
+    let mut staged = Vec::new();
+    for (name, target) in &targets {
+        let path = source.join(name);
+        if !path.exists() {
+            continue;
+        }
+        let content = fs::read_to_string(&path).map_err(|e| format!("{}: {}", name, e))?;
+        validate_config_file(name, &content).map_err(|e| format!("{}: {}", name, e))?;
+        staged.push((target, content));
+    }
+
+    if staged.is_empty() {
+        return Err("No configuration files found in source directory".to_string());
+    }
+
+    let mut temp_files = Vec::new();
+    for (target, content) in &staged {
+        let temp = target.with_extension("import.tmp");
+        if let Err(e) = fs::write(&temp, content) {
+            for temp in &temp_files {
+                let _ = fs::remove_file(temp);
+            }
+            return Err(e.to_string());
+        }
+        temp_files.push(temp);
+    }
+
+    for ((target, _), temp) in staged.iter().zip(&temp_files) {
+        fs::rename(temp, target).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn validate_config_file(name: &str, content: &str) -> Result<(), String> {
+    match name {
+        "settings.json" => serde_json::from_str::<SettingsRecord>(content)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        "ui_settings.json" => serde_json::from_str::<serde_json::Value>(content)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        // 没有 YAML 解析依赖，只检查 YAML 不允许的制表符缩进
+        _ => match content.lines().position(|line| line.starts_with('\t')) {
+            Some(index) => Err(format!("tab indentation on line {}", index + 1)),
+            None => Ok(()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,2865 @@
+use super::*;
+use std::path::PathBuf;
+use std::sync::Once;
+
+// ============ 测试夹具 ============
+
+// 设置、日志、恢复日志等按应用数据目录读写，所有测试共用一个临时目录，首次调用时设置
+fn init_app_data() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("annoti-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("XDG_DATA_HOME", &dir);
+        std::env::set_var("APPDATA", &dir);
+        std::env::set_var("HOME", &dir);
+        // 先生成默认设置，避免并发测试各自创建出不同的用户 ID
+        load_settings().unwrap();
+    });
+}
+
+// 修改全局配置的测试串行执行，守卫释放时（包括断言失败时）恢复 settings.json、ui_settings.json 与 typography.yaml
+struct SettingsGuard {
+    original: Option<SettingsRecord>,
+    files: Vec<(PathBuf, Option<String>)>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+fn edit_settings(edit: impl FnOnce(&mut SettingsRecord)) -> SettingsGuard {
+    static SETTINGS_LOCK: Mutex<()> = Mutex::new(());
+    init_app_data();
+    let lock = SETTINGS_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let files = [get_ui_settings_path(), get_typography_path()]
+        .into_iter()
+        .map(|path| {
+            let content = fs::read_to_string(&path).ok();
+            (path, content)
+        })
+        .collect();
+    let original = load_settings().unwrap();
+    let mut settings = load_settings().unwrap();
+    edit(&mut settings);
+    save_settings(&settings).unwrap();
+    SettingsGuard { original: Some(original), files, _lock: lock }
+}
+
+impl Drop for SettingsGuard {
+    fn drop(&mut self) {
+        if let Some(original) = self.original.take() {
+            let _ = save_settings(&original);
+        }
+        for (path, content) in &self.files {
+            let _ = match content {
+                Some(content) => fs::write(path, content),
+                None => fs::remove_file(path),
+            };
+        }
+    }
+}
+
+// 每个测试独立的临时目录，内含已建好表结构的数据库；结束时删除
+struct TestDb {
+    conn: Connection,
+    dir: PathBuf,
+}
+
+impl TestDb {
+    fn new() -> TestDb {
+        init_app_data();
+        let dir = std::env::temp_dir().join(format!("annoti-db-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let conn = Connection::open(dir.join("data.db")).unwrap();
+        create_schema(&conn).unwrap();
+        TestDb { conn, dir }
+    }
+
+    // 在临时目录中写入文档文件并入库
+    fn document(&self, name: &str, content: &str) -> DocumentRecord {
+        let path = self.path(name);
+        fs::write(&path, content).unwrap();
+        save_document(&self.conn, &path, content).unwrap()
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_string_lossy().to_string()
+    }
+
+    // 以当前用户为作者，构造覆盖 [start,end) 的注解，不入库
+    fn build(&self, doc: &DocumentRecord, start: usize, end: usize) -> AnnotationRecord {
+        let user = get_or_create_user(&self.conn, "admin".to_string()).unwrap();
+        build_annotation(&doc.id, &user, &doc.content[start..end], None, "#ffd700", "underline", (start, end))
+    }
+
+    // 构造并入库
+    fn annotate(&self, doc: &DocumentRecord, start: usize, end: usize) -> AnnotationRecord {
+        let anno = self.build(doc, start, end);
+        add_annotation(&self.conn, &anno).unwrap();
+        anno
+    }
+
+    // 在文档中第一次出现的 text 上添加注解
+    fn annotate_text(&self, doc: &DocumentRecord, text: &str) -> AnnotationRecord {
+        let start = doc.content.find(text).unwrap();
+        self.annotate(doc, start, start + text.len())
+    }
+
+    // 以指定作者（不存在时创建）在第一次出现的 text 上添加注解
+    fn annotate_as(&self, doc: &DocumentRecord, text: &str, author: &str) -> AnnotationRecord {
+        let user = find_or_create_user_by_name(&self.conn, author).unwrap();
+        let start = doc.content.find(text).unwrap();
+        let anno = build_annotation(&doc.id, &user, text, None, "#ffd700", "underline", (start, start + text.len()));
+        add_annotation(&self.conn, &anno).unwrap();
+        anno
+    }
+
+    // 同上，并带有笔记
+    fn annotate_note(&self, doc: &DocumentRecord, text: &str, note: &str) -> AnnotationRecord {
+        let start = doc.content.find(text).unwrap();
+        let mut anno = self.build(doc, start, start + text.len());
+        anno.note = Some(note.to_string());
+        add_annotation(&self.conn, &anno).unwrap();
+        anno
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// ============ 校验和 ============
+
+#[test]
+fn recompute_checksums_fixes_wrong_checksum() {
+    let db = TestDb::new();
+    let doc = db.document("a.md", "hello world");
+    db.document("b.md", "untouched");
+    db.conn.execute("UPDATE documents SET checksum = 'bogus' WHERE id = ?", [&doc.id]).unwrap();
+
+    assert_eq!(recompute_checksums(&db.conn).unwrap(), 1);
+
+    let fixed = get_document_by_id(&db.conn, &doc.id).unwrap().unwrap();
+    assert_eq!(fixed.checksum, compute_checksum("hello world"));
+    assert_eq!(recompute_checksums(&db.conn).unwrap(), 0);
+}
+
+// ============ GitHub Review 导出 ============
+
+#[test]
+fn github_review_uses_anchor_lines() {
+    let db = TestDb::new();
+    let doc = db.document("review.md", "line one\nline two\nline three\n");
+
+    // 界面锚点没有原文范围，但带有行号
+    let mut ui = db.build(&doc, 0, 4);
+    ui.anchor_data = r#"[{"containerPath":"p[3]","textNodeIndex":0,"startOffset":0,"endOffset":4,"line":3}]"#.to_string();
+    ui.note = Some("on line three".to_string());
+    add_annotation(&db.conn, &ui).unwrap();
+    db.annotate(&doc, 14, 17);
+
+    let json = export_as_github_review(&db.conn, &doc.id, &ExportFilter::default()).unwrap();
+    let comments: Vec<ReviewComment> = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(comments.len(), 2);
+    assert_eq!(comments[0].line, Some(2));
+    assert_eq!(comments[0].body, "> two");
+    assert_eq!(comments[1].line, Some(3));
+    assert_eq!(comments[1].body, "on line three");
+    assert!(comments.iter().all(|c| c.path == doc.path));
+}
+
+// ============ 导入目标推荐 ============
+
+fn package_for(name: &str, checksum: &str) -> String {
+    serde_json::json!({
+        "version": "1.0",
+        "exported_at": 0,
+        "source_document": { "name": name, "checksum": checksum },
+        "annotations": [],
+    }).to_string()
+}
+
+#[test]
+fn suggest_import_target_prefers_checksum_match() {
+    let db = TestDb::new();
+    let dir = db.dir.join("candidates");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("renamed.md"), "the original text").unwrap();
+    fs::write(dir.join("notes.md"), "something else").unwrap();
+
+    let package = package_for("notes.md", &compute_checksum("the original text"));
+    let candidates = suggest_import_target(&package, &dir.to_string_lossy()).unwrap();
+
+    assert!(candidates[0].0.ends_with("renamed.md"));
+    assert_eq!(candidates[0].1, 1.0);
+    assert!(candidates[1].0.ends_with("notes.md"));
+    assert!(candidates[1].1 < 1.0);
+}
+
+#[test]
+fn suggest_import_target_falls_back_to_name_similarity() {
+    let db = TestDb::new();
+    let dir = db.dir.join("candidates");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("chapter_01.md"), "local edits").unwrap();
+    fs::write(dir.join("zzz.txt"), "unrelated").unwrap();
+
+    let package = package_for("chapter-01.md", &compute_checksum("exported text"));
+    let candidates = suggest_import_target(&package, &dir.to_string_lossy()).unwrap();
+
+    assert!(candidates[0].0.ends_with("chapter_01.md"));
+    assert!(candidates[0].1 > 0.0 && candidates[0].1 < 1.0);
+    assert!(candidates.iter().skip(1).all(|(_, score)| *score < candidates[0].1));
+}
+
+// ============ 读连接池 ============
+
+#[test]
+fn concurrent_reads_do_not_hit_lock_errors() {
+    init_app_data();
+    let path = format!("concurrent-{}.md", Uuid::new_v4());
+    let doc = save_document(&init_db_for_write().unwrap(), &path, "revision 0").unwrap();
+
+    let readers: Vec<_> = (0..8)
+        .map(|_| {
+            let id = doc.id.clone();
+            std::thread::spawn(move || -> Result<(), String> {
+                for _ in 0..50 {
+                    let conn = read_db()?;
+                    let found = get_document_by_id(&conn, &id)?.ok_or("document disappeared")?;
+                    assert!(found.content.starts_with("revision"));
+                }
+                Ok(())
+            })
+        })
+        .collect();
+
+    // 读取进行期间写连接持续写入
+    for revision in 1..=20 {
+        save_document(&init_db_for_write().unwrap(), &path, &format!("revision {}", revision)).unwrap();
+    }
+    for reader in readers {
+        reader.join().unwrap().unwrap();
+    }
+}
+
+// ============ 词频 ============
+
+#[test]
+fn word_frequencies_lowercase_strip_punctuation_and_count_cjk() {
+    let db = TestDb::new();
+    let doc = db.document("words.md", "The cat. Then the CAT sat! 学习学习");
+    let other = db.document("other.md", "cat");
+    db.annotate_text(&doc, "The cat.");
+    db.annotate_text(&doc, "the CAT sat!");
+    db.annotate_text(&doc, "学习学习");
+    db.annotate_text(&other, "cat");
+
+    let scoped = get_highlight_word_frequencies(&db.conn, Some(&doc.id), 10).unwrap();
+    assert_eq!(scoped, vec![
+        ("cat".to_string(), 2),
+        ("the".to_string(), 2),
+        ("习".to_string(), 2),
+        ("学".to_string(), 2),
+        ("sat".to_string(), 1),
+    ]);
+
+    let all = get_highlight_word_frequencies(&db.conn, None, 1).unwrap();
+    assert_eq!(all, vec![("cat".to_string(), 3)]);
+}
+
+// ============ 笔记替换 ============
+
+fn note_of(conn: &Connection, id: &str) -> Option<String> {
+    get_annotation_by_id(conn, id).unwrap().unwrap().note
+}
+
+#[test]
+fn replace_in_notes_case_sensitive() {
+    let db = TestDb::new();
+    let doc = db.document("notes.md", "alpha beta");
+    let a = db.annotate_note(&doc, "alpha", "Rust and rust");
+    let b = db.annotate_note(&doc, "beta", "nothing here");
+
+    assert_eq!(replace_in_notes(&db.conn, &doc.id, "rust", "Go", true).unwrap(), 1);
+    assert_eq!(note_of(&db.conn, &a.id).as_deref(), Some("Rust and Go"));
+    assert_eq!(note_of(&db.conn, &b.id).as_deref(), Some("nothing here"));
+}
+
+#[test]
+fn replace_in_notes_case_insensitive() {
+    let db = TestDb::new();
+    let doc = db.document("notes.md", "alpha beta");
+    let a = db.annotate_note(&doc, "alpha", "Rust and rust and RUST");
+
+    assert_eq!(replace_in_notes(&db.conn, &doc.id, "rust", "Go", false).unwrap(), 1);
+    assert_eq!(note_of(&db.conn, &a.id).as_deref(), Some("Go and Go and Go"));
+    assert!(replace_in_notes(&db.conn, &doc.id, "", "x", false).is_err());
+}
+
+// ============ 撤销日志 ============
+
+#[test]
+fn undo_restores_deleted_document_with_annotations() {
+    let db = TestDb::new();
+    let doc = db.document("undo.md", "first second");
+    let first = db.annotate_text(&doc, "first");
+    let second = db.annotate_note(&doc, "second", "keep me");
+    let comment = add_comment(&db.conn, &first.id, "a reply", None).unwrap();
+    reindex_annotation_order(&db.conn, &doc.id, &doc.content).unwrap();
+    db.conn.execute("UPDATE annotations SET updated_at = 1000 WHERE id = ?", [&second.id]).unwrap();
+    let reading_order = |id: &str| -> Option<i64> {
+        db.conn.query_row("SELECT reading_order FROM annotations WHERE id = ?", [id], |row| row.get(0)).unwrap()
+    };
+
+    delete_document(&db.conn, &doc.id).unwrap();
+    assert!(get_document_by_id(&db.conn, &doc.id).unwrap().is_none());
+    assert!(get_annotation_by_id(&db.conn, &first.id).unwrap().is_none());
+
+    assert_eq!(undo_last_operation(&db.conn).unwrap().as_deref(), Some("delete_document"));
+
+    let restored = get_document_by_id(&db.conn, &doc.id).unwrap().unwrap();
+    assert_eq!(restored.content, "first second");
+    let annotations = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    assert_eq!(annotations.len(), 2);
+    assert_eq!(note_of(&db.conn, &second.id).as_deref(), Some("keep me"));
+    let comments = get_comments_by_annotation(&db.conn, &first.id).unwrap();
+    assert_eq!(comments.len(), 1);
+    assert_eq!(comments[0].id, comment.id);
+    // 按记录原样恢复，不会刷新修改时间或丢失阅读顺序
+    assert_eq!(get_annotation_by_id(&db.conn, &second.id).unwrap().unwrap().updated_at, 1000);
+    assert_eq!(reading_order(&first.id), Some(0));
+    assert_eq!(reading_order(&second.id), Some(1));
+
+    // 日志已消费
+    assert_eq!(undo_last_operation(&db.conn).unwrap(), None);
+}
+
+// ============ 定位笔记导出 ============
+
+#[test]
+fn positioned_notes_number_blocks_by_position() {
+    let db = TestDb::new();
+    let doc = db.document("srt.md", "aaa bbb ccc");
+    db.annotate_text(&doc, "ccc");
+    db.annotate_note(&doc, "aaa", "  first note  ");
+
+    let srt = export_as_positioned_notes(&db.conn, &doc.id, &ExportFilter::default()).unwrap();
+    assert_eq!(srt, "1\n0 --> 3\nfirst note\n\n2\n8 --> 11\n> ccc\n");
+}
+
+// ============ 只读数据库 ============
+
+#[test]
+fn read_only_connection_reports_friendly_error() {
+    let db = TestDb::new();
+    assert!(ensure_writable(&db.conn).is_ok());
+
+    let read_only = Connection::open_with_flags(db.dir.join("data.db"), OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
+    assert_eq!(ensure_writable(&read_only), Err(DB_READ_ONLY_ERROR.to_string()));
+
+    // 只读连接仍可读取
+    let count: i64 = read_only.query_row("SELECT COUNT(*) FROM documents", [], |row| row.get(0)).unwrap();
+    assert_eq!(count, 0);
+}
+
+// ============ 康奈尔笔记导出 ============
+
+#[test]
+fn cornell_export_has_two_column_rows() {
+    let db = TestDb::new();
+    let doc = db.document("cornell.md", "cue text and more");
+    db.annotate_note(&doc, "cue text", "why <this> matters");
+    db.annotate_text(&doc, "more");
+
+    let html = export_as_cornell(&db.conn, &doc.id, &doc.content, &ExportFilter::default()).unwrap();
+
+    assert!(html.contains("grid-template-columns: 30% 70%"));
+    assert!(html.contains(r#"<div class="cornell-header"><div>Cues</div><div>Notes</div></div>"#));
+    assert_eq!(html.matches(r#"<div class="cornell-row">"#).count(), 2);
+    assert_eq!(html.matches(r#"<div class="cornell-cue">"#).count(), 2);
+    assert_eq!(html.matches(r#"<div class="cornell-note">"#).count(), 2);
+    assert!(html.contains("why &lt;this&gt; matters"));
+    assert!(html.contains("2 highlights, 1 with notes"));
+}
+
+// ============ CSV 导入 ============
+
+#[test]
+fn csv_import_reports_unmatched_rows() {
+    let db = TestDb::new();
+    let doc = db.document("csv.md", "The quick brown fox");
+
+    let csv = "text,note,color\n\"quick brown\",\"a, note\",#87ceeb\nmissing words,,\n";
+    let report = import_from_csv(&db.conn, csv, &doc.path).unwrap();
+
+    assert_eq!(report.imported, 1);
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].position, 3);
+    assert_eq!(report.skipped[0].text, "missing words");
+    assert_eq!(report.skipped[0].reason, "text not found in document");
+
+    let annotations = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    assert_eq!(annotations.len(), 1);
+    assert_eq!(annotations[0].text, "quick brown");
+    assert_eq!(annotations[0].note.as_deref(), Some("a, note"));
+    assert_eq!(annotations[0].highlight_color, "#87ceeb");
+    assert_eq!(locate_annotation(&annotations[0], &doc.content), Some((4, 15)));
+}
+
+#[test]
+fn csv_import_validates_rows_and_advances_duplicates() {
+    let db = TestDb::new();
+    let doc = db.document("rows.md", "the cat and the hat");
+
+    let csv = "text,note,color,type\nthe,,,\nthe,,,\nthe,,,\ncat,,not-a-color,\nhat,,,sparkle\nand,,blue,square\n";
+    let report = import_from_csv(&db.conn, csv, &doc.path).unwrap();
+
+    assert_eq!(report.imported, 3);
+    let skipped: Vec<(usize, &str, &str)> = report.skipped.iter()
+        .map(|s| (s.position, s.text.as_str(), s.reason.as_str()))
+        .collect();
+    assert_eq!(skipped, [
+        (4, "the", "no further occurrence in document"),
+        (5, "cat", "invalid highlight color: not-a-color"),
+        (6, "hat", "invalid highlight type: sparkle"),
+    ]);
+
+    // 重复的文本依次落在不同的出现位置上，预设颜色名解析为十六进制
+    let annotations = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    let placed: Vec<_> = annotations.iter().map(|a| locate_annotation(a, &doc.content)).collect();
+    assert_eq!(placed, [Some((0, 3)), Some((8, 11)), Some((12, 15))]);
+    assert_eq!(annotations[1].highlight_color, "#87cefa");
+    assert_eq!(annotations[1].highlight_type, "square");
+}
+
+// ============ 注解上下文 ============
+
+#[test]
+fn annotation_context_clamps_and_respects_utf8() {
+    let db = TestDb::new();
+    let doc = db.document("context.md", "前文内容abcHIGHLIGHTdef后文");
+    let anno = db.annotate_text(&doc, "HIGHLIGHT");
+    assert_eq!(locate_annotation(&anno, &doc.content), Some((15, 24)));
+
+    let context = get_annotation_context(&db.conn, &anno.id, &doc.content, 5).unwrap();
+    assert_eq!(context, "…内容abc[[HIGHLIGHT]]def后文");
+
+    let wide = get_annotation_context(&db.conn, &anno.id, &doc.content, 100).unwrap();
+    assert_eq!(wide, "前文内容abc[[HIGHLIGHT]]def后文");
+}
+
+// ============ HTML 导出 ============
+
+#[test]
+fn html_export_print_block_only_when_enabled() {
+    let db = TestDb::new();
+    let doc = db.document("print.md", "printable text");
+    let anno = db.annotate_text(&doc, "printable");
+    let ids = vec![anno.id];
+    let html_content = "<p>printable text</p>";
+
+    let options = HtmlExportOptions { print_friendly: true, ..Default::default() };
+    let (html, _) = export_as_html(&db.conn, &doc.id, &ids, html_content, &options).unwrap();
+    assert!(html.contains("@media print"));
+    assert!(html.contains(".reopen-btn { display: none !important; }"));
+
+    let (html, _) = export_as_html(&db.conn, &doc.id, &ids, html_content, &HtmlExportOptions::default()).unwrap();
+    assert!(!html.contains("@media print"));
+}
+
+// ============ 批量修改高亮样式 ============
+
+#[test]
+fn change_highlight_type_converts_underline_to_background() {
+    let db = TestDb::new();
+    let doc = db.document("types.md", "one two three");
+    let one = db.annotate_text(&doc, "one");
+    let two = db.annotate_text(&doc, "two");
+    let mut three = db.build(&doc, 8, 13);
+    three.highlight_type = "square".to_string();
+    add_annotation(&db.conn, &three).unwrap();
+    db.conn.execute("UPDATE annotations SET updated_at = 1000 WHERE id = ?", [&one.id]).unwrap();
+
+    // background 即背景色块样式，存储为 square
+    assert_eq!(change_highlight_type(&db.conn, &doc.id, "underline", "background").unwrap(), 2);
+    for id in [&one.id, &two.id, &three.id] {
+        let anno = get_annotation_by_id(&db.conn, id).unwrap().unwrap();
+        assert_eq!(anno.highlight_type, "square");
+    }
+    let updated = get_annotation_by_id(&db.conn, &one.id).unwrap().unwrap();
+    assert!(updated.updated_at > 1000);
+
+    // 未知样式在修改前被拒绝
+    assert!(change_highlight_type(&db.conn, &doc.id, "square", "sparkle").is_err());
+    assert_eq!(change_highlight_type(&db.conn, &doc.id, "underline", "square").unwrap(), 0);
+}
+
+// ============ Mermaid 导出 ============
+
+#[test]
+fn mermaid_export_has_header_and_one_node_per_annotation() {
+    let db = TestDb::new();
+    let doc = db.document("mind.md", "alpha beta gamma");
+    db.annotate_as(&doc, "alpha", "Ada");
+    db.annotate_as(&doc, "beta", "Bob");
+    db.annotate_as(&doc, "gamma", "Ada");
+
+    let diagram = export_as_mermaid(&db.conn, &doc.id, &ExportFilter::default()).unwrap();
+    let lines: Vec<&str> = diagram.lines().collect();
+
+    assert_eq!(lines[0], "mindmap");
+    assert_eq!(lines[1], "  root((\"mind.md\"))");
+    assert_eq!(lines.iter().filter(|l| l.trim_start().starts_with("author")).count(), 2);
+    assert_eq!(lines.iter().filter(|l| l.trim_start().starts_with("note")).count(), 3);
+}
+
+// ============ 注解包往返 ============
+
+#[test]
+fn threaded_annotation_survives_export_and_import() {
+    let source = TestDb::new();
+    let doc = source.document("thread.md", "a threaded passage");
+    let anno = source.annotate_note(&doc, "threaded", "root note");
+    let question = add_comment(&source.conn, &anno.id, "question?", None).unwrap();
+    add_comment(&source.conn, &anno.id, "answer!", Some(&question.id)).unwrap();
+
+    let json = export_annotation(&source.conn, &anno.id, &doc.path, None).unwrap();
+    let imported = import_annotation(&json, None).unwrap();
+
+    // 目标库中没有来源库的用户，按作者名映射到本地用户
+    let target = TestDb::new();
+    let target_doc = target.document("thread.md", "a threaded passage");
+    let imported = resolve_import_users(&target.conn, imported).unwrap();
+    assert_eq!(merge_imported_annotations(&target.conn, &imported, &target_doc.id).unwrap(), 1);
+
+    let restored = &get_annotations_by_doc(&target.conn, &target_doc.id).unwrap()[0];
+    assert_eq!(restored.note.as_deref(), Some("root note"));
+    let comments = get_comments_by_annotation(&target.conn, &restored.id).unwrap();
+    assert_eq!(comments.len(), 2);
+    let root = comments.iter().find(|c| c.body == "question?").unwrap();
+    let reply = comments.iter().find(|c| c.body == "answer!").unwrap();
+    assert_eq!(root.parent_id, None);
+    assert_eq!(reply.parent_id.as_deref(), Some(root.id.as_str()));
+}
+
+#[test]
+fn html_export_timing_is_populated_when_requested() {
+    let db = TestDb::new();
+    let doc = db.document("timing.md", "one two three four");
+    let ids: Vec<String> = ["one", "two", "three", "four"]
+        .iter()
+        .map(|word| db.annotate_text(&doc, word).id)
+        .collect();
+
+    let options = HtmlExportOptions { debug_timing: true, ..Default::default() };
+    let (_, timing) = export_as_html(&db.conn, &doc.id, &ids, &doc.content, &options).unwrap();
+    let timing = timing.unwrap();
+    assert_eq!(timing.annotation_count, 4);
+    assert!(timing.load_annotations_ms > 0.0);
+    assert!(timing.inject_highlights_ms > 0.0);
+    assert!(timing.render_ms > 0.0);
+
+    let (_, timing) = export_as_html(&db.conn, &doc.id, &ids, &doc.content, &HtmlExportOptions::default()).unwrap();
+    assert!(timing.is_none());
+}
+
+// ============ 文档路径规范化 ============
+
+#[test]
+fn windows_paths_normalize_drive_and_separators() {
+    assert_eq!(normalize_windows_path("C:/Docs/Doc.md"), "c:\\Docs\\Doc.md");
+    assert_eq!(normalize_windows_path("c:\\Docs\\Doc.md"), "c:\\Docs\\Doc.md");
+    assert_eq!(normalize_windows_path("\\\\server\\share\\a.md"), "\\\\server\\share\\a.md");
+}
+
+#[test]
+fn document_paths_resolve_per_platform() {
+    let db = TestDb::new();
+    let upper = save_document(&db.conn, "C:\\Notes\\Doc.md", "content").unwrap();
+    let lower = save_document(&db.conn, "c:/notes/doc.md", "content").unwrap();
+
+    if cfg!(windows) {
+        // 大小写与分隔符不同的写法指向同一记录
+        assert_eq!(upper.id, lower.id);
+    } else {
+        // 区分大小写的文件系统上保持原样，各自一条记录
+        assert_ne!(upper.id, lower.id);
+        assert_eq!(get_document_by_path(&db.conn, "c:/notes/doc.md").unwrap().unwrap().id, lower.id);
+        assert!(get_document_by_path(&db.conn, "c:/Notes/Doc.md").unwrap().is_none());
+    }
+}
+
+// ============ 按范围创建注解 ============
+
+#[test]
+fn create_annotation_from_valid_range() {
+    let db = TestDb::new();
+    let path = db.path("range.md");
+    let content = "hello 世界 again";
+
+    let anno = create_annotation(&db.conn, &path, 6, 12, content, Some("note".to_string()), Some("#87ceeb".to_string())).unwrap();
+    assert_eq!(anno.text, "世界");
+    assert_eq!(anno.note.as_deref(), Some("note"));
+    assert_eq!(anno.highlight_color, "#87ceeb");
+
+    // 文档不存在时自动入库
+    let doc = get_document_by_path(&db.conn, &path).unwrap().unwrap();
+    assert_eq!(doc.id, anno.document_id);
+    assert_eq!(locate_annotation(&anno, content), Some((6, 12)));
+}
+
+#[test]
+fn create_annotation_rejects_invalid_ranges() {
+    let db = TestDb::new();
+    let path = db.path("range.md");
+    let content = "hello 世界";
+
+    assert!(create_annotation(&db.conn, &path, 3, 3, content, None, None).is_err());
+    assert!(create_annotation(&db.conn, &path, 4, 2, content, None, None).is_err());
+    assert!(create_annotation(&db.conn, &path, 0, 100, content, None, None).is_err());
+    // 7 位于“世”的 UTF-8 编码中间
+    assert!(create_annotation(&db.conn, &path, 7, 12, content, None, None).is_err());
+    // 颜色与 add_annotation 命令同样解析：非法颜色被拒绝，预设名称转换为十六进制
+    assert!(create_annotation(&db.conn, &path, 0, 5, content, None, Some("no-such-color".to_string())).is_err());
+    assert!(get_document_by_path(&db.conn, &path).unwrap().is_none());
+
+    let anno = create_annotation(&db.conn, &path, 0, 5, content, None, Some("Blue".to_string())).unwrap();
+    assert_eq!(anno.highlight_color, "#87cefa");
+}
+
+// ============ 按颜色过滤导出 ============
+
+#[test]
+fn export_filters_to_gold_annotations() {
+    let db = TestDb::new();
+    let doc = db.document("colors.md", "gold one\nblue two\ngold three\n");
+    db.annotate_text(&doc, "gold one");
+    let mut blue = db.build(&doc, 9, 17);
+    blue.highlight_color = "#87ceeb".to_string();
+    add_annotation(&db.conn, &blue).unwrap();
+    db.annotate_text(&doc, "gold three");
+
+    // 颜色按 RGB 比较，大小写与透明度写法不影响匹配
+    let gold = ExportFilter { colors: vec!["#ffd700FF".to_string()], ..ExportFilter::default() };
+    let json = export_as_github_review(&db.conn, &doc.id, &gold).unwrap();
+    let comments: Vec<ReviewComment> = serde_json::from_str(&json).unwrap();
+    let bodies: Vec<&str> = comments.iter().map(|c| c.body.as_str()).collect();
+    assert_eq!(bodies, vec!["> gold one", "> gold three"]);
+
+    // 未指定颜色时导出全部，非法颜色报错
+    let all: Vec<ReviewComment> = serde_json::from_str(&export_as_github_review(&db.conn, &doc.id, &ExportFilter::default()).unwrap()).unwrap();
+    assert_eq!(all.len(), 3);
+    let named = ExportFilter { colors: vec!["gold".to_string()], ..ExportFilter::default() };
+    assert!(export_as_github_review(&db.conn, &doc.id, &named).is_err());
+}
+
+#[test]
+fn every_export_format_honours_the_filter() {
+    let db = TestDb::new();
+    let doc = db.document("filtered.md", "gold one\nblue two\n");
+    db.annotate_note(&doc, "gold one", "gold note");
+    let mut blue = db.build(&doc, 9, 17);
+    blue.highlight_color = "#87CEEB".to_string();
+    blue.note = Some("blue note".to_string());
+    add_annotation(&db.conn, &blue).unwrap();
+    let ids: Vec<String> = get_annotations_by_doc(&db.conn, &doc.id).unwrap().into_iter().map(|a| a.id).collect();
+
+    // HTML 与 Markdown 带有全文，按注解 id 与便签内容判断
+    let filter = ExportFilter { colors: vec!["#87ceeb".to_string()], ..ExportFilter::default() };
+    let html = export_by_format(&db.conn, "html", &doc.id, &ids, &doc.content, &filter).unwrap();
+    assert!(html.contains(&blue.id));
+    assert!(!html.contains(&ids.iter().find(|id| **id != blue.id).unwrap()[..]));
+    for format in ["md", "cornell", "index-cards", "bib", "kanban"] {
+        let output = export_by_format(&db.conn, format, &doc.id, &ids, &doc.content, &filter).unwrap();
+        assert!(output.contains("blue note"), "{}", format);
+        assert!(!output.contains("gold note"), "{}", format);
+    }
+    let heat: Vec<HeatPoint> = serde_json::from_str(&export_by_format(&db.conn, "heatmap", &doc.id, &ids, &doc.content, &filter).unwrap()).unwrap();
+    assert_eq!(heat.len(), 1);
+
+    let saved = export_and_save(&db.conn, &doc.id, &ids, &doc.content, &db.path("blue.md"), "md", &filter).unwrap();
+    assert!(!fs::read_to_string(saved).unwrap().contains("gold note"));
+    assert!(!export_for_llm(&db.conn, &doc.id, 100, &filter).unwrap().contains("gold note"));
+    assert_eq!(export_with_template(&db.conn, &doc.id, "{text}", &filter).unwrap().trim(), "blue two");
+
+    let out = db.dir.join("chunks");
+    let paths = export_annotations_chunked(&db.conn, &doc.id, 10, &out.to_string_lossy(), &filter).unwrap();
+    let package: serde_json::Value = serde_json::from_str(&fs::read_to_string(&paths[0]).unwrap()).unwrap();
+    assert_eq!(package["annotations"].as_array().unwrap().len(), 1);
+
+    // 作者过滤对同一组导出同样生效
+    let nobody = ExportFilter { authors: vec!["Nobody".to_string()], ..ExportFilter::default() };
+    let board: Vec<KanbanColumn> = serde_json::from_str(&export_as_kanban(&db.conn, &doc.id, &nobody).unwrap()).unwrap();
+    assert!(board.iter().all(|column| column.cards.is_empty()));
+}
+
+// ============ 文档相似度 ============
+
+#[test]
+fn document_similarity_identical_disjoint_and_partial() {
+    let db = TestDb::new();
+    let a = db.document("a.md", "one two three four five");
+    let same = db.document("same.md", "One, two. Three four five!");
+    let disjoint = db.document("disjoint.md", "six seven eight nine ten");
+    let partial = db.document("partial.md", "one two three four ten");
+
+    assert_eq!(document_similarity(&db.conn, &a.path, &same.path).unwrap(), 1.0);
+    assert_eq!(document_similarity(&db.conn, &a.path, &disjoint.path).unwrap(), 0.0);
+    // 三元组 {123,234,345} 与 {123,234,34T}：交 2 并 4
+    assert_eq!(document_similarity(&db.conn, &a.path, &partial.path).unwrap(), 0.5);
+    assert!(document_similarity(&db.conn, &a.path, &db.path("missing.md")).is_err());
+}
+
+// ============ 追加内容 ============
+
+#[test]
+fn append_keeps_existing_anchors_resolvable() {
+    let db = TestDb::new();
+    let doc = db.document("append.md", "first paragraph\n");
+    let anno = db.annotate_text(&doc, "paragraph");
+
+    let updated = append_to_document(&db.conn, &doc.path, "second paragraph\n", default_max_document_bytes()).unwrap();
+    assert_eq!(updated.content, "first paragraph\nsecond paragraph\n");
+    assert_eq!(fs::read_to_string(&doc.path).unwrap(), updated.content);
+    assert_eq!(updated.checksum, compute_checksum(&updated.content));
+
+    let stored = get_document_by_id(&db.conn, &doc.id).unwrap().unwrap();
+    assert_eq!(stored.content, updated.content);
+    assert_eq!(locate_annotation(&anno, &stored.content), Some((6, 15)));
+    assert_eq!(audit_annotation_consistency(&db.conn, &doc.id, &stored.content).unwrap().len(), 0);
+}
+
+#[test]
+fn append_stores_the_file_content_on_disk() {
+    let db = TestDb::new();
+    let doc = db.document("drift.md", "stored\n");
+    // 文件在外部被修改，数据库中的内容已过期
+    fs::write(&doc.path, "edited outside\n").unwrap();
+
+    let updated = append_to_document(&db.conn, &doc.path, "tail\n", default_max_document_bytes()).unwrap();
+    assert_eq!(updated.content, "edited outside\ntail\n");
+    assert_eq!(get_document_by_id(&db.conn, &doc.id).unwrap().unwrap().content, updated.content);
+}
+
+// ============ 评论树 ============
+
+#[test]
+fn annotation_thread_nests_two_levels() {
+    let db = TestDb::new();
+    let doc = db.document("thread.md", "discussed text");
+    let anno = db.annotate_text(&doc, "discussed");
+    let root = add_comment(&db.conn, &anno.id, "root", None).unwrap();
+    add_comment(&db.conn, &anno.id, "reply", Some(&root.id)).unwrap();
+    add_comment(&db.conn, &anno.id, "another root", None).unwrap();
+
+    let thread = get_annotation_thread(&db.conn, &anno.id).unwrap();
+    assert_eq!(thread.annotation.id, anno.id);
+    assert_eq!(thread.comments.len(), 2);
+
+    let root_node = thread.comments.iter().find(|n| n.comment.id == root.id).unwrap();
+    assert_eq!(root_node.replies.len(), 1);
+    assert_eq!(root_node.replies[0].comment.body, "reply");
+    assert!(root_node.replies[0].replies.is_empty());
+    let other = thread.comments.iter().find(|n| n.comment.body == "another root").unwrap();
+    assert!(other.replies.is_empty());
+
+    // 回复只能指向同一注解下的评论
+    let elsewhere = db.annotate_text(&doc, "text");
+    assert!(add_comment(&db.conn, &elsewhere.id, "bad", Some(&root.id)).is_err());
+}
+
+// ============ 文档快照 ============
+
+#[test]
+fn snapshot_create_list_and_restore() {
+    let db = TestDb::new();
+    let doc = db.document("snap.md", "version one");
+    let kept = db.annotate_note(&doc, "one", "original note");
+
+    let snapshot = create_snapshot(&db.conn, &doc.path, "before edit").unwrap();
+    assert_eq!(snapshot.annotation_count, 1);
+
+    // 修改文档与注解
+    fs::write(&doc.path, "version two").unwrap();
+    let edited = save_document(&db.conn, &doc.path, "version two").unwrap();
+    delete_annotation(&db.conn, &kept.id).unwrap();
+    let replaced = db.annotate_text(&edited, "two");
+
+    // 文件无法替换时数据库保持不变
+    fs::remove_file(&doc.path).unwrap();
+    fs::create_dir(&doc.path).unwrap();
+    assert!(restore_snapshot(&db.conn, &snapshot.id).is_err());
+    assert_eq!(get_document_by_id(&db.conn, &doc.id).unwrap().unwrap().content, "version two");
+    assert_eq!(get_annotations_by_doc(&db.conn, &doc.id).unwrap()[0].id, replaced.id);
+    fs::remove_dir(&doc.path).unwrap();
+    fs::write(&doc.path, "version two").unwrap();
+
+    let listed = list_snapshots(&db.conn, &doc.path).unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].id, snapshot.id);
+    assert_eq!(listed[0].label, "before edit");
+
+    let restored = restore_snapshot(&db.conn, &snapshot.id).unwrap();
+    assert_eq!(restored.content, "version one");
+    assert_eq!(fs::read_to_string(&doc.path).unwrap(), "version one");
+    let annotations = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    assert_eq!(annotations.len(), 1);
+    assert_eq!(annotations[0].text, "one");
+    assert_eq!(annotations[0].note.as_deref(), Some("original note"));
+
+    // 撤销后文档、文件与注解都回到恢复前的状态
+    assert_eq!(undo_last_operation(&db.conn).unwrap().as_deref(), Some("restore_snapshot"));
+    assert_eq!(get_document_by_id(&db.conn, &doc.id).unwrap().unwrap().content, "version two");
+    assert_eq!(fs::read_to_string(&doc.path).unwrap(), "version two");
+    let annotations = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    assert_eq!(annotations.len(), 1);
+    assert_eq!(annotations[0].id, replaced.id);
+}
+
+// ============ 默认导出目录 ============
+
+#[test]
+fn relative_export_names_land_in_configured_dir() {
+    let db = TestDb::new();
+    let exports = db.dir.join("configured-exports");
+    let _settings = edit_settings(|s| s.export.default_dir = Some(exports.to_string_lossy().to_string()));
+
+    assert_eq!(resolve_export_path("notes/out.md").unwrap(), exports.join("notes/out.md"));
+    assert!(exports.join("notes").is_dir());
+
+    // 绝对路径不受影响
+    let absolute = db.dir.join("elsewhere.md");
+    assert_eq!(resolve_export_path(&absolute.to_string_lossy()).unwrap(), absolute);
+}
+
+// ============ 文档格式检测 ============
+
+#[test]
+fn detects_representative_formats() {
+    let markdown = "# Title\n\nSome text with a [link](https://example.com).\n\n```rust\nfn main() {}\n```\n";
+    assert_eq!(detect_document_format(markdown), DocumentFormat::Markdown);
+
+    let html = "<div class=\"page\">\n  <p>Hello</p>\n  <p>World</p>\n</div>\n";
+    assert_eq!(detect_document_format(html), DocumentFormat::Html);
+    assert_eq!(detect_document_format("<!DOCTYPE html>\n<html></html>"), DocumentFormat::Html);
+
+    let plain = "Just a few sentences of prose.\nNothing special here.\n";
+    assert_eq!(detect_document_format(plain), DocumentFormat::PlainText);
+
+    assert_eq!(detect_document_format("   \n"), DocumentFormat::Unknown);
+    assert_eq!(detect_document_format("PK\u{3}\u{4}\0\0binary"), DocumentFormat::Unknown);
+}
+
+// ============ 重复注解合并 ============
+
+#[test]
+fn dedupe_collapses_two_duplicates() {
+    let db = TestDb::new();
+    let doc = db.document("dup.md", "some duplicated text here");
+    let mut first = db.build(&doc, 5, 15);
+    first.note = Some("first note".to_string());
+    first.created_at = 100;
+    add_annotation(&db.conn, &first).unwrap();
+    let mut second = db.build(&doc, 5, 15);
+    second.note = Some("second note".to_string());
+    second.created_at = 200;
+    add_annotation(&db.conn, &second).unwrap();
+    let other = db.annotate_text(&doc, "here");
+
+    assert_eq!(dedupe_annotations(&db.conn, &doc.id).unwrap(), 1);
+
+    let remaining = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    assert_eq!(remaining.len(), 2);
+    let keeper = remaining.iter().find(|a| a.id == first.id).unwrap();
+    assert_eq!(keeper.created_at, 100);
+    assert_eq!(keeper.note.as_deref(), Some("first note\n\nsecond note"));
+    assert!(remaining.iter().any(|a| a.id == other.id));
+    assert_eq!(dedupe_annotations(&db.conn, &doc.id).unwrap(), 0);
+
+    // 被合并的重复项可以撤销恢复
+    assert_eq!(undo_last_operation(&db.conn).unwrap().as_deref(), Some("dedupe_annotations"));
+    assert_eq!(get_annotation_by_id(&db.conn, &second.id).unwrap().unwrap().note.as_deref(), Some("second note"));
+}
+
+// ============ 热力数据导出 ============
+
+#[test]
+fn heatmap_json_has_one_entry_per_anchored_annotation() {
+    let db = TestDb::new();
+    let doc = db.document("heat.md", "alpha beta gamma");
+    db.annotate_text(&doc, "alpha");
+    db.annotate_note(&doc, "gamma", &"x".repeat(200));
+    // 文本已不在文档中且无原文锚点，无法定位
+    let mut lost = db.build(&doc, 6, 10);
+    lost.text = "missing".to_string();
+    lost.anchor_data = "[]".to_string();
+    add_annotation(&db.conn, &lost).unwrap();
+
+    let points: Vec<HeatPoint> = serde_json::from_str(&export_heatmap_json(&db.conn, &doc.id, &ExportFilter::default()).unwrap()).unwrap();
+    assert_eq!(points.len(), 2);
+    assert_eq!((points[0].offset, points[0].length), (0, 5));
+    assert_eq!(points[0].weight, 1.0);
+    assert_eq!((points[1].offset, points[1].length), (11, 5));
+    assert_eq!(points[1].weight, 3.0);
+}
+
+// ============ 引用完整性 ============
+
+#[test]
+fn integrity_check_reports_and_fixes_dangling_user() {
+    let db = TestDb::new();
+    let doc = db.document("orphan.md", "orphaned text");
+    let orphan = db.annotate_text(&doc, "orphaned");
+    let healthy = db.annotate_text(&doc, "text");
+    assert!(check_referential_integrity(&db.conn).unwrap().annotations_missing_user.is_empty());
+
+    // 绕过外键约束制造悬空引用
+    db.conn.execute_batch("PRAGMA foreign_keys = OFF").unwrap();
+    db.conn.execute("UPDATE annotations SET user_id = 'ghost', user_name = 'Ghost' WHERE id = ?", [&orphan.id]).unwrap();
+
+    let issues = check_referential_integrity(&db.conn).unwrap();
+    assert_eq!(issues.annotations_missing_user, vec![orphan.id.clone()]);
+    assert!(issues.annotations_missing_document.is_empty());
+    assert!(issues.foreign_key_violations.iter().any(|v| v.table == "annotations" && v.parent == "users"));
+
+    // 补建占位用户后注解保留
+    assert_eq!(fix_referential_integrity(&db.conn, IntegrityFixStrategy::Reassign).unwrap(), 1);
+    let issues = check_referential_integrity(&db.conn).unwrap();
+    assert!(issues.annotations_missing_user.is_empty());
+    assert!(issues.foreign_key_violations.is_empty());
+    assert_eq!(get_annotations_by_doc(&db.conn, &doc.id).unwrap().len(), 2);
+
+    // 删除策略只移除孤立注解
+    db.conn.execute("UPDATE annotations SET user_id = 'ghost-2' WHERE id = ?", [&orphan.id]).unwrap();
+    assert_eq!(fix_referential_integrity(&db.conn, IntegrityFixStrategy::Delete).unwrap(), 1);
+    let remaining = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].id, healthy.id);
+}
+
+// ============ Obsidian 导出 ============
+
+#[test]
+fn obsidian_export_uses_highlight_and_callout_syntax() {
+    let db = TestDb::new();
+    let content = "First paragraph with a key idea.\n\nSecond paragraph.";
+    let doc = db.document("obsidian.md", content);
+    db.annotate_note(&doc, "key idea", "worth remembering");
+
+    let output = export_as_obsidian(&db.conn, &doc.id, content, &ExportFilter::default()).unwrap();
+    assert_eq!(
+        output,
+        "First paragraph with a ==key idea==.\n\n> [!note] admin\n> worth remembering\n\nSecond paragraph."
+    );
+}
+
+// ============ W3C Web Annotation 导入 ============
+
+#[test]
+fn imports_minimal_web_annotation_payloads() {
+    let db = TestDb::new();
+    let doc = db.document("web.md", "the cat sat on the mat");
+
+    let single = r#"{
+        "@context": "http://www.w3.org/ns/anno.jsonld",
+        "type": "Annotation",
+        "body": {"type": "TextualBody", "value": "which mat?"},
+        "target": {"selector": {"type": "TextQuoteSelector", "exact": "mat", "prefix": "the "}}
+    }"#;
+    assert_eq!(import_web_annotations(&db.conn, single, &doc.path).unwrap(), 1);
+
+    let page = r#"{
+        "type": "AnnotationPage",
+        "items": [
+            {"type": "Annotation", "creator": {"name": "Reader"},
+             "target": {"selector": [{"type": "TextQuoteSelector", "exact": "cat"}]}},
+            {"type": "Annotation", "target": {"selector": {"type": "TextQuoteSelector", "exact": "dog"}}}
+        ]
+    }"#;
+    assert_eq!(import_web_annotations(&db.conn, page, &doc.path).unwrap(), 1);
+
+    let annotations = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    let mat = annotations.iter().find(|a| a.text == "mat").unwrap();
+    assert_eq!(mat.note.as_deref(), Some("which mat?"));
+    assert_eq!(locate_annotation(mat, &doc.content), Some((19, 22)));
+
+    // 作者映射到本地用户，外键有效
+    let cat = annotations.iter().find(|a| a.text == "cat").unwrap();
+    assert_eq!(cat.user_name, "Reader");
+    assert_eq!(find_or_create_user_by_name(&db.conn, "Reader").unwrap().id, cat.user_id);
+}
+
+// ============ 可移植设置 ============
+
+#[test]
+fn portable_settings_import_keeps_local_user_id() {
+    let db = TestDb::new();
+    let exports = db.dir.join("exports").to_string_lossy().to_string();
+    let _settings = edit_settings(|s| {
+        s.user.id = "local-user-id".to_string();
+        s.export.default_dir = Some(exports.clone());
+    });
+
+    let portable = export_portable_settings().unwrap();
+    assert!(!portable.contains("local-user-id"));
+    assert!(!portable.contains(&exports));
+
+    // 来自另一台机器的设置带着别的用户 ID
+    let mut foreign: serde_json::Value = serde_json::from_str(&portable).unwrap();
+    foreign["user"]["id"] = serde_json::json!("other-machine-id");
+    foreign["editor"]["font_size"] = serde_json::json!(21);
+    let imported = import_portable_settings(&foreign.to_string()).unwrap();
+
+    assert_eq!(imported.user.id, "local-user-id");
+    let saved = load_settings().unwrap();
+    assert_eq!(saved.user.id, "local-user-id");
+    assert_eq!(saved.editor.font_size, 21);
+    assert_eq!(saved.export.default_dir.as_deref(), Some(exports.as_str()));
+}
+
+// ============ 批量重新锚定 ============
+
+#[test]
+fn reanchor_project_reports_each_document() {
+    let db = TestDb::new();
+    let project = db.dir.join("project");
+    fs::create_dir_all(&project).unwrap();
+    let moved = db.document("project/moved.md", "target sentence");
+    let moved_anno = db.annotate_text(&moved, "target");
+    let stable = db.document("project/stable.md", "nothing changes");
+    db.annotate_text(&stable, "nothing");
+    let outside = db.document("outside.md", "not in the project");
+    db.annotate_text(&outside, "project");
+
+    // 批量替换后文本整体后移
+    fs::write(&moved.path, "A new intro. target sentence").unwrap();
+
+    let reports = reanchor_project(&db.conn, &project.to_string_lossy()).unwrap();
+    assert_eq!(reports.len(), 2);
+    let moved_report = &reports[&moved.path];
+    assert_eq!((moved_report.total, moved_report.relocated, moved_report.unchanged), (1, 1, 0));
+    let stable_report = &reports[&stable.path];
+    assert_eq!((stable_report.total, stable_report.relocated, stable_report.unchanged), (1, 0, 1));
+
+    let reloaded = get_document_by_id(&db.conn, &moved.id).unwrap().unwrap();
+    assert_eq!(reloaded.content, "A new intro. target sentence");
+    let anno = get_annotation_by_id(&db.conn, &moved_anno.id).unwrap().unwrap();
+    assert_eq!(locate_annotation(&anno, &reloaded.content), Some((13, 19)));
+}
+
+// ============ 文档大小上限 ============
+
+#[test]
+fn oversized_document_triggers_warning() {
+    let limit = 64 * 1024;
+    let err = check_document_size(limit + 1, limit).unwrap_err();
+    assert!(err.starts_with(DOCUMENT_TOO_LARGE_ERROR));
+    assert!(err.contains(&format!("{}/{}", limit + 1, limit)));
+    assert!(check_document_size(limit, limit).is_ok());
+
+    // 追加超限时不写入磁盘
+    let db = TestDb::new();
+    let doc = db.document("growing.md", "small");
+    let err = append_to_document(&db.conn, &doc.path, "more", 8).unwrap_err();
+    assert_eq!(err, format!("{}: 9/8", DOCUMENT_TOO_LARGE_ERROR));
+    assert_eq!(fs::read_to_string(&doc.path).unwrap(), "small");
+}
+
+// ============ 待写笔记 ============
+
+#[test]
+fn unnoted_annotations_exclude_noted_ones() {
+    let db = TestDb::new();
+    let doc = db.document("todo.md", "first second third fourth");
+    let third = db.annotate_text(&doc, "third");
+    db.annotate_note(&doc, "second", "already noted");
+    let first = db.annotate_text(&doc, "first");
+    let blank = db.annotate_note(&doc, "fourth", "   ");
+
+    let ids: Vec<String> = get_unnoted_annotations(&db.conn, &doc.id).unwrap()
+        .into_iter()
+        .map(|a| a.id)
+        .collect();
+    assert_eq!(ids, vec![first.id, third.id, blank.id]);
+}
+
+// ============ 便签着色 ============
+
+#[test]
+fn blue_highlight_tints_note_header_blue() {
+    let db = TestDb::new();
+    let doc = db.document("tint.md", "blue words");
+    let mut anno = db.build(&doc, 0, 4);
+    anno.highlight_color = "#1e66f5".to_string();
+    anno.note = Some("tinted".to_string());
+    add_annotation(&db.conn, &anno).unwrap();
+
+    let (background, header, foreground) = note_tint("#1e66f5").unwrap();
+    let (r, g, b) = parse_hex_color(&header).unwrap();
+    assert!(b > r && b > g);
+    assert_eq!(foreground, "#333");
+    assert!(note_tint("not-a-color").is_none());
+
+    let (html, _) = export_as_html(&db.conn, &doc.id, &[anno.id], &doc.content, &HtmlExportOptions::default()).unwrap();
+    assert!(html.contains(&format!(r#"<div class="note-header" style="background: {}; color: #333;">"#, header)));
+    assert!(html.contains(&format!("background: {};", background)));
+}
+
+// ============ 注解补丁 ============
+
+#[test]
+fn annotation_patch_round_trip() {
+    let source = TestDb::new();
+    let target = TestDb::new();
+    let content = "one two three four";
+    let source_doc = source.document("patch.md", content);
+    let target_doc = target.document("patch.md", content);
+
+    // 双方共同的基线
+    let user = get_or_create_user(&source.conn, "admin".to_string()).unwrap();
+    target.conn.execute(
+        "INSERT INTO users (id, name, created_at) VALUES (?, ?, ?)",
+        params![user.id, user.name, user.created_at],
+    ).unwrap();
+    let mut baseline = Vec::new();
+    for text in ["one", "two"] {
+        let mut anno = source.build(&source_doc, content.find(text).unwrap(), content.find(text).unwrap() + text.len());
+        anno.created_at = 100;
+        anno.updated_at = 100;
+        add_annotation(&source.conn, &anno).unwrap();
+        let mut copy = anno.clone();
+        copy.document_id = target_doc.id.clone();
+        add_annotation(&target.conn, &copy).unwrap();
+        baseline.push(anno);
+    }
+
+    // 基线之后：修改一条、删除一条、新增一条
+    let mut edited = baseline[0].clone();
+    edited.note = Some("edited".to_string());
+    edited.tags = vec!["review".to_string()];
+    update_annotation(&source.conn, &edited).unwrap();
+    set_annotation_tags(&source.conn, &edited.id, &edited.tags).unwrap();
+    delete_annotation(&source.conn, &baseline[1].id).unwrap();
+    let added = source.annotate_note(&source_doc, "four", "new");
+
+    let patch = export_annotation_patch(&source.conn, &source_doc.id, 1000).unwrap();
+    assert_eq!(apply_annotation_patch(&target.conn, &patch, &target_doc.path).unwrap(), 3);
+
+    let mut result = get_annotations_by_doc(&target.conn, &target_doc.id).unwrap();
+    result.sort_by_key(|a| a.created_at);
+    assert_eq!(result.len(), 2);
+    assert_eq!(result[0].id, edited.id);
+    assert_eq!(result[0].note.as_deref(), Some("edited"));
+    assert_eq!(result[0].tags, vec!["review".to_string()]);
+    assert_eq!(result[1].id, added.id);
+    assert_eq!(result[1].note.as_deref(), Some("new"));
+
+    // 恢复快照把当前注解记入撤销日志后以同一 ID 写回，补丁不能把它们列为已删除
+    let snapshot = create_snapshot(&source.conn, &source_doc.path, "sync").unwrap();
+    restore_snapshot(&source.conn, &snapshot.id).unwrap();
+    let patch = export_annotation_patch(&source.conn, &source_doc.id, 1000).unwrap();
+    let parsed: AnnotationPatch = serde_json::from_str(&patch).unwrap();
+    assert_eq!(parsed.deleted, [baseline[1].id.clone()]);
+    apply_annotation_patch(&target.conn, &patch, &target_doc.path).unwrap();
+    let ids: Vec<String> = get_annotations_by_doc(&target.conn, &target_doc.id).unwrap().into_iter().map(|a| a.id).collect();
+    assert_eq!(ids.len(), 2);
+    assert!(ids.contains(&edited.id) && ids.contains(&added.id));
+}
+
+// ============ 颜色预设 ============
+
+#[test]
+fn default_presets_and_name_resolution() {
+    let presets = default_color_presets();
+    assert_eq!(presets[0].name, "gold");
+    assert_eq!(presets[0].hex, "#ffd700");
+    assert!(presets.iter().all(|p| validate_color(&p.hex).is_ok()));
+
+    // 旧版设置文件没有 color_presets 字段时补上默认值
+    let legacy: EditorSettingsRecord = serde_json::from_str(
+        r##"{"default_highlight_color":"#ffd700","default_highlight_type":"underline","font_size":16,"font_family":"system-ui"}"##
+    ).unwrap();
+    assert_eq!(legacy.color_presets.len(), presets.len());
+
+    let _settings = edit_settings(|s| s.editor.color_presets.push(ColorPreset {
+        name: "Teal".to_string(),
+        hex: "#008080".to_string(),
+    }));
+    assert_eq!(get_color_presets().unwrap().last().unwrap().name, "Teal");
+    assert_eq!(resolve_highlight_color("Blue").unwrap(), "#87cefa");
+    assert_eq!(resolve_highlight_color(" teal ").unwrap(), "#008080");
+    assert_eq!(resolve_highlight_color("#123abc").unwrap(), "#123abc");
+    assert!(resolve_highlight_color("no-such-color").is_err());
+}
+
+// ============ 便签位置导出 ============
+
+#[test]
+fn note_positions_emit_one_feature_per_note() {
+    let db = TestDb::new();
+    let doc = db.document("map.md", "north south east west");
+    let mut north = db.build(&doc, 0, 5);
+    north.note = Some("cold".to_string());
+    north.note_position_x = 120.0;
+    north.note_position_y = 40.0;
+    add_annotation(&db.conn, &north).unwrap();
+    db.annotate_note(&doc, "south", "warm");
+    db.annotate_text(&doc, "east");
+
+    let collection: serde_json::Value = serde_json::from_str(&export_note_positions(&db.conn, &doc.id, &ExportFilter::default()).unwrap()).unwrap();
+    assert_eq!(collection["type"], "FeatureCollection");
+    let features = collection["features"].as_array().unwrap();
+    assert_eq!(features.len(), 2);
+    let first = features.iter().find(|f| f["properties"]["id"] == north.id.as_str()).unwrap();
+    assert_eq!(first["geometry"]["type"], "Point");
+    assert_eq!(first["geometry"]["coordinates"], serde_json::json!([120.0, 40.0]));
+    assert_eq!(first["properties"]["note"], "cold");
+    assert_eq!(first["properties"]["text"], "north");
+}
+
+// ============ 配置导入 ============
+
+#[test]
+fn import_config_copies_from_source_dir() {
+    let db = TestDb::new();
+    let _settings = edit_settings(|_| {});
+
+    let source = db.dir.join("other-profile");
+    fs::create_dir_all(&source).unwrap();
+    let mut settings = load_settings().unwrap();
+    settings.editor.font_size = 23;
+    fs::write(source.join("settings.json"), serde_json::to_string(&settings).unwrap()).unwrap();
+    fs::write(source.join("ui_settings.json"), r#"{"theme":"dark"}"#).unwrap();
+    fs::write(source.join("typography.yaml"), "body:\n  font_size: 18\n").unwrap();
+
+    let result = import_config_from_dir(&source.to_string_lossy());
+    let imported_ui = fs::read_to_string(get_ui_settings_path()).unwrap_or_default();
+    let imported_typography = fs::read_to_string(get_typography_path()).unwrap_or_default();
+    let imported_font_size = load_settings().unwrap().editor.font_size;
+
+    // 校验失败时不修改任何文件
+    let broken = db.dir.join("broken-profile");
+    fs::create_dir_all(&broken).unwrap();
+    fs::write(broken.join("ui_settings.json"), r#"{"theme":"light"}"#).unwrap();
+    fs::write(broken.join("typography.yaml"), "body:\n\tfont_size: 12\n").unwrap();
+    let broken_result = import_config_from_dir(&broken.to_string_lossy());
+    let ui_after_broken = fs::read_to_string(get_ui_settings_path()).unwrap_or_default();
+
+    result.unwrap();
+    assert_eq!(imported_font_size, 23);
+    assert_eq!(imported_ui, r#"{"theme":"dark"}"#);
+    assert_eq!(imported_typography, "body:\n  font_size: 18\n");
+    assert!(broken_result.unwrap_err().contains("typography.yaml"));
+    assert_eq!(ui_after_broken, r#"{"theme":"dark"}"#);
+}
+
+// ============ 覆盖率 ============
+
+#[test]
+fn coverage_uses_union_of_overlapping_ranges() {
+    let db = TestDb::new();
+    let content = "0123456789abcdefghij";
+    let doc = db.document("coverage.md", content);
+    db.annotate(&doc, 0, 6);
+    db.annotate(&doc, 4, 10);
+    // 无效锚点跳过
+    let mut invalid = db.build(&doc, 0, 1);
+    invalid.text = "not present".to_string();
+    invalid.anchor_data = "[]".to_string();
+    add_annotation(&db.conn, &invalid).unwrap();
+
+    assert_eq!(get_annotation_coverage(&db.conn, &doc.id, content).unwrap(), 0.5);
+    assert_eq!(get_annotation_coverage(&db.conn, &doc.id, "").unwrap(), 0.0);
+}
+
+// ============ 旧版注解 JSON ============
+
+#[test]
+fn legacy_annotation_json_deserializes() {
+    let minimal = r#"{"id":"a1","user_id":"u1","user_name":"old","text":"hi","anchor_data":"[]"}"#;
+    let anno: AnnotationRecord = serde_json::from_str(minimal).unwrap();
+    assert_eq!(anno.document_id, "");
+    assert!(!anno.note_visible);
+    assert_eq!((anno.note_width, anno.note_height), (280.0, 180.0));
+    assert_eq!(anno.highlight_color, "#ffd700");
+    assert_eq!(anno.highlight_type, "underline");
+    assert!(anno.tags.is_empty());
+
+    // 早期导出中 note_visible 为整数
+    let integer = r#"{"id":"a2","user_id":"u1","user_name":"old","text":"hi","anchor_data":"[]","note_visible":1}"#;
+    assert!(serde_json::from_str::<AnnotationRecord>(integer).unwrap().note_visible);
+    let boolean = r#"{"id":"a3","user_id":"u1","user_name":"old","text":"hi","anchor_data":"[]","note_visible":false}"#;
+    assert!(!serde_json::from_str::<AnnotationRecord>(boolean).unwrap().note_visible);
+}
+
+// ============ 活动时间线 ============
+
+#[test]
+fn activity_timeline_fills_gap_days() {
+    let db = TestDb::new();
+    let doc = db.document("timeline.md", "a b c");
+    let at = |day: u32, hour: u32| Local.with_ymd_and_hms(2024, 3, day, hour, 0, 0).unwrap().timestamp_millis();
+
+    for (start, created, updated) in [(0, at(1, 9), at(1, 9)), (2, at(1, 18), at(3, 10)), (4, at(3, 8), at(3, 8))] {
+        let mut anno = db.build(&doc, start, start + 1);
+        anno.created_at = created;
+        add_annotation(&db.conn, &anno).unwrap();
+        // add_annotation 总是把 updated_at 写为当前时间
+        db.conn.execute("UPDATE annotations SET updated_at = ? WHERE id = ?", params![updated, anno.id]).unwrap();
+    }
+
+    let timeline = get_activity_timeline(&db.conn, at(1, 0), at(3, 23)).unwrap();
+    let summary: Vec<(&str, usize, usize)> = timeline.iter()
+        .map(|d| (d.date.as_str(), d.created, d.updated))
+        .collect();
+    assert_eq!(summary, vec![("2024-03-01", 2, 0), ("2024-03-02", 0, 0), ("2024-03-03", 1, 1)]);
+
+    assert!(get_activity_timeline(&db.conn, at(3, 0), at(1, 0)).is_err());
+}
+
+// ============ 注解拆分 ============
+
+#[test]
+fn split_produces_two_adjacent_ranges() {
+    let db = TestDb::new();
+    let content = "first idea. second idea.";
+    let doc = db.document("split.md", content);
+    let original = db.annotate_note(&doc, "first idea. second idea", "two thoughts");
+    add_comment(&db.conn, &original.id, "kept on the first half", None).unwrap();
+
+    assert!(split_annotation(&db.conn, &original.id, 0, content).is_err());
+    assert!(split_annotation(&db.conn, &original.id, 23, content).is_err());
+
+    let (first, second) = split_annotation(&db.conn, &original.id, 12, content).unwrap();
+    assert_eq!(first.text, "first idea. ");
+    assert_eq!(second.text, "second idea");
+    assert_eq!(locate_annotation(&first, content), Some((0, 12)));
+    assert_eq!(locate_annotation(&second, content), Some((12, 23)));
+    assert_eq!(second.note.as_deref(), Some("two thoughts"));
+
+    assert!(get_annotation_by_id(&db.conn, &original.id).unwrap().is_none());
+    assert_eq!(get_annotations_by_doc(&db.conn, &doc.id).unwrap().len(), 2);
+
+    // 补丁把原注解列为已删除
+    let patch: AnnotationPatch = serde_json::from_str(&export_annotation_patch(&db.conn, &doc.id, 0).unwrap()).unwrap();
+    assert_eq!(patch.deleted, std::slice::from_ref(&original.id));
+
+    // 撤销后只剩原注解及其评论
+    assert_eq!(undo_last_operation(&db.conn).unwrap().as_deref(), Some("split_annotation"));
+    let annotations = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    assert_eq!(annotations.len(), 1);
+    assert_eq!(annotations[0].id, original.id);
+    assert_eq!(get_comments_by_annotation(&db.conn, &original.id).unwrap().len(), 1);
+}
+
+// ============ SQLite 导出 ============
+
+#[test]
+fn sqlite_export_is_a_valid_database_with_rows() {
+    let db = TestDb::new();
+    let doc = db.document("research.md", "alpha beta");
+    let alpha = db.annotate_note(&doc, "alpha", "first");
+    add_comment(&db.conn, &alpha.id, "discussion", None).unwrap();
+    db.annotate_as(&doc, "beta", "Researcher");
+
+    let dest = db.path("export.sqlite");
+    export_annotations_sqlite(&db.conn, &dest).unwrap();
+    assert!(!std::path::Path::new(&format!("{}.tmp", dest)).exists());
+
+    let out = Connection::open(&dest).unwrap();
+    let check: String = out.query_row("PRAGMA integrity_check", [], |row| row.get(0)).unwrap();
+    assert_eq!(check, "ok");
+    let mut stmt = out.prepare("SELECT text, document_path, user_name, note, comment_count FROM annotations ORDER BY text").unwrap();
+    let rows: Vec<(String, String, String, Option<String>, i64)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(rows, vec![
+        ("alpha".to_string(), doc.path.clone(), "admin".to_string(), Some("first".to_string()), 1),
+        ("beta".to_string(), doc.path.clone(), "Researcher".to_string(), None, 0),
+    ]);
+}
+
+// ============ 导出预览 ============
+
+#[test]
+fn preview_text_and_binary_formats_in_memory() {
+    let db = TestDb::new();
+    let content = "Preview this sentence.";
+    let doc = db.document("preview.md", content);
+    let anno = db.annotate_note(&doc, "sentence", "shown in preview");
+    let ids = vec![anno.id.clone()];
+    let listing = || {
+        let mut names: Vec<String> = fs::read_dir(&db.dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    };
+    let before = listing();
+
+    let html = preview_export(&db.conn, "html", &doc.id, &ids, content, &ExportFilter::default()).unwrap();
+    assert!(html.contains("<html"));
+    assert!(html.contains("shown in preview"));
+
+    let markdown = preview_export(&db.conn, "MD", &doc.id, &ids, content, &ExportFilter::default()).unwrap();
+    assert!(markdown.starts_with("Preview this ==sentence==."));
+    assert!(markdown.contains("> shown in preview"));
+
+    // 二进制格式以 data URI 返回
+    let epub = preview_export(&db.conn, "epub", &doc.id, &ids, content, &ExportFilter::default()).unwrap();
+    let encoded = epub.strip_prefix("data:application/epub+zip;base64,").unwrap();
+    assert!(BASE64.decode(encoded).unwrap().starts_with(b"PK\x03\x04"));
+    let pdf = preview_export(&db.conn, "pdf", &doc.id, &ids, content, &ExportFilter::default()).unwrap();
+    let encoded = pdf.strip_prefix("data:application/pdf;base64,").unwrap();
+    assert!(BASE64.decode(encoded).unwrap().starts_with(b"%PDF-"));
+
+    assert!(preview_export(&db.conn, "docx", &doc.id, &ids, content, &ExportFilter::default()).is_err());
+    assert_eq!(listing(), before);
+}
+
+// ============ 用户改名 ============
+
+#[test]
+fn rename_user_updates_existing_attribution() {
+    let db = TestDb::new();
+    let doc = db.document("rename.md", "mine and theirs");
+    let mine = db.annotate_text(&doc, "mine");
+    let theirs = db.annotate_as(&doc, "theirs", "Other");
+    add_comment(&db.conn, &mine.id, "a remark", None).unwrap();
+
+    update_user_name(&db.conn, &mine.user_id, "Renamed").unwrap();
+
+    assert_eq!(get_user_by_id(&db.conn, &mine.user_id).unwrap().unwrap().name, "Renamed");
+    assert_eq!(get_annotation_by_id(&db.conn, &mine.id).unwrap().unwrap().user_name, "Renamed");
+    assert_eq!(get_comments_by_annotation(&db.conn, &mine.id).unwrap()[0].user_name, "Renamed");
+    assert_eq!(get_annotation_by_id(&db.conn, &theirs.id).unwrap().unwrap().user_name, "Other");
+}
+
+// ============ Markdown 高亮导入 ============
+
+#[test]
+fn markdown_highlights_import_with_footnote() {
+    let db = TestDb::new();
+    let doc = db.document("source.md", "The quick brown fox jumps over the lazy dog.");
+    let md = "My notes: ==quick brown fox==[^1] and ==lazy dog==, plus ==purple cow==.\n\n\
+              [^1]: A classic pangram\n    used for typing practice.\n";
+
+    let report = import_from_markdown_highlights(&db.conn, md, &doc.path).unwrap();
+    assert_eq!(report.imported, 2);
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].text, "purple cow");
+    assert_eq!(report.skipped[0].position, 3);
+
+    let annotations = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    let fox = annotations.iter().find(|a| a.text == "quick brown fox").unwrap();
+    assert_eq!(fox.note.as_deref(), Some("A classic pangram\nused for typing practice."));
+    assert_eq!(locate_annotation(fox, &doc.content), Some((4, 19)));
+    let dog = annotations.iter().find(|a| a.text == "lazy dog").unwrap();
+    assert!(dog.note.is_none());
+}
+
+// ============ 合并配置 ============
+
+#[test]
+fn effective_config_has_all_namespaces_with_defaults() {
+    let _settings = edit_settings(|_| {});
+    let _ = fs::remove_file(get_ui_settings_path());
+    let _ = fs::remove_file(get_typography_path());
+
+    let config: serde_json::Value = serde_json::from_str(&get_effective_config().unwrap()).unwrap();
+    assert_eq!(config["settings"]["editor"]["default_highlight_type"], "underline");
+    assert_eq!(config["ui"]["theme"], "light");
+    assert_eq!(config["ui"]["sidebar_width"], 30);
+    assert_eq!(config["typography"]["is_default"], true);
+    assert_eq!(config["typography"]["content"], "");
+
+    // 已保存的界面设置覆盖默认值，未保存的键保留默认
+    fs::write(get_ui_settings_path(), r#"{"theme":"dark"}"#).unwrap();
+    let config: serde_json::Value = serde_json::from_str(&get_effective_config().unwrap()).unwrap();
+    assert_eq!(config["ui"]["theme"], "dark");
+    assert_eq!(config["ui"]["window_width"], 800);
+}
+
+// ============ 注解转移 ============
+
+#[test]
+fn reassign_moves_annotations_between_users() {
+    let db = TestDb::new();
+    let doc = db.document("reassign.md", "one two three");
+    // 先创建当前用户，get_or_create_user 返回表中的第一个用户
+    let kept = db.annotate_text(&doc, "three");
+    let first = db.annotate_as(&doc, "one", "Duplicate");
+    db.annotate_as(&doc, "two", "Duplicate");
+    let target = get_user_by_id(&db.conn, &kept.user_id).unwrap().unwrap();
+
+    assert!(reassign_annotations(&db.conn, "missing", &target.id).is_err());
+    assert!(reassign_annotations(&db.conn, &first.user_id, "missing").is_err());
+
+    assert_eq!(reassign_annotations(&db.conn, &first.user_id, &target.id).unwrap(), 2);
+    let annotations = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    assert!(annotations.iter().all(|a| a.user_id == target.id && a.user_name == target.name));
+    assert_eq!(reassign_annotations(&db.conn, &first.user_id, &target.id).unwrap(), 0);
+}
+
+// ============ 自定义样式 ============
+
+#[test]
+fn custom_css_is_appended_and_breakout_neutralized() {
+    let db = TestDb::new();
+    let doc = db.document("branded.md", "brand text");
+    let ids = vec![db.annotate_text(&doc, "brand").id];
+
+    let options = HtmlExportOptions {
+        custom_css: Some("body { font-family: Georgia; }\n</style><script>alert(1)</script>".to_string()),
+        ..Default::default()
+    };
+    let (html, _) = export_as_html(&db.conn, &doc.id, &ids, &doc.content, &options).unwrap();
+
+    let custom = html.find("<style id=\"custom-css\">").unwrap();
+    assert!(custom > html.find("<style>").unwrap());
+    assert!(html[custom..].contains("body { font-family: Georgia; }"));
+    assert!(!html.contains("</style><script>alert(1)"));
+    assert!(!html.contains("<script>alert(1)</script>"));
+
+    let (plain, _) = export_as_html(&db.conn, &doc.id, &ids, &doc.content, &HtmlExportOptions::default()).unwrap();
+    assert!(!plain.contains("custom-css"));
+}
+
+// ============ 区间内的注解 ============
+
+#[test]
+fn annotations_in_range_include_inside_and_overlapping() {
+    let db = TestDb::new();
+    let doc = db.document("range.md", "0123456789abcdefghij");
+    let inside = db.annotate(&doc, 6, 9);
+    let overlapping = db.annotate(&doc, 2, 6);
+    db.annotate(&doc, 12, 15); // 与区间末端相接，不相交
+    db.annotate(&doc, 16, 20);
+    let mut lost = db.build(&doc, 7, 8);
+    lost.text = "gone".to_string();
+    lost.anchor_data = "[]".to_string();
+    add_annotation(&db.conn, &lost).unwrap();
+
+    let mut ids: Vec<String> = get_annotations_in_range(&db.conn, &doc.id, 5, 12).unwrap()
+        .into_iter()
+        .map(|a| a.id)
+        .collect();
+    ids.sort();
+    let mut expected = vec![inside.id, overlapping.id];
+    expected.sort();
+    assert_eq!(ids, expected);
+
+    assert!(get_annotations_in_range(&db.conn, &doc.id, 12, 5).is_err());
+}
+
+// ============ 便签位置修正 ============
+
+#[test]
+fn clamp_note_positions_fixes_out_of_bounds() {
+    let db = TestDb::new();
+    let doc = db.document("bounds.md", "left right fine");
+    let positions = [("left", -50.0, 20.0), ("right", 5000.0, 9000.0), ("fine", 100.0, 100.0)];
+    let mut ids = Vec::new();
+    for (text, x, y) in positions {
+        let start = doc.content.find(text).unwrap();
+        let mut anno = db.build(&doc, start, start + text.len());
+        anno.note_position_x = x;
+        anno.note_position_y = y;
+        add_annotation(&db.conn, &anno).unwrap();
+        ids.push(anno.id);
+    }
+
+    assert_eq!(clamp_note_positions(&db.conn, &doc.id, 1200.0, 800.0).unwrap(), 2);
+    let position = |id: &str| {
+        let anno = get_annotation_by_id(&db.conn, id).unwrap().unwrap();
+        (anno.note_position_x, anno.note_position_y)
+    };
+    assert_eq!(position(&ids[0]), (0.0, 20.0));
+    assert_eq!(position(&ids[1]), (1200.0, 800.0));
+    assert_eq!(position(&ids[2]), (100.0, 100.0));
+
+    assert_eq!(clamp_note_positions(&db.conn, &doc.id, 1200.0, 800.0).unwrap(), 0);
+    assert!(clamp_note_positions(&db.conn, &doc.id, -1.0, 800.0).is_err());
+}
+
+// ============ BibTeX 导出 ============
+
+#[test]
+fn bibtex_entries_are_well_formed() {
+    let db = TestDb::new();
+    let doc = db.document("My Paper.md", "Costs rose 50% & more. Second point.");
+    let mut first = db.build(&doc, 0, 21);
+    first.note = Some("see {fig} #2".to_string());
+    first.created_at = Utc.with_ymd_and_hms(2023, 6, 15, 12, 0, 0).unwrap().timestamp_millis();
+    add_annotation(&db.conn, &first).unwrap();
+    db.annotate_text(&doc, "Second point");
+
+    let bib = export_as_bibtex(&db.conn, &doc.id, &ExportFilter::default()).unwrap();
+    let entries: Vec<&str> = bib.trim_end().split("\n\n").collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0], "@misc{my-paper-1,\n  \
+        title = {Annotation 1 on My Paper.md},\n  \
+        author = {admin},\n  \
+        year = {2023},\n  \
+        annotation = {Costs rose 50\\% \\& more},\n  \
+        note = {see \\{fig\\} \\#2}\n}");
+    assert!(entries[1].starts_with("@misc{my-paper-2,\n"));
+    assert!(entries[1].contains("  annotation = {Second point}\n}"));
+    assert!(!entries[1].contains("note = "));
+}
+
+// ============ 语言检测 ============
+
+#[test]
+fn detects_english_and_chinese() {
+    assert_eq!(detect_language("The quick brown fox jumps over the lazy dog and it is happy."), "en");
+    assert_eq!(detect_language("今天天气很好，我们一起去公园散步吧。"), "zh");
+    // 中文夹杂少量英文术语
+    assert_eq!(detect_language("我们使用 Rust 编写后端，并通过 Tauri 与前端通信。"), "zh");
+    assert_eq!(detect_language("12345 !!! ---"), "und");
+}
+
+// ============ 一步导出并保存 ============
+
+#[test]
+fn export_and_save_writes_complete_file() {
+    let db = TestDb::new();
+    let content = "Saved in one step.";
+    let doc = db.document("atomic.md", content);
+    let ids = vec![db.annotate_note(&doc, "one step", "atomic").id];
+
+    let dest = db.path("out/atomic.html");
+    let path = export_and_save(&db.conn, &doc.id, &ids, content, &dest, "html", &ExportFilter::default()).unwrap();
+    assert_eq!(path, dest);
+    let written = fs::read_to_string(&dest).unwrap();
+    assert_eq!(written, export_by_format(&db.conn, "html", &doc.id, &ids, content, &ExportFilter::default()).unwrap());
+    assert!(written.trim_end().ends_with("</html>"));
+    assert!(!std::path::Path::new(&format!("{}.tmp", dest)).exists());
+
+    // 不支持的格式不会留下文件
+    let rejected = db.path("out/atomic.docx");
+    assert!(export_and_save(&db.conn, &doc.id, &ids, content, &rejected, "docx", &ExportFilter::default()).is_err());
+    assert!(!std::path::Path::new(&rejected).exists());
+}
+
+// ============ 标签统计 ============
+
+#[test]
+fn list_all_tags_counts_across_documents() {
+    let db = TestDb::new();
+    let first = db.document("tags-a.md", "alpha beta");
+    let second = db.document("tags-b.md", "gamma delta");
+    let alpha = db.annotate_text(&first, "alpha");
+    let beta = db.annotate_text(&first, "beta");
+    let gamma = db.annotate_text(&second, "gamma");
+    db.annotate_text(&second, "delta");
+    set_annotation_tags(&db.conn, &alpha.id, &["idea".to_string(), "todo".to_string()]).unwrap();
+    set_annotation_tags(&db.conn, &beta.id, &[" idea ".to_string(), "idea".to_string()]).unwrap();
+    set_annotation_tags(&db.conn, &gamma.id, &["todo".to_string(), "idea".to_string(), "quote".to_string()]).unwrap();
+
+    assert_eq!(list_all_tags(&db.conn).unwrap(), vec![
+        ("idea".to_string(), 3),
+        ("todo".to_string(), 2),
+        ("quote".to_string(), 1),
+    ]);
+
+    // 标签随记录读出，并在删除撤销后保留
+    assert_eq!(get_annotation_by_id(&db.conn, &alpha.id).unwrap().unwrap().tags, vec!["idea", "todo"]);
+    delete_annotation(&db.conn, &alpha.id).unwrap();
+    assert_eq!(list_all_tags(&db.conn).unwrap()[0], ("idea".to_string(), 2));
+    undo_last_operation(&db.conn).unwrap();
+    assert_eq!(get_annotation_by_id(&db.conn, &alpha.id).unwrap().unwrap().tags, vec!["idea", "todo"]);
+}
+
+// ============ EPUB 导出 ============
+
+#[test]
+fn epub_export_produces_valid_zip_structure() {
+    let db = TestDb::new();
+    let content = "# Chapter One\n\nIt was a dark and stormy night.";
+    let doc = db.document("novel.md", content);
+    db.annotate_note(&doc, "stormy night", "classic opening");
+
+    let path = export_as_epub(&db.conn, &doc.id, content, &db.path("novel.epub"), &ExportFilter::default()).unwrap();
+    let bytes = fs::read(&path).unwrap();
+
+    // 第一个条目必须是未压缩的 mimetype
+    assert_eq!(&bytes[..4], b"PK\x03\x04");
+    let name_len = u16::from_le_bytes([bytes[26], bytes[27]]) as usize;
+    let extra_len = u16::from_le_bytes([bytes[28], bytes[29]]) as usize;
+    assert_eq!(&bytes[30..30 + name_len], b"mimetype");
+    let data = 30 + name_len + extra_len;
+    assert_eq!(&bytes[data..data + 20], b"application/epub+zip");
+
+    // 中央目录以原文记录文件名，末尾为目录结束记录
+    let has_entry = |name: &str| bytes.windows(name.len()).any(|w| w == name.as_bytes());
+    for name in ["META-INF/container.xml", "content.opf", "content.xhtml", "notes.xhtml"] {
+        assert!(has_entry(name), "missing {}", name);
+    }
+    assert!(bytes.windows(4).any(|w| w == b"PK\x05\x06"));
+}
+
+#[test]
+fn project_pdf_renders_every_document_on_its_own_pages() {
+    let db = TestDb::new();
+    let project = db.dir.join("project");
+    fs::create_dir_all(&project).unwrap();
+    let alpha = db.document("project/alpha.md", "# Alpha\n\nThe first chapter.");
+    db.annotate_note(&alpha, "first", "opening");
+    let beta = db.document("project/beta.md", "The second document.");
+    db.annotate_as(&beta, "second", "Reader");
+    db.document("outside.md", "not in the project");
+
+    // 目录页加上每个文档各自一页；内容流未压缩，ASCII 文字的 UTF-16BE 编码写为带 \000 转义的字面量
+    let ids = vec![alpha.id.clone(), beta.id.clone()];
+    let filter = ExportFilter { authors: vec!["admin".to_string()], ..ExportFilter::default() };
+    let bytes = build_documents_pdf(&db.conn, &ids, &filter).unwrap();
+    assert!(bytes.starts_with(b"%PDF-"));
+    let contains = |text: &str| {
+        let encoded: String = text.chars().map(|c| format!("\\000{}", c)).collect();
+        bytes.windows(encoded.len()).any(|w| w == encoded.as_bytes())
+    };
+    assert!(contains("alpha.md") && contains("beta.md") && contains("opening"));
+    assert!(!contains("Reader"));
+    // 页面对象为 /Type /Page，排除 /Pages 等同前缀的名称
+    let count = |name: &[u8]| bytes.windows(name.len() + 1)
+        .filter(|w| w.starts_with(name) && !w[name.len()].is_ascii_alphanumeric())
+        .count();
+    assert_eq!(count(b"/Page"), 3);
+    assert_eq!(count(b"/Link"), 2);
+
+    let path = export_project_pdf(&db.conn, &project.to_string_lossy(), &db.path("project.pdf"), &ExportFilter::default()).unwrap();
+    assert!(fs::read(&path).unwrap().starts_with(b"%PDF-"));
+
+    assert!(export_project_pdf(&db.conn, &db.path("empty"), &db.path("empty.pdf"), &ExportFilter::default()).is_err());
+}
+
+// ============ 注解合并 ============
+
+#[test]
+fn merge_annotations_combines_notes() {
+    let db = TestDb::new();
+    let doc = db.document("merge.md", "same phrase twice");
+    let mut keep = db.build(&doc, 0, 11);
+    keep.note = Some("first thought".to_string());
+    keep.highlight_color = "#87cefa".to_string();
+    add_annotation(&db.conn, &keep).unwrap();
+    let mut other = db.build(&doc, 0, 11);
+    other.note = Some("second thought".to_string());
+    add_annotation(&db.conn, &other).unwrap();
+    add_comment(&db.conn, &other.id, "moved along", None).unwrap();
+    set_annotation_tags(&db.conn, &other.id, &["merged".to_string()]).unwrap();
+
+    assert!(merge_annotations(&db.conn, &keep.id, &keep.id).is_err());
+    let merged = merge_annotations(&db.conn, &keep.id, &other.id).unwrap();
+    assert_eq!(merged.note.as_deref(), Some("first thought\n\nsecond thought"));
+    assert_eq!(merged.highlight_color, "#87cefa");
+    assert_eq!(merged.anchor_data, keep.anchor_data);
+    assert_eq!(merged.tags, vec!["merged"]);
+    assert!(get_annotation_by_id(&db.conn, &other.id).unwrap().is_none());
+    assert_eq!(get_comments_by_annotation(&db.conn, &keep.id).unwrap().len(), 1);
+
+    // 撤销后被合并的注解连同评论一起恢复，保留的注解回到合并前的笔记与标签
+    assert_eq!(undo_last_operation(&db.conn).unwrap().as_deref(), Some("merge_annotations"));
+    assert!(get_annotation_by_id(&db.conn, &other.id).unwrap().is_some());
+    assert_eq!(get_comments_by_annotation(&db.conn, &other.id).unwrap().len(), 1);
+    assert!(get_comments_by_annotation(&db.conn, &keep.id).unwrap().is_empty());
+    let restored = get_annotation_by_id(&db.conn, &keep.id).unwrap().unwrap();
+    assert_eq!(restored.note.as_deref(), Some("first thought"));
+    assert!(restored.tags.is_empty());
+
+    let elsewhere = db.document("elsewhere.md", "same phrase twice");
+    let foreign = db.annotate_text(&elsewhere, "same phrase");
+    assert!(merge_annotations(&db.conn, &keep.id, &foreign.id).is_err());
+    assert!(get_annotation_by_id(&db.conn, &foreign.id).unwrap().is_some());
+}
+
+// ============ 模板导出 ============
+
+#[test]
+fn template_export_renders_sections_and_rejects_unknown_placeholders() {
+    let db = TestDb::new();
+    let doc = db.document("template.md", "apples and oranges");
+    db.annotate_note(&doc, "oranges", "citrus");
+    db.annotate_text(&doc, "apples");
+
+    let template = "{{header}}# {document} ({count}){{/header}}\n{index}. \"{text}\" by {author}: {note}\n{{footer}}-- end --{{/footer}}";
+    assert_eq!(
+        export_with_template(&db.conn, &doc.id, template, &ExportFilter::default()).unwrap(),
+        "# template.md (2)\n1. \"apples\" by admin: \n2. \"oranges\" by admin: citrus\n-- end --\n"
+    );
+
+    assert!(export_with_template(&db.conn, &doc.id, "{text} {unknown}", &ExportFilter::default()).unwrap_err().contains("unknown"));
+    assert!(export_with_template(&db.conn, &doc.id, "{{header}}never closed\n{text}", &ExportFilter::default()).is_err());
+}
+
+// ============ 单项界面设置 ============
+
+#[test]
+fn set_ui_setting_leaves_other_keys_untouched() {
+    let _settings = edit_settings(|_| {});
+    save_ui_settings(&serde_json::json!({"theme": "dark", "sidebar_width": 25, "custom": [1, 2]})).unwrap();
+
+    set_ui_setting("sidebar_width", serde_json::json!(40)).unwrap();
+
+    let saved = load_ui_settings().unwrap().unwrap();
+    assert_eq!(saved, serde_json::json!({"theme": "dark", "sidebar_width": 40, "custom": [1, 2]}));
+    assert_eq!(get_ui_setting("sidebar_width").unwrap(), Some(serde_json::json!(40)));
+    // 未保存的键返回默认值，未知键返回空
+    assert_eq!(get_ui_setting("window_width").unwrap(), Some(serde_json::json!(800)));
+    assert_eq!(get_ui_setting("no_such_key").unwrap(), None);
+    assert!(set_ui_setting("  ", serde_json::json!(1)).is_err());
+}
+
+// ============ 按标签导出 ============
+
+#[test]
+fn export_by_tag_repeats_multi_tagged_annotations() {
+    let db = TestDb::new();
+    let doc = db.document("themes.md", "love and war and peace");
+    let love = db.annotate_note(&doc, "love", "a theme");
+    let war = db.annotate_text(&doc, "war");
+    db.annotate_text(&doc, "peace");
+    set_annotation_tags(&db.conn, &love.id, &["emotion".to_string(), "theme".to_string()]).unwrap();
+    set_annotation_tags(&db.conn, &war.id, &["theme".to_string()]).unwrap();
+
+    let sections = export_by_tag(&db.conn, &doc.id, &ExportFilter::default()).unwrap();
+    let mut keys: Vec<&String> = sections.keys().collect();
+    keys.sort();
+    assert_eq!(keys, vec!["emotion", "theme", "untagged"]);
+
+    assert_eq!(sections["emotion"], "## emotion\n\n> love\n\na theme\n\n— admin\n");
+    assert_eq!(sections["theme"], "## theme\n\n> love\n\na theme\n\n— admin\n\n> war\n\n— admin\n");
+    assert_eq!(sections["untagged"], "## untagged\n\n> peace\n\n— admin\n");
+}
+
+#[test]
+fn export_by_tag_keeps_only_selected_authors() {
+    let db = TestDb::new();
+    let doc = db.document("authors.md", "love and war");
+    let love = db.annotate_text(&doc, "love");
+    let war = db.annotate_as(&doc, "war", "Reader");
+    set_annotation_tags(&db.conn, &love.id, &["theme".to_string()]).unwrap();
+    set_annotation_tags(&db.conn, &war.id, &["theme".to_string()]).unwrap();
+
+    let filter = ExportFilter { authors: vec!["reader".to_string()], ..ExportFilter::default() };
+    let sections = export_by_tag(&db.conn, &doc.id, &filter).unwrap();
+    assert_eq!(sections.len(), 1);
+    assert_eq!(sections["theme"], "## theme\n\n> war\n\n— Reader\n");
+}
+
+// ============ 文件校验和 ============
+
+#[test]
+fn file_checksum_matches_in_memory_checksum() {
+    let db = TestDb::new();
+    // 超过一个缓冲区，且多字节字符跨越缓冲区边界
+    let content = "注解 annotation ".repeat(10_000);
+    let path = db.path("large.md");
+    fs::write(&path, &content).unwrap();
+
+    assert_eq!(compute_file_checksum(&path).unwrap(), compute_checksum(&content));
+
+    let empty = db.path("empty.md");
+    fs::write(&empty, "").unwrap();
+    assert_eq!(compute_file_checksum(&empty).unwrap(), compute_checksum(""));
+    assert!(compute_file_checksum(&db.path("missing.md")).is_err());
+}
+
+// ============ 版本对比 ============
+
+#[test]
+fn compare_versions_with_inserted_and_deleted_paragraphs() {
+    let db = TestDb::new();
+    let old = "Intro.\n\nPara one.\n\nPara two.\n\nPara three.";
+    let doc = db.document("versions.md", old);
+    let intro = db.annotate_text(&doc, "Intro");
+    let one = db.annotate_text(&doc, "one");
+    let two = db.annotate_text(&doc, "two");
+    let three = db.annotate_text(&doc, "three");
+
+    // 在开头段落后插入一段，并删除第二段
+    let new = "Intro.\n\nInserted paragraph.\n\nPara one.\n\nPara three.";
+    let comparison = compare_versions(&db.conn, &doc.id, new).unwrap();
+
+    assert_eq!(comparison.intact, vec![intro.id]);
+    assert_eq!(comparison.lost, vec![two.id]);
+    let drifted: Vec<(String, Option<usize>, usize)> = comparison.drifted.iter()
+        .map(|d| (d.id.clone(), d.old_offset, d.new_offset))
+        .collect();
+    assert_eq!(drifted, vec![
+        (one.id, Some(old.find("one").unwrap()), new.find("one").unwrap()),
+        (three.id, Some(old.find("three").unwrap()), new.find("three").unwrap()),
+    ]);
+    assert_eq!((comparison.diff.unchanged, comparison.diff.added, comparison.diff.removed), (5, 2, 2));
+
+    // 对比不修改数据库
+    assert_eq!(get_document_by_id(&db.conn, &doc.id).unwrap().unwrap().content, old);
+}
+
+// ============ 按锚点重叠去重 ============
+
+#[test]
+fn anchor_dedup_skips_overlapping_annotations_with_different_text() {
+    let db = TestDb::new();
+    let doc = db.document("overlap.md", "The quick brown fox jumps over the lazy dog.");
+    db.annotate_text(&doc, "quick brown fox");
+    let incoming = vec![
+        db.build(&doc, 4, 15),  // "quick brown"：与已有注解完全重叠
+        db.build(&doc, 16, 25), // "fox jumps"：仅 3 个字符重叠
+        db.build(&doc, 4, 19),  // 文本相同
+    ];
+
+    let report = merge_imported_annotations_with_mode(&db.conn, &incoming, &doc.id, DedupMode::Anchor, 0.5).unwrap();
+    assert_eq!(report.imported, 1);
+    assert_eq!(report.skipped_overlap, vec!["quick brown"]);
+    assert_eq!(report.skipped_text, vec!["quick brown fox"]);
+
+    // 文本模式只按文本去重
+    let other = db.document("overlap-text.md", &doc.content);
+    db.annotate_text(&other, "quick brown fox");
+    let incoming: Vec<AnnotationRecord> = incoming.into_iter().take(2).collect();
+    let report = merge_imported_annotations_with_mode(&db.conn, &incoming, &other.id, DedupMode::Text, 0.5).unwrap();
+    assert_eq!(report.imported, 2);
+    assert!(report.skipped_overlap.is_empty());
+
+    assert!(merge_imported_annotations_with_mode(&db.conn, &[], &doc.id, DedupMode::Anchor, 1.5).is_err());
+}
+
+// ============ 文档大纲 ============
+
+#[test]
+fn outline_skips_headings_inside_code_fences() {
+    let content = "# 标题\n\nintro\n\n## Section A\n\n```bash\n# not a heading\n```\n\n### Sub A.1 ###\n\n#NoSpace\n\n## Section B\n";
+    let outline: Vec<(u8, String, usize)> = get_document_outline(content)
+        .into_iter()
+        .map(|e| (e.level, e.title, e.offset))
+        .collect();
+
+    let char_offset = |needle: &str| content[..content.find(needle).unwrap()].chars().count();
+    assert_eq!(outline, vec![
+        (1, "标题".to_string(), 0),
+        (2, "Section A".to_string(), char_offset("## Section A")),
+        (3, "Sub A.1".to_string(), char_offset("### Sub")),
+        (2, "Section B".to_string(), char_offset("## Section B")),
+    ]);
+}
+
+// ============ 锚点上下文 ============
+
+#[test]
+fn context_reanchors_into_edited_document() {
+    let source = TestDb::new();
+    let target = TestDb::new();
+    let doc = source.document("context.md", "alpha beta gamma delta");
+    let anno = source.annotate_note(&doc, "gamma", "the third");
+
+    let json = export_annotation(&source.conn, &anno.id, &doc.path, None).unwrap();
+    let package: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(package["annotations"][0]["context"]["prefix"], "alpha beta ");
+    assert_eq!(package["annotations"][0]["context"]["suffix"], " delta");
+
+    // 目标文档开头多了一处同样的文本，原偏移也已失效
+    let edited = target.document("context.md", "gamma rays first. alpha beta gamma delta");
+    let imported = resolve_import_users(&target.conn, import_annotation(&json, None).unwrap()).unwrap();
+    assert_eq!(merge_imported_annotations(&target.conn, &imported, &edited.id).unwrap(), 1);
+
+    let annotations = get_annotations_by_doc(&target.conn, &edited.id).unwrap();
+    assert_eq!(annotations[0].note.as_deref(), Some("the third"));
+    assert_eq!(locate_annotation(&annotations[0], &edited.content), Some((29, 34)));
+}
+
+// ============ 导出记录 ============
+
+#[test]
+fn exports_are_recorded_and_listed() {
+    let db = TestDb::new();
+    let doc = db.document("recorded.md", "export me");
+    let ids = vec![db.annotate_text(&doc, "export").id];
+
+    // 导出本身不写数据库，由调用方在同一连接上记录
+    let first = export_and_save(&db.conn, &doc.id, &ids, &doc.content, &db.path("first.html"), "html", &ExportFilter::default()).unwrap();
+    assert!(list_recent_exports(&db.conn, 10).unwrap().is_empty());
+    record_export(&db.conn, Some(&doc.id), "html", &first).unwrap();
+    let listed = list_recent_exports(&db.conn, 10).unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].path, first);
+    assert_eq!(listed[0].format, "html");
+    assert_eq!(listed[0].document_id.as_deref(), Some(doc.id.as_str()));
+
+    // 文件被删除后列表跳过该记录，下次记录导出时清除
+    fs::remove_file(&first).unwrap();
+    assert!(list_recent_exports(&db.conn, 10).unwrap().is_empty());
+    let second = export_and_save(&db.conn, &doc.id, &ids, &doc.content, &db.path("second.md"), "md", &ExportFilter::default()).unwrap();
+    record_export(&db.conn, Some(&doc.id), "md", &second).unwrap();
+    let stored = read_export_records(&db.conn).unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].path, second);
+}
+
+// ============ 对比度 ============
+
+#[test]
+fn contrast_for_known_pairs() {
+    let black_white = check_color_contrast("#ffffff", "#000").unwrap();
+    assert_eq!(black_white.ratio, 21.0);
+    assert!(black_white.aa && black_white.aaa);
+
+    // #767676 是白底上刚好通过 AA 的最浅灰色，#777777 则刚好不通过
+    let passing = check_color_contrast("#ffffff", "#767676").unwrap();
+    assert_eq!(passing.ratio, 4.54);
+    assert!(passing.aa && passing.aaa_large && !passing.aaa);
+    let failing = check_color_contrast("#ffffff", "#777777").unwrap();
+    assert!(!failing.aa && failing.aa_large);
+
+    // 默认金色高亮上的白字不可读
+    let gold = check_color_contrast("#ffd700", "#fff").unwrap();
+    assert!(!gold.aa_large);
+
+    assert!(check_color_contrast("gold", "#000").is_err());
+}
+
+// ============ 按作者导出 ============
+
+#[test]
+fn export_includes_only_selected_authors() {
+    let db = TestDb::new();
+    let doc = db.document("reviewers.md", "line one\nline two\n");
+    let mine = db.annotate_note(&doc, "one", "from admin");
+    let theirs = db.annotate_as(&doc, "two", "Reviewer");
+
+    let filtered = filter_by_authors(get_annotations_by_doc(&db.conn, &doc.id).unwrap(), &["  reviewer ".to_string()]);
+    assert_eq!(filtered.len(), 1);
+    assert_eq!(filtered[0].id, theirs.id);
+    assert_eq!(filter_by_authors(get_annotations_by_doc(&db.conn, &doc.id).unwrap(), &[]).len(), 2);
+
+    let review: Vec<serde_json::Value> = serde_json::from_str(
+        &export_as_github_review(&db.conn, &doc.id, &ExportFilter { authors: vec!["REVIEWER".to_string()], ..ExportFilter::default() }).unwrap()
+    ).unwrap();
+    assert_eq!(review.len(), 1);
+    assert_eq!(review[0]["line"], 2);
+
+    let filter = ExportFilter { authors: vec!["Reviewer".to_string()], ..ExportFilter::default() };
+    let options = HtmlExportOptions { filter, ..Default::default() };
+    let ids = vec![mine.id.clone(), theirs.id.clone()];
+    let (html, _) = export_as_html(&db.conn, &doc.id, &ids, &doc.content, &options).unwrap();
+    assert!(html.contains(&theirs.id));
+    assert!(!html.contains(&mine.id));
+    assert!(!html.contains("from admin"));
+}
+
+// ============ 注解 slug ============
+
+#[test]
+fn identical_text_gets_distinct_stable_slugs() {
+    let db = TestDb::new();
+    let doc = db.document("slugs.md", "Hello, World! again Hello, World!");
+    let first = db.annotate(&doc, 0, 13);
+    let second = db.annotate(&doc, 20, 33);
+
+    let first_slug = get_annotation_slug(&db.conn, &first.id).unwrap();
+    let second_slug = get_annotation_slug(&db.conn, &second.id).unwrap();
+    assert_ne!(first_slug, second_slug);
+    assert!(first_slug.starts_with("hello-world-"));
+    assert!(second_slug.starts_with("hello-world-"));
+    assert_eq!(get_annotation_slug(&db.conn, &first.id).unwrap(), first_slug);
+
+    // HTML 导出中便签使用同一 slug 作为元素 ID
+    let (html, _) = export_as_html(&db.conn, &doc.id, &[first.id, second.id], &doc.content, &HtmlExportOptions::default()).unwrap();
+    assert!(html.contains(&format!("id=\"{}\"", first_slug)));
+    assert!(html.contains(&format!("id=\"{}\"", second_slug)));
+}
+
+// ============ 崩溃恢复日志 ============
+
+#[test]
+fn journal_replays_into_database_without_annotations() {
+    let db = TestDb::new();
+    let doc = db.document("journal.md", "one two three");
+    let _ = fs::remove_file(get_journal_path());
+
+    // 已记入日志但崩溃前未写入数据库
+    let lost = db.build(&doc, 0, 3);
+    append_journal("add", &lost).unwrap();
+    let mut edited_twice = db.build(&doc, 8, 13);
+    append_journal("add", &edited_twice).unwrap();
+    edited_twice.note = Some("latest".to_string());
+    append_journal("update", &edited_twice).unwrap();
+
+    // 已写入数据库，但之后的修改丢失
+    let stale = db.annotate_text(&doc, "two");
+    let mut newer = stale.clone();
+    newer.note = Some("newer note".to_string());
+    newer.updated_at = Utc::now().timestamp_millis() + 1000;
+    append_journal("update", &newer).unwrap();
+
+    // 崩溃时写了一半的行
+    let mut file = fs::OpenOptions::new().append(true).open(get_journal_path()).unwrap();
+    std::io::Write::write_all(&mut file, b"{\"operation\":\"add\",\"annot").unwrap();
+
+    assert_eq!(recover_from_journal(&db.conn).unwrap(), 3);
+    assert_eq!(note_of(&db.conn, &lost.id), None);
+    assert_eq!(note_of(&db.conn, &edited_twice.id).as_deref(), Some("latest"));
+    assert_eq!(note_of(&db.conn, &stale.id).as_deref(), Some("newer note"));
+
+    assert_eq!(fs::read_to_string(get_journal_path()).unwrap(), "");
+    assert_eq!(recover_from_journal(&db.conn).unwrap(), 0);
+
+    // 正常写入后日志即清空，之后删除的注解不会在下次启动时被回放
+    let deleted = db.build(&doc, 4, 7);
+    with_journal("add", &deleted, || add_annotation(&db.conn, &deleted)).unwrap();
+    assert_eq!(fs::read_to_string(get_journal_path()).unwrap(), "");
+    delete_annotation(&db.conn, &deleted.id).unwrap();
+    assert_eq!(recover_from_journal(&db.conn).unwrap(), 0);
+    assert!(get_annotation_by_id(&db.conn, &deleted.id).unwrap().is_none());
+
+    // 写入失败同样清空，不留待回放的记录
+    assert!(with_journal("add", &stale, || add_annotation(&db.conn, &stale)).is_err());
+    assert_eq!(fs::read_to_string(get_journal_path()).unwrap(), "");
+
+    // 残留的已归档注解记录不会回放到常规表
+    append_journal("update", &newer).unwrap();
+    archive_annotations(&db.conn, &doc.id).unwrap();
+    assert_eq!(recover_from_journal(&db.conn).unwrap(), 0);
+    assert!(get_annotation_by_id(&db.conn, &stale.id).unwrap().is_none());
+}
+
+// ============ 颜色图例 ============
+
+#[test]
+fn legend_lists_every_color_present() {
+    let db = TestDb::new();
+    let doc = db.document("legend.md", "red green blue gold");
+    let mut ids = Vec::new();
+    for (text, color) in [("red", "#FF0000"), ("green", "#90ee90"), ("blue", "#0000ff"), ("gold", "#ff0000")] {
+        let start = doc.content.find(text).unwrap();
+        let mut anno = db.build(&doc, start, start + text.len());
+        anno.highlight_color = color.to_string();
+        add_annotation(&db.conn, &anno).unwrap();
+        ids.push(anno.id);
+    }
+    let _settings = edit_settings(|s| {
+        s.export.color_labels.insert("#ff0000".to_string(), "Disagree".to_string());
+    });
+
+    let options = HtmlExportOptions { show_legend: true, ..Default::default() };
+    let (html, _) = export_as_html(&db.conn, &doc.id, &ids, &doc.content, &options).unwrap();
+    let legend = &html[html.find(r#"<ul class="color-legend">"#).unwrap()..];
+    let legend = &legend[..legend.find("</ul>").unwrap()];
+
+    assert_eq!(legend.matches("<li>").count(), 3);
+    assert!(legend.contains("<code>#ff0000</code> Disagree</li>"));
+    // 未设置说明时使用预设名称
+    assert!(legend.contains("<code>#90ee90</code> green</li>"));
+    assert!(legend.contains("<code>#0000ff</code> </li>"));
+
+    let (plain, _) = export_as_html(&db.conn, &doc.id, &ids, &doc.content, &HtmlExportOptions::default()).unwrap();
+    assert!(!plain.contains(r#"<ul class="color-legend">"#));
+}
+
+// ============ 目录批量导入 ============
+
+#[test]
+fn import_packages_from_directory_of_two_packages() {
+    let db = TestDb::new();
+    let content = "alpha beta gamma";
+    let colleague = db.document("colleague.md", content);
+    let target = db.document("mine.md", content);
+    let alpha = db.annotate_note(&colleague, "alpha", "from a");
+    let beta = db.annotate_note(&colleague, "beta", "from b");
+
+    let dir = db.dir.join("incoming");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.json"), export_annotation(&db.conn, &alpha.id, &colleague.path, None).unwrap()).unwrap();
+    // 第二个包与第一个重复了 alpha
+    let mut second: serde_json::Value = serde_json::from_str(&export_annotation(&db.conn, &beta.id, &colleague.path, None).unwrap()).unwrap();
+    let duplicate = serde_json::to_value(get_annotation_by_id(&db.conn, &alpha.id).unwrap().unwrap()).unwrap();
+    second["annotations"].as_array_mut().unwrap().push(duplicate);
+    fs::write(dir.join("b.annoti"), second.to_string()).unwrap();
+    fs::write(dir.join("readme.txt"), "not a package").unwrap();
+    fs::write(dir.join("c.json"), "{ broken").unwrap();
+
+    let summary = import_packages_from_dir(&db.conn, &dir.to_string_lossy(), &target.path, "text").unwrap();
+    assert_eq!(summary.total_imported, 2);
+    let files: Vec<(&str, usize, usize, bool)> = summary.files.iter()
+        .map(|f| (f.file.as_str(), f.imported, f.skipped_text, f.error.is_some()))
+        .collect();
+    assert_eq!(files, vec![("a.json", 1, 0, false), ("b.annoti", 1, 1, false), ("c.json", 0, 0, true)]);
+
+    let mut notes: Vec<String> = get_annotations_by_doc(&db.conn, &target.id).unwrap()
+        .into_iter()
+        .filter_map(|a| a.note)
+        .collect();
+    notes.sort();
+    assert_eq!(notes, vec!["from a", "from b"]);
+    assert!(import_packages_from_dir(&db.conn, &dir.to_string_lossy(), &target.path, "fuzzy").is_err());
+}
+
+// ============ 索引卡片 ============
+
+#[test]
+fn index_cards_have_page_breaks_and_one_card_each() {
+    let db = TestDb::new();
+    let doc = db.document("cards.md", "first fact, second fact, third <fact>");
+    db.annotate_note(&doc, "first fact", "remember this");
+    db.annotate_text(&doc, "second fact");
+    db.annotate_text(&doc, "third <fact>");
+
+    let html = export_as_index_cards(&db.conn, &doc.id, &ExportFilter::default()).unwrap();
+    assert!(html.contains("@page { size: 5in 3in; margin: 0; }"));
+    assert!(html.contains("page-break-after: always"));
+    assert!(html.contains("break-inside: avoid"));
+    assert_eq!(html.matches(r#"<section class="card""#).count(), 3);
+    assert_eq!(html.matches(r#"class="card-face card-back""#).count(), 3);
+    assert!(html.contains("remember this"));
+    assert!(html.contains("third &lt;fact&gt;"));
+}
+
+// ============ 锚点位移 ============
+
+#[test]
+fn anchor_drift_after_inserted_paragraph() {
+    let db = TestDb::new();
+    let doc = db.document("drift.md", "Intro text.\n\nBody with target words.");
+    let intro = db.annotate_text(&doc, "Intro");
+    let body = db.annotate_text(&doc, "Body");
+    let target = db.annotate_text(&doc, "target");
+    let words = db.annotate_text(&doc, "words");
+
+    let inserted = "Inserted paragraph here.\n\n";
+    let new_content = format!("Intro text.\n\n{}Main with target words.", inserted);
+    let drifts = measure_anchor_drift(&db.conn, &doc.id, &new_content).unwrap();
+
+    let summary: Vec<(String, Option<i64>, bool)> = drifts.iter().map(|d| (d.id.clone(), d.delta, d.lost)).collect();
+    assert_eq!(summary[0], (body.id, None, true));
+    let shift = Some(inserted.len() as i64);
+    let mut moved: Vec<&(String, Option<i64>, bool)> = summary[1..3].iter().collect();
+    moved.sort();
+    let mut expected = [(target.id, shift, false), (words.id, shift, false)];
+    expected.sort();
+    assert_eq!(moved, expected.iter().collect::<Vec<_>>());
+    assert_eq!(summary[3], (intro.id, Some(0), false));
+    assert_eq!(drifts[3].new_offset, Some(0));
+}
+
+// ============ 删除用户 ============
+
+#[test]
+fn delete_user_reassigns_or_deletes_annotations() {
+    let db = TestDb::new();
+    let doc = db.document("users.md", "alpha beta gamma");
+    let mine = db.annotate_text(&doc, "alpha");
+    let kept = db.annotate_as(&doc, "beta", "Leaving");
+    let dropped = db.annotate_as(&doc, "gamma", "Purged");
+
+    let active = get_or_create_user(&db.conn, "admin".to_string()).unwrap();
+    assert!(delete_user(&db.conn, &active.id, false).is_err());
+
+    assert_eq!(delete_user(&db.conn, &kept.user_id, false).unwrap(), 1);
+    let reassigned = get_annotation_by_id(&db.conn, &kept.id).unwrap().unwrap();
+    assert_eq!(reassigned.user_id, "deleted-user");
+    assert!(get_user_by_id(&db.conn, &kept.user_id).unwrap().is_none());
+
+    assert_eq!(delete_user(&db.conn, &dropped.user_id, true).unwrap(), 1);
+    assert!(get_annotation_by_id(&db.conn, &dropped.id).unwrap().is_none());
+    assert!(get_annotation_by_id(&db.conn, &mine.id).unwrap().is_some());
+    assert!(delete_user(&db.conn, "deleted-user", false).is_err());
+
+    // 撤销时用户行随注解一起恢复，注解不会指向不存在的用户
+    assert_eq!(undo_last_operation(&db.conn).unwrap().as_deref(), Some("delete_user"));
+    let restored = get_annotation_by_id(&db.conn, &dropped.id).unwrap().unwrap();
+    let author = get_user_by_id(&db.conn, &restored.user_id).unwrap().unwrap();
+    assert_eq!(author.name, "Purged");
+}
+
+// ============ 项目清单 ============
+
+#[test]
+fn manifest_lists_tracked_missing_and_untracked_files() {
+    let db = TestDb::new();
+    let tracked = db.document("tracked.md", "kept on disk");
+    db.annotate_as(&tracked, "kept", "Reader");
+    let gone = db.document("gone.txt", "removed later");
+    fs::remove_file(&gone.path).unwrap();
+    fs::create_dir_all(db.dir.join("sub")).unwrap();
+    fs::write(db.dir.join("sub").join("new.markdown"), "not imported").unwrap();
+    fs::write(db.dir.join("image.png"), "binary").unwrap();
+    fs::write(db.dir.join(".hidden.md"), "skipped").unwrap();
+
+    let json = generate_project_manifest(&db.conn, &db.dir.to_string_lossy()).unwrap();
+    let manifest: ProjectManifest = serde_json::from_str(&json).unwrap();
+    let entries: Vec<(&str, &str)> = manifest.documents.iter()
+        .map(|e| (e.path.rsplit(['/', '\\']).next().unwrap(), e.status.as_str()))
+        .collect();
+    assert_eq!(entries, [("gone.txt", "missing"), ("new.markdown", "untracked"), ("tracked.md", "tracked")]);
+
+    let entry = manifest.documents.iter().find(|e| e.status == "tracked").unwrap();
+    assert_eq!(entry.annotation_count, 1);
+    assert_eq!(entry.authors, ["Reader"]);
+    assert_eq!(entry.checksum.as_deref(), Some(tracked.checksum.as_str()));
+    assert!(manifest.documents.iter().find(|e| e.status == "untracked").unwrap().checksum.is_none());
+}
+
+// ============ 注解归档 ============
+
+#[test]
+fn archive_round_trip_keeps_comments_without_integrity_errors() {
+    let db = TestDb::new();
+    let doc = db.document("archive.md", "first second");
+    let first = db.annotate_text(&doc, "first");
+    db.annotate_text(&doc, "second");
+    add_comment(&db.conn, &first.id, "still here", None).unwrap();
+
+    assert_eq!(archive_annotations(&db.conn, &doc.id).unwrap(), 2);
+    assert!(get_annotations_by_doc(&db.conn, &doc.id).unwrap().is_empty());
+    assert!(get_comments_by_annotation(&db.conn, &first.id).unwrap().is_empty());
+    let archived: i64 = db.conn.query_row("SELECT COUNT(*) FROM comments_archive WHERE annotation_id = ?", [&first.id], |row| row.get(0)).unwrap();
+    assert_eq!(archived, 1);
+    let issues = check_referential_integrity(&db.conn).unwrap();
+    assert!(issues.foreign_key_violations.is_empty());
+    assert!(issues.comments_missing_annotation.is_empty());
+    // 修复时不得删除归档注解的评论
+    assert_eq!(fix_referential_integrity(&db.conn, IntegrityFixStrategy::Delete).unwrap(), 0);
+
+    assert_eq!(unarchive_annotations(&db.conn, &doc.id).unwrap(), 2);
+    assert_eq!(get_annotations_by_doc(&db.conn, &doc.id).unwrap().len(), 2);
+    assert_eq!(get_comments_by_annotation(&db.conn, &first.id).unwrap().len(), 1);
+    assert!(archive_annotations(&db.conn, "no-such-doc").is_err());
+}
+
+#[test]
+fn schema_moves_legacy_archived_comments_out_of_comments() {
+    let db = TestDb::new();
+    let doc = db.document("legacy.md", "old note");
+    let anno = db.annotate_text(&doc, "old");
+    add_comment(&db.conn, &anno.id, "left behind", None).unwrap();
+    // 旧版归档只移动注解，评论留在 comments 表中
+    db.conn.execute_batch(&format!(
+        "PRAGMA foreign_keys = OFF;
+         INSERT INTO annotations_archive ({0}) SELECT {0} FROM annotations;
+         DELETE FROM annotations;
+         PRAGMA foreign_keys = ON;",
+        ANNOTATION_COLUMNS
+    )).unwrap();
+
+    create_schema(&db.conn).unwrap();
+    let issues = check_referential_integrity(&db.conn).unwrap();
+    assert!(issues.foreign_key_violations.is_empty() && issues.comments_missing_annotation.is_empty());
+    assert_eq!(unarchive_annotations(&db.conn, &doc.id).unwrap(), 1);
+    assert_eq!(get_comments_by_annotation(&db.conn, &anno.id).unwrap()[0].body, "left behind");
+}
+
+// ============ 便签位置视口换算 ============
+
+#[test]
+fn note_positions_scale_between_viewports() {
+    let db = TestDb::new();
+    let doc = db.document("viewport.md", "pinned note");
+    let mut anno = db.build(&doc, 0, 6);
+    anno.note_position_x = 200.0;
+    anno.note_position_y = 150.0;
+    add_annotation(&db.conn, &anno).unwrap();
+
+    let source = Viewport { width: 800.0, height: 600.0 };
+    let json = export_annotation(&db.conn, &anno.id, &doc.path, Some(source)).unwrap();
+
+    let scaled = import_annotation(&json, Some(Viewport { width: 1600.0, height: 1200.0 })).unwrap();
+    assert_eq!((scaled[0].note_position_x, scaled[0].note_position_y), (400.0, 300.0));
+    // 未提供视口时按导出时的视口还原
+    let same = import_annotation(&json, None).unwrap();
+    assert_eq!((same[0].note_position_x, same[0].note_position_y), (200.0, 150.0));
+    assert_ne!(same[0].id, anno.id);
+
+    assert!(export_annotation(&db.conn, &anno.id, &doc.path, Some(Viewport { width: 0.0, height: 600.0 })).is_err());
+}
+
+// ============ 日志 ============
+
+#[test]
+fn logged_lines_read_back_with_level() {
+    init_app_data();
+    let marker = Uuid::new_v4().to_string();
+    log_info(&format!("opened {}", marker));
+    log_warn(&format!("first {}\nsecond line", marker));
+
+    let recent = read_recent_logs(200).unwrap();
+    assert!(recent.contains(&format!("[INFO] opened {}", marker)));
+    // 多行消息的后续行缩进，便于区分条目
+    assert!(recent.contains(&format!("[WARN] first {}\n    second line", marker)));
+    assert!(read_recent_logs(0).unwrap().is_empty());
+}
+
+// ============ 锚点一致性 ============
+
+#[test]
+fn consistency_audit_reports_mismatches_and_unlocatable() {
+    let db = TestDb::new();
+    let content = "héllo world here";
+    let doc = db.document("audit.md", content);
+    db.annotate_text(&doc, "world");
+
+    let start = content.find("here").unwrap();
+    let mut mismatched = db.build(&doc, start, start + 4);
+    mismatched.text = "hero".to_string();
+    add_annotation(&db.conn, &mismatched).unwrap();
+
+    // 锚点落在 é 中间，无法切出子串，文本也不在原文中
+    let mut split = db.build(&doc, 0, 1);
+    split.anchor_data = build_text_anchor(0, 2);
+    split.text = "absent".to_string();
+    add_annotation(&db.conn, &split).unwrap();
+
+    let mut issues = audit_annotation_consistency(&db.conn, &doc.id, content).unwrap();
+    issues.sort_by_key(|i| i.start);
+    assert_eq!(issues.len(), 2);
+
+    assert_eq!(issues[0].id, split.id);
+    assert_eq!(issues[0].actual, None);
+    assert!(!issues[0].located);
+
+    assert_eq!(issues[1].id, mismatched.id);
+    assert_eq!((issues[1].start, issues[1].end), (Some(start), Some(start + 4)));
+    assert_eq!(issues[1].expected, "hero");
+    assert_eq!(issues[1].actual.as_deref(), Some("here"));
+}
+
+// ============ 演示模式 ============
+
+#[test]
+fn presentation_mode_persists_in_settings() {
+    let _settings = edit_settings(|s| s.editor.presentation_mode = false);
+    set_presentation_mode(true).unwrap();
+    assert!(load_settings().unwrap().editor.presentation_mode);
+    set_presentation_mode(false).unwrap();
+    assert!(!load_settings().unwrap().editor.presentation_mode);
+}
+
+// ============ 旧版注解文件编码 ============
+
+#[test]
+fn gbk_sidecar_is_decoded_and_migrated() {
+    let db = TestDb::new();
+    let doc = db.document("旧笔记.md", "第一章 开始");
+    let mut anno = db.build(&doc, 0, "第一章".len());
+    anno.note = Some("重要段落".to_string());
+    let json = serde_json::to_string(&vec![&anno]).unwrap();
+    let (gbk, _, unmappable) = encoding_rs::GBK.encode(&json);
+    assert!(!unmappable);
+    assert!(std::str::from_utf8(&gbk).is_err());
+    assert_eq!(decode_sidecar_bytes(&gbk).as_deref(), Some(json.as_str()));
+
+    let sidecar = format!("{}.ann", doc.path);
+    fs::write(&sidecar, &gbk).unwrap();
+    let report = migrate_sidecar_files(&db.conn, &db.dir.to_string_lossy()).unwrap();
+    assert_eq!((report.migrated, report.errors), (1, 0));
+    assert!(report.undecodable.is_empty());
+    assert!(std::path::Path::new(&format!("{}.backup.migrated", sidecar)).exists());
+
+    let imported = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    assert_eq!(imported.len(), 1);
+    assert_eq!(imported[0].text, "第一章");
+    assert_eq!(imported[0].note.as_deref(), Some("重要段落"));
+}
+
+// ============ LLM 导出 ============
+
+#[test]
+fn llm_export_is_compact_and_truncates_quotes() {
+    let db = TestDb::new();
+    let doc = db.document("llm.md", "short quote\nthen a much longer quoted passage");
+    db.annotate_note(&doc, "short quote", "keep\n  this");
+    db.annotate_text(&doc, "then a much longer quoted passage");
+
+    let text = export_for_llm(&db.conn, &doc.id, 12, &ExportFilter::default()).unwrap();
+    let date = Utc::now().format("%Y-%m-%d").to_string();
+    assert_eq!(text, format!(
+        "DOCUMENT: llm.md\nANNOTATIONS: 2\n\
+         ---\n[1] admin, {0}\nQUOTE: short quote\nNOTE: keep this\n\
+         ---\n[2] admin, {0}\nQUOTE: then a much…\n",
+        date
+    ));
+    assert!(!text.contains(&doc.id));
+}
+
+// ============ 排版配置重置 ============
+
+#[test]
+fn typography_reset_overwrites_invalid_file() {
+    let _settings = edit_settings(|_| {});
+    let path = get_typography_path();
+    fs::write(&path, "fonts: [unclosed\n\t- broken").unwrap();
+
+    let defaults = reset_typography_config().unwrap();
+    assert_eq!(defaults, DEFAULT_TYPOGRAPHY_YAML);
+    assert_eq!(fs::read_to_string(&path).unwrap(), DEFAULT_TYPOGRAPHY_YAML);
+    assert!(!path.with_extension("yaml.tmp").exists());
+}
+
+// ============ 用户漂移 ============
+
+#[test]
+fn reconcile_user_prefers_database_identity() {
+    let _settings = edit_settings(|s| {
+        s.user.id = "settings-id".to_string();
+        s.user.name = "Settings Name".to_string();
+    });
+
+    // 数据库为空时按设置补建用户
+    let empty = TestDb::new();
+    let created = reconcile_user(&empty.conn).unwrap();
+    assert_eq!((created.id.as_str(), created.name.as_str()), ("settings-id", "Settings Name"));
+    assert!(get_user_by_id(&empty.conn, "settings-id").unwrap().is_some());
+
+    let db = TestDb::new();
+    db.conn.execute(
+        "INSERT INTO users (id, name, created_at) VALUES ('db-id', 'Db Name', 0)",
+        [],
+    ).unwrap();
+    let user = reconcile_user(&db.conn).unwrap();
+    assert_eq!((user.id.as_str(), user.name.as_str()), ("db-id", "Db Name"));
+
+    let settings = load_settings().unwrap();
+    assert_eq!((settings.user.id.as_str(), settings.user.name.as_str()), ("db-id", "Db Name"));
+}
+
+// ============ 看板导出 ============
+
+#[test]
+fn kanban_columns_follow_tags_with_backlog_first() {
+    let db = TestDb::new();
+    let doc = db.document("board.md", "alpha beta gamma");
+    let alpha = db.annotate_note(&doc, "alpha", "  fix me  ");
+    let beta = db.annotate_text(&doc, "beta");
+    let gamma = db.annotate_as(&doc, "gamma", "Helper");
+    set_annotation_tags(&db.conn, &alpha.id, &["todo".to_string(), "bug".to_string()]).unwrap();
+    set_annotation_tags(&db.conn, &gamma.id, &["todo".to_string()]).unwrap();
+
+    let board: Vec<KanbanColumn> = serde_json::from_str(&export_as_kanban(&db.conn, &doc.id, &ExportFilter::default()).unwrap()).unwrap();
+    let layout: Vec<(&str, Vec<&str>)> = board.iter()
+        .map(|c| (c.title.as_str(), c.cards.iter().map(|card| card.text.as_str()).collect()))
+        .collect();
+    assert_eq!(layout, [
+        ("Backlog", vec!["beta"]),
+        ("bug", vec!["alpha"]),
+        ("todo", vec!["alpha", "gamma"]),
+    ]);
+
+    assert_eq!(board[0].cards[0].id, beta.id);
+    assert_eq!(board[1].cards[0].note.as_deref(), Some("fix me"));
+    assert_eq!(board[2].cards[1].author, "Helper");
+}
+
+// ============ 导入预览 ============
+
+#[test]
+fn import_preview_respects_limit_without_writing() {
+    let db = TestDb::new();
+    let doc = db.document("preview.md", "one two three");
+    let annotations: Vec<AnnotationRecord> = ["one", "two", "three"].iter()
+        .map(|text| db.build(&doc, doc.content.find(text).unwrap(), doc.content.find(text).unwrap() + text.len()))
+        .collect();
+    let package = BatchPackage {
+        version: "1.0".to_string(),
+        exported_at: 0,
+        source_document: None,
+        annotations: annotations.clone(),
+        viewport: None,
+    };
+    let json = serde_json::to_string(&package).unwrap();
+
+    let preview = preview_import(&json, 2).unwrap();
+    assert_eq!(preview.iter().map(|a| a.text.as_str()).collect::<Vec<_>>(), ["one", "two"]);
+    assert!(preview.iter().zip(&annotations).all(|(p, a)| p.id != a.id));
+    assert_eq!(preview_import(&json, 10).unwrap().len(), 3);
+    assert!(get_annotations_by_doc(&db.conn, &doc.id).unwrap().is_empty());
+}
+
+// ============ 按作者分别导出 ============
+
+#[test]
+fn per_author_export_writes_one_file_each() {
+    let db = TestDb::new();
+    let doc = db.document("review.md", "alpha\nbeta\ngamma");
+    db.annotate_text(&doc, "alpha");
+    db.annotate_as(&doc, "beta", "Guest");
+    db.annotate_as(&doc, "gamma", "Guest");
+    let out = db.dir.join("authors");
+
+    let mut paths = export_authors_to_dir(&db.conn, &doc.id, &out.to_string_lossy(), "GitHub-Review").unwrap();
+    paths.sort();
+    let names: Vec<&str> = paths.iter().map(|p| p.rsplit(['/', '\\']).next().unwrap()).collect();
+    assert_eq!(names, ["review-Guest.json", "review-admin.json"]);
+
+    let guest: Vec<ReviewComment> = serde_json::from_str(&fs::read_to_string(&paths[0]).unwrap()).unwrap();
+    let admin: Vec<ReviewComment> = serde_json::from_str(&fs::read_to_string(&paths[1]).unwrap()).unwrap();
+    assert_eq!(guest.iter().map(|c| c.line).collect::<Vec<_>>(), [Some(2), Some(3)]);
+    assert_eq!(admin.iter().map(|c| c.line).collect::<Vec<_>>(), [Some(1)]);
+
+    assert!(export_authors_to_dir(&db.conn, &doc.id, &out.to_string_lossy(), "pdf").is_err());
+}
+
+// ============ 阅读顺序 ============
+
+#[test]
+fn stored_reading_order_matches_position() {
+    let db = TestDb::new();
+    let doc = db.document("order.md", "first second third");
+    let third = db.annotate_text(&doc, "third");
+    let first = db.annotate_text(&doc, "first");
+    let second = db.annotate_text(&doc, "second");
+    // 创建时间与位置相反；旧数据没有阅读顺序时按创建时间返回
+    for (anno, created_at) in [(&third, 1), (&first, 2), (&second, 3)] {
+        db.conn.execute("UPDATE annotations SET created_at = ?, reading_order = NULL WHERE id = ?", params![created_at, anno.id]).unwrap();
+    }
+    let listed = |conn: &Connection| -> Vec<String> {
+        get_annotations_by_doc(conn, &doc.id).unwrap().into_iter().map(|a| a.text).collect()
+    };
+    assert_eq!(listed(&db.conn), ["third", "first", "second"]);
+
+    assert_eq!(reindex_annotation_order(&db.conn, &doc.id, &doc.content).unwrap(), 3);
+    for (anno, rank) in [(&first, 0), (&second, 1), (&third, 2)] {
+        let stored: i64 = db.conn.query_row("SELECT reading_order FROM annotations WHERE id = ?", [&anno.id], |row| row.get(0)).unwrap();
+        assert_eq!(stored, rank);
+    }
+    assert_eq!(listed(&db.conn), ["first", "second", "third"]);
+}
+
+#[test]
+fn inserts_keep_reading_order_current() {
+    let db = TestDb::new();
+    let doc = db.document("inserts.md", "alpha beta gamma delta");
+    let listed = || -> Vec<String> {
+        get_annotations_by_doc(&db.conn, &doc.id).unwrap().into_iter().map(|a| a.text).collect()
+    };
+
+    // 新注解位于已有注解之前时，无需手动重算即排在前面
+    db.annotate_text(&doc, "gamma");
+    let alpha = db.annotate_text(&doc, "alpha");
+    assert_eq!(listed(), ["alpha", "gamma"]);
+
+    import_from_csv(&db.conn, "text\ndelta\nbeta\n", &doc.path).unwrap();
+    assert_eq!(listed(), ["alpha", "beta", "gamma", "delta"]);
+
+    delete_annotation(&db.conn, &alpha.id).unwrap();
+    undo_last_operation(&db.conn).unwrap();
+    assert_eq!(listed(), ["alpha", "beta", "gamma", "delta"]);
+}
+
+// ============ 版本差异 ============
+
+#[test]
+fn version_diff_marks_lines_and_places_annotations() {
+    let db = TestDb::new();
+    let old = "keep\nold line\nend\n";
+    let new = "keep\nnew line\nend\n";
+    let doc = db.document("draft.md", old);
+    let kept = db.annotate_note(&doc, "end", "closing");
+    let dropped = db.annotate_text(&doc, "old line");
+
+    let html = export_version_diff_html(&db.conn, &doc.id, old, new).unwrap();
+    assert!(html.contains(r#"<p class="summary">+1 / -1</p>"#));
+    assert!(html.contains(r#"<tr class="line delete"><td class="ln">2</td><td class="ln"></td><td class="marker">-</td><td class="code">old line</td></tr>"#));
+    assert!(html.contains(r#"<tr class="line insert"><td class="ln"></td><td class="ln">2</td><td class="marker">+</td><td class="code">new line</td></tr>"#));
+
+    // 注解紧跟在新版本中所在行之后
+    let end_row = html.find(r#"<td class="code">end</td></tr>"#).unwrap();
+    let kept_at = html.find(&format!(r#"data-anno-id="{}""#, kept.id)).unwrap();
+    assert!(kept_at > end_row);
+    assert!(html[end_row..kept_at].contains(r#"<tr class="anno-row">"#));
+    assert!(html[kept_at..].contains("<p>closing</p>"));
+
+    let lost_at = html.find(r#"<section class="lost">"#).unwrap();
+    assert!(html[lost_at..].contains(&dropped.id));
+}
+
+// ============ 批量显示/隐藏笔记 ============
+
+#[test]
+fn toggle_all_notes_visibility() {
+    let db = TestDb::new();
+    let doc = db.document("notes.md", "one two");
+    let other = db.document("other.md", "three");
+    db.annotate_text(&doc, "one");
+    db.annotate_text(&doc, "two");
+    let untouched = db.annotate_text(&other, "three");
+    db.conn.execute("UPDATE annotations SET updated_at = 0", []).unwrap();
+
+    assert_eq!(set_all_notes_visible(&db.conn, &doc.id, false).unwrap(), 2);
+    let hidden = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    assert!(hidden.iter().all(|a| !a.note_visible && a.updated_at > 0));
+    assert_eq!(get_annotation_by_id(&db.conn, &untouched.id).unwrap().unwrap().updated_at, 0);
+
+    assert_eq!(set_all_notes_visible(&db.conn, &doc.id, true).unwrap(), 2);
+    assert!(get_annotations_by_doc(&db.conn, &doc.id).unwrap().iter().all(|a| a.note_visible));
+}
+
+// ============ 便签二维码 ============
+
+#[test]
+fn html_export_adds_qr_per_note_when_enabled() {
+    let db = TestDb::new();
+    let doc = db.document("print.md", "alpha beta");
+    let ids: Vec<String> = ["alpha", "beta"].iter().map(|t| db.annotate_note(&doc, t, "scan me").id).collect();
+
+    let options = HtmlExportOptions { show_qr: true, ..Default::default() };
+    let (html, _) = export_as_html(&db.conn, &doc.id, &ids, &doc.content, &options).unwrap();
+    assert_eq!(html.matches(r#"<div class="note-qr""#).count(), 2);
+    for id in &ids {
+        let link = format!("annoti://open?doc={}&amp;anno={}", doc.id, id);
+        let at = html.find(&format!(r#"<div class="note-qr" title="{}"><svg"#, link)).unwrap();
+        assert!(html[at..].contains("</svg></div>"));
+    }
+
+    let (plain, _) = export_as_html(&db.conn, &doc.id, &ids, &doc.content, &HtmlExportOptions::default()).unwrap();
+    assert!(!plain.contains(r#"<div class="note-qr""#));
+}
+
+// ============ 文档活跃度 ============
+
+#[test]
+fn freshness_sorts_stale_documents_first() {
+    let db = TestDb::new();
+    let recent = db.document("recent.md", "fresh");
+    let stale = db.document("stale.md", "old old");
+    let empty = db.document("empty.md", "nothing");
+    db.annotate_text(&recent, "fresh");
+    let old = db.annotate_text(&stale, "old");
+    let older = db.build(&stale, 4, 7);
+    add_annotation(&db.conn, &older).unwrap();
+
+    let day = 86_400_000;
+    let now = Utc::now().timestamp_millis();
+    db.conn.execute("UPDATE annotations SET updated_at = ? WHERE id = ?", params![now - 10 * day, old.id]).unwrap();
+    db.conn.execute("UPDATE annotations SET updated_at = ? WHERE id = ?", params![now - 30 * day, older.id]).unwrap();
+
+    let freshness = get_document_freshness(&db.conn).unwrap();
+    let order: Vec<&str> = freshness.iter().map(|f| f.document_id.as_str()).collect();
+    assert_eq!(order, [stale.id.as_str(), recent.id.as_str(), empty.id.as_str()]);
+
+    assert_eq!(freshness[0].annotation_count, 2);
+    assert_eq!(freshness[0].last_annotation_at, Some(now - 10 * day));
+    assert_eq!(freshness[0].days_since, Some(10));
+    assert_eq!(freshness[1].days_since, Some(0));
+    assert_eq!((freshness[2].annotation_count, freshness[2].last_annotation_at, freshness[2].days_since), (0, None, None));
+}
+
+// ============ 分块导出 ============
+
+#[test]
+fn chunked_export_splits_five_annotations_into_two_files() {
+    let db = TestDb::new();
+    let doc = db.document("chunks.md", "a b c d e");
+    for text in ["e", "d", "c", "b", "a"] {
+        db.annotate_text(&doc, text);
+    }
+    let out = db.dir.join("chunks");
+    fs::create_dir_all(&out).unwrap();
+
+    let paths = export_annotations_chunked(&db.conn, &doc.id, 3, &out.to_string_lossy(), &ExportFilter::default()).unwrap();
+    let names: Vec<&str> = paths.iter().map(|p| p.rsplit(['/', '\\']).next().unwrap()).collect();
+    assert_eq!(names, ["chunks-1.annpkg", "chunks-2.annpkg"]);
+
+    let texts: Vec<Vec<String>> = paths.iter()
+        .map(|p| {
+            let package: BatchPackage = serde_json::from_str(&fs::read_to_string(p).unwrap()).unwrap();
+            assert_eq!(package.source_document.unwrap().checksum, doc.checksum);
+            package.annotations.into_iter().map(|a| a.text).collect()
+        })
+        .collect();
+    assert_eq!(texts, [vec!["a", "b", "c"], vec!["d", "e"]]);
+
+    assert!(export_annotations_chunked(&db.conn, &doc.id, 0, &out.to_string_lossy(), &ExportFilter::default()).is_err());
+}
+
+// ============ 导入时按作者名映射用户 ============
+
+#[test]
+fn imported_author_maps_to_existing_local_user() {
+    let db = TestDb::new();
+    let doc = db.document("shared.md", "imported text");
+    get_or_create_user(&db.conn, "admin".to_string()).unwrap(); // 先建当前用户
+    let alice = find_or_create_user_by_name(&db.conn, "Alice").unwrap();
+
+    let mut incoming = db.build(&doc, 0, "imported".len());
+    incoming.user_id = "foreign-id".to_string();
+    incoming.user_name = " alice ".to_string();
+    incoming.comments = vec![CommentRecord {
+        id: Uuid::new_v4().to_string(),
+        annotation_id: incoming.id.clone(),
+        user_id: "foreign-bob".to_string(),
+        user_name: "Bob".to_string(),
+        body: "agreed".to_string(),
+        created_at: 0,
+        parent_id: None,
+    }];
+
+    let resolved = resolve_import_users(&db.conn, vec![incoming]).unwrap();
+    assert_eq!((resolved[0].user_id.as_str(), resolved[0].user_name.as_str()), (alice.id.as_str(), "Alice"));
+    let bob = &resolved[0].comments[0];
+    assert_ne!(bob.user_id, "foreign-bob");
+    assert_eq!(get_user_by_id(&db.conn, &bob.user_id).unwrap().unwrap().name, "Bob");
+
+    assert_eq!(merge_imported_annotations(&db.conn, &resolved, &doc.id).unwrap(), 1);
+    let stored = get_annotations_by_doc(&db.conn, &doc.id).unwrap();
+    assert_eq!(stored[0].user_id, alice.id);
+    assert_eq!(filter_by_authors(stored, &["ALICE".to_string()]).len(), 1);
+}
+
+// ============ 锚点描述导出 ============
+
+#[test]
+fn anchor_descriptors_include_every_field() {
+    let db = TestDb::new();
+    let doc = db.document("anchors.md", "第一行\nsay hello world now");
+    let anno = db.annotate_text(&doc, "hello");
+
+    let json = export_anchors(&db.conn, &doc.id, &ExportFilter::default()).unwrap();
+    let raw: serde_json::Value = serde_json::from_str(&json).unwrap();
+    for key in ["id", "exact", "start", "end", "start_line", "start_column", "end_line", "end_column", "prefix", "suffix"] {
+        assert!(raw["anchors"][0].get(key).is_some_and(|v| !v.is_null()), "missing {}", key);
+    }
+
+    let export: AnchorExport = serde_json::from_str(&json).unwrap();
+    assert_eq!((export.document.as_str(), export.offset_unit.as_str()), ("anchors.md", "char"));
+    assert_eq!(export.checksum, doc.checksum);
+    let anchor = &export.anchors[0];
+    assert_eq!((anchor.id.as_str(), anchor.exact.as_str()), (anno.id.as_str(), "hello"));
+    // 偏移按字符计数，行列从 1 开始
+    assert_eq!((anchor.start, anchor.end), (Some(8), Some(13)));
+    assert_eq!((anchor.start_line, anchor.start_column), (Some(2), Some(5)));
+    assert_eq!((anchor.end_line, anchor.end_column), (Some(2), Some(10)));
+    assert_eq!(anchor.prefix.as_deref(), Some("第一行\nsay "));
+    assert_eq!(anchor.suffix.as_deref(), Some(" world now"));
+
+    // 与其他导出一样按颜色过滤
+    let filter = ExportFilter { colors: vec!["#87cefa".to_string()], ..ExportFilter::default() };
+    let filtered: AnchorExport = serde_json::from_str(&export_anchors(&db.conn, &doc.id, &filter).unwrap()).unwrap();
+    assert!(filtered.anchors.is_empty());
+}
+
+// ============ 启动自检 ============
+
+#[test]
+fn startup_diagnostics_recreate_missing_index() {
+    init_app_data();
+    // 先完成共享连接的初始化，避免其他测试在检查前重建索引
+    drop(init_db_for_write().unwrap());
+    drop(read_db().unwrap());
+    init_db_for_write().unwrap().execute_batch("DROP INDEX idx_annotations_order").unwrap();
+
+    let report = run_startup_diagnostics().unwrap();
+    assert!(report.integrity_ok);
+    assert_eq!(report.schema_version, SCHEMA_VERSION);
+    assert!(!report.has_fatal());
+    let issue = report.issues.iter()
+        .find(|i| i.kind == "missing_index" && i.object.as_deref() == Some("idx_annotations_order"))
+        .unwrap();
+    assert!(issue.fixed && !issue.fatal);
+
+    let conn = init_db_for_write().unwrap();
+    assert!(sqlite_object_names(&conn, "index").unwrap().contains("idx_annotations_order"));
+}
@@ -1,19 +1,42 @@
 use std::fs::{self, File};
 use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 mod db;
 
+// ============ 演示模式 ============
+
+// 演示模式开关，启动时从设置读取；开启时注解与文档的修改命令返回 ReadOnlyMode，导出等只读操作不受影响
+struct PresentationMode(AtomicBool);
+
+impl PresentationMode {
+    fn check(&self) -> Result<(), String> {
+        if self.0.load(Ordering::Relaxed) {
+            return Err(db::PRESENTATION_MODE_ERROR.to_string());
+        }
+        Ok(())
+    }
+}
+
 // ============ 基础文件操作 ============
 
+// 文档大小上限在命令层从设置读取，数据库层只做比较
+fn max_document_bytes() -> Result<u64, String> {
+    Ok(db::load_settings()?.editor.max_document_bytes)
+}
+
 #[tauri::command]
 fn read_file_content(path: String) -> Result<String, String> {
-    println!("正在读取文件: {}", path);
+    db::log_info(&format!("正在读取文件: {}", path));
+    let size = fs::metadata(&path).map_err(|err| err.to_string())?.len();
+    db::check_document_size(size, max_document_bytes()?)?;
     fs::read_to_string(&path).map_err(|err| err.to_string())
 }
 
 #[tauri::command]
-fn write_file_content(path: String, content: String) -> Result<(), String> {
-    println!("正在写入文件: {}", path);
+fn write_file_content(path: String, content: String, presentation: tauri::State<'_, PresentationMode>) -> Result<(), String> {
+    presentation.check()?;
+    db::log_info(&format!("正在写入文件: {}", path));
     let mut file = File::create(&path).map_err(|err| err.to_string())?;
     file.write_all(content.as_bytes()).map_err(|err| err.to_string())?;
     Ok(())
@@ -32,19 +55,37 @@ async fn init_db() -> Result<(), String> {
     Ok(())
 }
 
+#[tauri::command]
+async fn run_startup_diagnostics() -> Result<db::DiagnosticsReport, String> {
+    db::run_startup_diagnostics()
+}
+
+#[tauri::command]
+async fn set_presentation_mode(enabled: bool, presentation: tauri::State<'_, PresentationMode>) -> Result<(), String> {
+    db::set_presentation_mode(enabled)?;
+    presentation.0.store(enabled, Ordering::Relaxed);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_presentation_mode(presentation: tauri::State<'_, PresentationMode>) -> Result<bool, String> {
+    Ok(presentation.0.load(Ordering::Relaxed))
+}
+
 // ============ 用户操作 ============
 
 #[tauri::command]
 async fn get_current_user() -> Result<db::UserRecord, String> {
-    let conn = db::init_db()?;
+    let conn = db::init_db_for_write()?;
     let user = db::get_or_create_user(&conn, "admin".to_string())
         .map_err(|e| e.to_string())?;
     Ok(user)
 }
 
 #[tauri::command]
-async fn update_user_name(name: String) -> Result<(), String> {
-    let conn = db::init_db()?;
+async fn update_user_name(name: String, presentation: tauri::State<'_, PresentationMode>) -> Result<(), String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
     // 获取当前用户ID
     let user_id = {
         let mut stmt = conn.prepare("SELECT id FROM users LIMIT 1").map_err(|e| e.to_string())?;
@@ -60,6 +101,27 @@ async fn update_user_name(name: String) -> Result<(), String> {
     Ok(())
 }
 
+#[tauri::command]
+async fn reconcile_user(presentation: tauri::State<'_, PresentationMode>) -> Result<db::UserRecord, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::reconcile_user(&conn)
+}
+
+#[tauri::command]
+async fn reassign_annotations(from_user_id: String, to_user_id: String, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::reassign_annotations(&conn, &from_user_id, &to_user_id)
+}
+
+#[tauri::command]
+async fn delete_user(id: String, delete_annotations: bool, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::delete_user(&conn, &id, delete_annotations)
+}
+
 #[tauri::command]
 async fn generate_random_name() -> Result<String, String> {
     Ok(db::generate_random_name())
@@ -68,66 +130,382 @@ async fn generate_random_name() -> Result<String, String> {
 // ============ 文档操作 ============
 
 #[tauri::command]
-async fn save_document(path: String, content: String) -> Result<db::DocumentRecord, String> {
-    let conn = db::init_db()?;
+async fn save_document(path: String, content: String, presentation: tauri::State<'_, PresentationMode>) -> Result<db::DocumentRecord, String> {
+    presentation.check()?;
+    db::check_document_size(content.len() as u64, max_document_bytes()?)?;
+    let conn = db::init_db_for_write()?;
     db::save_document(&conn, &path, &content).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 async fn get_document(path: String) -> Result<Option<db::DocumentRecord>, String> {
-    let conn = db::init_db()?;
+    let conn = db::read_db()?;
     db::get_document_by_path(&conn, &path).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn append_to_document(path: String, additional_content: String, presentation: tauri::State<'_, PresentationMode>) -> Result<db::DocumentRecord, String> {
+    presentation.check()?;
+    let max_bytes = max_document_bytes()?;
+    let conn = db::init_db_for_write()?;
+    db::append_to_document(&conn, &path, &additional_content, max_bytes)
+}
+
+#[tauri::command]
+async fn delete_document(doc_id: String, presentation: tauri::State<'_, PresentationMode>) -> Result<(), String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::delete_document(&conn, &doc_id)
+}
+
+#[tauri::command]
+async fn recompute_checksums(presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::recompute_checksums(&conn)
+}
+
+#[tauri::command]
+async fn compute_file_checksum(path: String) -> Result<String, String> {
+    db::compute_file_checksum(&path)
+}
+
+#[tauri::command]
+async fn detect_document_format(content: String) -> Result<db::DocumentFormat, String> {
+    Ok(db::detect_document_format(&content))
+}
+
+#[tauri::command]
+async fn generate_project_manifest(dir: String) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::generate_project_manifest(&conn, &dir)
+}
+
+#[tauri::command]
+async fn get_document_outline(content: String) -> Result<Vec<db::OutlineEntry>, String> {
+    Ok(db::get_document_outline(&content))
+}
+
+#[tauri::command]
+async fn detect_language(content: String) -> Result<String, String> {
+    Ok(db::detect_language(&content))
+}
+
 // ============ 注解操作 ============
 
 #[tauri::command]
 async fn get_annotations(doc_id: String) -> Result<Vec<db::AnnotationRecord>, String> {
-    let conn = db::init_db()?;
+    let conn = db::read_db()?;
     db::get_annotations_by_doc(&conn, &doc_id).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn add_annotation(annotation: String) -> Result<(), String> {
-    let anno: db::AnnotationRecord = serde_json::from_str(&annotation)
+async fn get_unnoted_annotations(doc_id: String) -> Result<Vec<db::AnnotationRecord>, String> {
+    let conn = db::read_db()?;
+    db::get_unnoted_annotations(&conn, &doc_id)
+}
+
+#[tauri::command]
+async fn get_annotations_in_range(doc_id: String, start: usize, end: usize) -> Result<Vec<db::AnnotationRecord>, String> {
+    let conn = db::read_db()?;
+    db::get_annotations_in_range(&conn, &doc_id, start, end)
+}
+
+#[tauri::command]
+async fn add_annotation(annotation: String, presentation: tauri::State<'_, PresentationMode>) -> Result<(), String> {
+    presentation.check()?;
+    let mut anno: db::AnnotationRecord = serde_json::from_str(&annotation)
         .map_err(|e| e.to_string())?;
-    let conn = db::init_db()?;
-    db::add_annotation(&conn, &anno).map_err(|e| e.to_string())
+    anno.highlight_color = db::resolve_highlight_color(&anno.highlight_color)?;
+    let conn = db::init_db_for_write()?;
+    db::with_journal("add", &anno, || db::add_annotation(&conn, &anno))
+}
+
+#[tauri::command]
+async fn create_annotation(
+    doc_path: String,
+    start: usize,
+    end: usize,
+    content: String,
+    note: Option<String>,
+    color: Option<String>,
+    presentation: tauri::State<'_, PresentationMode>,
+) -> Result<db::AnnotationRecord, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::create_annotation(&conn, &doc_path, start, end, &content, note, color)
 }
 
 #[tauri::command]
-async fn update_annotation(annotation: String) -> Result<(), String> {
+async fn update_annotation(annotation: String, presentation: tauri::State<'_, PresentationMode>) -> Result<(), String> {
+    presentation.check()?;
     let anno: db::AnnotationRecord = serde_json::from_str(&annotation)
         .map_err(|e| e.to_string())?;
-    let conn = db::init_db()?;
-    db::update_annotation(&conn, &anno).map_err(|e| e.to_string())
+    let conn = db::init_db_for_write()?;
+    db::with_journal("update", &anno, || db::update_annotation(&conn, &anno))
+}
+
+#[tauri::command]
+async fn archive_annotations(doc_id: String, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::archive_annotations(&conn, &doc_id)
+}
+
+#[tauri::command]
+async fn unarchive_annotations(doc_id: String, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::unarchive_annotations(&conn, &doc_id)
+}
+
+#[tauri::command]
+async fn recover_from_journal(presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    // 演示模式下跳过恢复并保留日志，退出演示模式后的下次启动再重放，不阻断前端初始化
+    if presentation.check().is_err() {
+        return Ok(0);
+    }
+    let conn = db::init_db_for_write()?;
+    db::recover_from_journal(&conn)
+}
+
+#[tauri::command]
+async fn read_recent_logs(lines: usize) -> Result<String, String> {
+    db::read_recent_logs(lines)
+}
+
+#[tauri::command]
+async fn get_annotation_context(anno_id: String, content: String, radius: usize) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::get_annotation_context(&conn, &anno_id, &content, radius)
+}
+
+#[tauri::command]
+async fn change_highlight_type(doc_id: String, from_type: String, to_type: String, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::change_highlight_type(&conn, &doc_id, &from_type, &to_type)
+}
+
+#[tauri::command]
+async fn set_all_notes_visible(doc_id: String, visible: bool, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::set_all_notes_visible(&conn, &doc_id, visible)
+}
+
+#[tauri::command]
+async fn clamp_note_positions(doc_id: String, max_x: f64, max_y: f64, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::clamp_note_positions(&conn, &doc_id, max_x, max_y)
+}
+
+#[tauri::command]
+async fn replace_in_notes(doc_id: String, find: String, replace: String, case_sensitive: bool, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::replace_in_notes(&conn, &doc_id, &find, &replace, case_sensitive)
+}
+
+#[tauri::command]
+async fn dedupe_annotations(doc_id: String, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::dedupe_annotations(&conn, &doc_id)
+}
+
+#[tauri::command]
+async fn get_annotation_slug(id: String) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::get_annotation_slug(&conn, &id)
+}
+
+#[tauri::command]
+async fn merge_annotations(keep_id: String, merge_id: String, presentation: tauri::State<'_, PresentationMode>) -> Result<db::AnnotationRecord, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::merge_annotations(&conn, &keep_id, &merge_id)
+}
+
+#[tauri::command]
+async fn split_annotation(id: String, split_offset: usize, content: String, presentation: tauri::State<'_, PresentationMode>) -> Result<(db::AnnotationRecord, db::AnnotationRecord), String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::split_annotation(&conn, &id, split_offset, &content)
 }
 
 #[tauri::command]
-async fn delete_annotation(id: String) -> Result<(), String> {
-    let conn = db::init_db()?;
+async fn delete_annotation(id: String, presentation: tauri::State<'_, PresentationMode>) -> Result<(), String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
     db::delete_annotation(&conn, &id).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn undo_last_operation(presentation: tauri::State<'_, PresentationMode>) -> Result<Option<String>, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::undo_last_operation(&conn)
+}
+
+// ============ 注解补丁 ============
+
+#[tauri::command]
+async fn export_annotation_patch(doc_id: String, since_ms: i64) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_annotation_patch(&conn, &doc_id, since_ms)
+}
+
+#[tauri::command]
+async fn apply_annotation_patch(patch_json: String, doc_path: String, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::apply_annotation_patch(&conn, &patch_json, &doc_path)
+}
+
+// ============ 文档快照 ============
+
+#[tauri::command]
+async fn create_snapshot(path: String, label: String, presentation: tauri::State<'_, PresentationMode>) -> Result<db::SnapshotRecord, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::create_snapshot(&conn, &path, &label)
+}
+
+#[tauri::command]
+async fn list_snapshots(path: String) -> Result<Vec<db::SnapshotRecord>, String> {
+    let conn = db::read_db()?;
+    db::list_snapshots(&conn, &path)
+}
+
+#[tauri::command]
+async fn restore_snapshot(snapshot_id: String, presentation: tauri::State<'_, PresentationMode>) -> Result<db::DocumentRecord, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::restore_snapshot(&conn, &snapshot_id)
+}
+
+// ============ 批量重新锚定 ============
+
+#[tauri::command]
+async fn compare_versions(doc_id: String, new_content: String) -> Result<db::VersionComparison, String> {
+    let conn = db::read_db()?;
+    db::compare_versions(&conn, &doc_id, &new_content)
+}
+
+#[tauri::command]
+async fn measure_anchor_drift(doc_id: String, new_content: String) -> Result<Vec<db::AnchorDrift>, String> {
+    let conn = db::read_db()?;
+    db::measure_anchor_drift(&conn, &doc_id, &new_content)
+}
+
+#[tauri::command]
+async fn export_version_diff_html(old_content: String, new_content: String, doc_id: String) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_version_diff_html(&conn, &doc_id, &old_content, &new_content)
+}
+
+#[tauri::command]
+async fn reindex_annotation_order(doc_id: String, content: String, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::reindex_annotation_order(&conn, &doc_id, &content)
+}
+
+#[tauri::command]
+async fn audit_annotation_consistency(doc_id: String, content: String) -> Result<Vec<db::ConsistencyIssue>, String> {
+    let conn = db::read_db()?;
+    db::audit_annotation_consistency(&conn, &doc_id, &content)
+}
+
+#[tauri::command]
+async fn reanchor_project(dir: String, presentation: tauri::State<'_, PresentationMode>) -> Result<std::collections::HashMap<String, db::ReanchorReport>, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::reanchor_project(&conn, &dir)
+}
+
+// ============ 评论 ============
+
+#[tauri::command]
+async fn get_comments(annotation_id: String) -> Result<Vec<db::CommentRecord>, String> {
+    let conn = db::read_db()?;
+    db::get_comments_by_annotation(&conn, &annotation_id)
+}
+
+#[tauri::command]
+async fn add_comment(annotation_id: String, body: String, parent_id: Option<String>, presentation: tauri::State<'_, PresentationMode>) -> Result<db::CommentRecord, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::add_comment(&conn, &annotation_id, &body, parent_id.as_deref())
+}
+
+#[tauri::command]
+async fn get_annotation_thread(annotation_id: String) -> Result<db::AnnotationThread, String> {
+    let conn = db::read_db()?;
+    db::get_annotation_thread(&conn, &annotation_id)
+}
+
+// ============ 标签 ============
+
+#[tauri::command]
+async fn set_annotation_tags(anno_id: String, tags: Vec<String>, presentation: tauri::State<'_, PresentationMode>) -> Result<Vec<String>, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::set_annotation_tags(&conn, &anno_id, &tags)
+}
+
+#[tauri::command]
+async fn get_annotation_tags(doc_id: String) -> Result<std::collections::HashMap<String, Vec<String>>, String> {
+    let conn = db::read_db()?;
+    db::get_annotation_tags(&conn, &doc_id)
+}
+
+#[tauri::command]
+async fn list_all_tags() -> Result<Vec<(String, usize)>, String> {
+    let conn = db::read_db()?;
+    db::list_all_tags(&conn)
+}
+
 // ============ 单注解导出/导入 ============
 
 #[tauri::command]
-async fn export_annotation(anno_id: String, doc_path: String) -> Result<String, String> {
-    let conn = db::init_db()?;
-    db::export_annotation(&conn, &anno_id, &doc_path).map_err(|e| e.to_string())
+async fn export_annotation(anno_id: String, doc_path: String, viewport: Option<db::Viewport>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_annotation(&conn, &anno_id, &doc_path, viewport).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn import_annotation(json: String) -> Result<String, String> {
-    let annotations = db::import_annotation(&json).map_err(|e| e.to_string())?;
+async fn export_annotations_chunked(doc_id: String, per_file: usize, dest_dir: String, filter: Option<db::ExportFilter>) -> Result<Vec<String>, String> {
+    let conn = db::read_db()?;
+    let paths = db::export_annotations_chunked(&conn, &doc_id, per_file, &dest_dir, &filter.unwrap_or_default())?;
+    record_exports(Some(&doc_id), "annpkg", &paths);
+    Ok(paths)
+}
+
+#[tauri::command]
+async fn import_annotation(json: String, viewport: Option<db::Viewport>) -> Result<String, String> {
+    let annotations = db::import_annotation(&json, viewport).map_err(|e| e.to_string())?;
     serde_json::to_string(&annotations).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn merge_imported_annotations(annotations_json: String, doc_path: String) -> Result<usize, String> {
+async fn preview_import(package_json: String, limit: usize) -> Result<Vec<db::AnnotationRecord>, String> {
+    db::preview_import(&package_json, limit)
+}
+
+#[tauri::command]
+async fn suggest_import_target(package_json: String, candidate_dir: String) -> Result<Vec<(String, f64)>, String> {
+    db::suggest_import_target(&package_json, &candidate_dir)
+}
+
+#[tauri::command]
+async fn merge_imported_annotations(annotations_json: String, doc_path: String, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
     let annotations: Vec<db::AnnotationRecord> = serde_json::from_str(&annotations_json)
         .map_err(|e| e.to_string())?;
-    let conn = db::init_db()?;
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
 
     // 获取文档 ID
     let doc = db::get_document_by_path(&conn, &doc_path)?
@@ -137,10 +515,50 @@ async fn merge_imported_annotations(annotations_json: String, doc_path: String)
 }
 
 #[tauri::command]
-async fn merge_imported_annotation(annotation_json: String, doc_path: String) -> Result<(), String> {
+async fn merge_imported_annotations_with_mode(
+    annotations_json: String,
+    doc_path: String,
+    mode: Option<db::DedupMode>,
+    overlap_threshold: Option<f64>,
+    resolve_users_by_name: Option<bool>,
+    presentation: tauri::State<'_, PresentationMode>,
+) -> Result<db::MergeReport, String> {
+    let annotations: Vec<db::AnnotationRecord> = serde_json::from_str(&annotations_json)
+        .map_err(|e| e.to_string())?;
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    // 按作者名映射到本地用户，而不是保留外部 user_id
+    let annotations = if resolve_users_by_name.unwrap_or(false) {
+        db::resolve_import_users(&conn, annotations)?
+    } else {
+        annotations
+    };
+
+    let doc = db::get_document_by_path(&conn, &doc_path)?
+        .ok_or_else(|| "Document not found".to_string())?;
+
+    db::merge_imported_annotations_with_mode(
+        &conn,
+        &annotations,
+        &doc.id,
+        mode.unwrap_or_default(),
+        overlap_threshold.unwrap_or(db::DEFAULT_OVERLAP_THRESHOLD),
+    )
+}
+
+#[tauri::command]
+async fn import_packages_from_dir(dir: String, doc_path: String, strategy: String, presentation: tauri::State<'_, PresentationMode>) -> Result<db::ImportSummary, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::import_packages_from_dir(&conn, &dir, &doc_path, &strategy)
+}
+
+#[tauri::command]
+async fn merge_imported_annotation(annotation_json: String, doc_path: String, presentation: tauri::State<'_, PresentationMode>) -> Result<(), String> {
     let anno: db::AnnotationRecord = serde_json::from_str(&annotation_json)
         .map_err(|e| e.to_string())?;
-    let conn = db::init_db()?;
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
 
     // 获取文档 ID
     let doc = db::get_document_by_path(&conn, &doc_path)?
@@ -149,29 +567,263 @@ async fn merge_imported_annotation(annotation_json: String, doc_path: String) ->
     db::merge_imported_annotation(&conn, &anno, &doc.id).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn import_from_markdown_highlights(md: String, doc_path: String, presentation: tauri::State<'_, PresentationMode>) -> Result<db::ImportReport, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::import_from_markdown_highlights(&conn, &md, &doc_path)
+}
+
+#[tauri::command]
+async fn import_web_annotations(json: String, doc_path: String, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::import_web_annotations(&conn, &json, &doc_path)
+}
+
+#[tauri::command]
+async fn import_from_csv(csv: String, doc_path: String, presentation: tauri::State<'_, PresentationMode>) -> Result<db::ImportReport, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::import_from_csv(&conn, &csv, &doc_path)
+}
+
 // ============ HTML 导出 ============
 
 #[tauri::command]
-async fn export_as_html(doc_id: String, anno_ids: Vec<String>, content: String) -> Result<String, String> {
-    let conn = db::init_db()?;
-    db::export_as_html(&conn, &doc_id, &anno_ids, &content).map_err(|e| e.to_string())
+async fn export_as_html(doc_id: String, anno_ids: Vec<String>, content: String, options: Option<db::HtmlExportOptions>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    let options = options.unwrap_or_default();
+    db::export_as_html(&conn, &doc_id, &anno_ids, &content, &options).map(|(html, _)| html)
 }
 
 #[tauri::command]
-async fn save_html_file(path: String, html: String) -> Result<(), String> {
+async fn measure_html_export(doc_id: String, anno_ids: Vec<String>, content: String, options: Option<db::HtmlExportOptions>) -> Result<Option<db::HtmlExportTiming>, String> {
+    let conn = db::read_db()?;
+    let options = options.unwrap_or_default();
+    db::export_as_html(&conn, &doc_id, &anno_ids, &content, &options).map(|(_, timing)| timing)
+}
+
+#[tauri::command]
+async fn preview_export(doc_id: String, anno_ids: Vec<String>, content: String, format: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::preview_export(&conn, &format, &doc_id, &anno_ids, &content, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_and_save(doc_id: String, anno_ids: Vec<String>, content: String, dest_path: String, format: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    let path = db::export_and_save(&conn, &doc_id, &anno_ids, &content, &dest_path, &format, &filter.unwrap_or_default())?;
+    record_exports(Some(&doc_id), &format.trim().to_ascii_lowercase(), std::slice::from_ref(&path));
+    Ok(path)
+}
+
+#[tauri::command]
+async fn export_authors_to_dir(doc_id: String, dest_dir: String, format: String) -> Result<Vec<String>, String> {
+    let conn = db::read_db()?;
+    let paths = db::export_authors_to_dir(&conn, &doc_id, &dest_dir, &format)?;
+    record_exports(Some(&doc_id), &format.trim().to_ascii_lowercase(), &paths);
+    Ok(paths)
+}
+
+#[tauri::command]
+async fn save_html_file(path: String, html: String, doc_id: Option<String>) -> Result<(), String> {
+    let path = db::resolve_export_path(&path)?;
     let mut file = File::create(&path).map_err(|e| e.to_string())?;
     file.write_all(html.as_bytes()).map_err(|e| e.to_string())?;
+
+    record_exports(doc_id.as_deref(), "html", &[path.to_string_lossy().to_string()]);
     Ok(())
 }
 
+// 导出记录仅为簿记：在导出完成后单独取写连接记录，失败（如数据库只读）时记入日志而不影响已完成的导出
+fn record_exports(doc_id: Option<&str>, format: &str, paths: &[String]) {
+    let recorded = db::init_db_for_write()
+        .and_then(|conn| paths.iter().try_for_each(|path| db::record_export(&conn, doc_id, format, path)));
+    if let Err(e) = recorded {
+        db::log_warn(&format!("Failed to record export {}: {}", paths.join(", "), e));
+    }
+}
+
+#[tauri::command]
+async fn list_recent_exports(limit: Option<usize>) -> Result<Vec<db::ExportRecord>, String> {
+    let conn = db::read_db()?;
+    db::list_recent_exports(&conn, limit.unwrap_or(20))
+}
+
+// ============ 其他格式导出 ============
+
+#[tauri::command]
+async fn export_as_github_review(doc_id: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_as_github_review(&conn, &doc_id, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_as_cornell(doc_id: String, content: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_as_cornell(&conn, &doc_id, &content, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_as_index_cards(doc_id: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_as_index_cards(&conn, &doc_id, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_as_mermaid(doc_id: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_as_mermaid(&conn, &doc_id, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_as_obsidian(doc_id: String, content: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_as_obsidian(&conn, &doc_id, &content, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_as_positioned_notes(doc_id: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_as_positioned_notes(&conn, &doc_id, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_heatmap_json(doc_id: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_heatmap_json(&conn, &doc_id, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_as_bibtex(doc_id: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_as_bibtex(&conn, &doc_id, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_as_kanban(doc_id: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_as_kanban(&conn, &doc_id, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_anchors(doc_id: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_anchors(&conn, &doc_id, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_for_llm(doc_id: String, max_quote_chars: Option<usize>, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_for_llm(&conn, &doc_id, max_quote_chars.unwrap_or(db::DEFAULT_LLM_QUOTE_LIMIT), &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_as_epub(doc_id: String, content: String, dest_path: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    let path = db::export_as_epub(&conn, &doc_id, &content, &dest_path, &filter.unwrap_or_default())?;
+    record_exports(Some(&doc_id), "epub", std::slice::from_ref(&path));
+    Ok(path)
+}
+
+#[tauri::command]
+async fn export_project_pdf(dir: String, dest_path: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    let path = db::export_project_pdf(&conn, &dir, &dest_path, &filter.unwrap_or_default())?;
+    record_exports(None, "pdf", std::slice::from_ref(&path));
+    Ok(path)
+}
+
+#[tauri::command]
+async fn export_with_template(doc_id: String, template: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_with_template(&conn, &doc_id, &template, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_by_tag(doc_id: String, filter: Option<db::ExportFilter>) -> Result<std::collections::HashMap<String, String>, String> {
+    let conn = db::read_db()?;
+    db::export_by_tag(&conn, &doc_id, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_note_positions(doc_id: String, filter: Option<db::ExportFilter>) -> Result<String, String> {
+    let conn = db::read_db()?;
+    db::export_note_positions(&conn, &doc_id, &filter.unwrap_or_default())
+}
+
+#[tauri::command]
+async fn export_annotations_sqlite(dest_path: String) -> Result<(), String> {
+    let conn = db::read_db()?;
+    let path = db::export_annotations_sqlite(&conn, &dest_path)?;
+    record_exports(None, "sqlite", &[path]);
+    Ok(())
+}
+
+// ============ 统计 ============
+
+#[tauri::command]
+async fn get_highlight_word_frequencies(doc_id: Option<String>, top_n: usize) -> Result<Vec<(String, usize)>, String> {
+    let conn = db::read_db()?;
+    db::get_highlight_word_frequencies(&conn, doc_id.as_deref(), top_n)
+}
+
+#[tauri::command]
+async fn get_annotation_coverage(doc_id: String, content: String) -> Result<f64, String> {
+    let conn = db::read_db()?;
+    db::get_annotation_coverage(&conn, &doc_id, &content)
+}
+
+#[tauri::command]
+async fn get_document_freshness() -> Result<Vec<db::DocumentFreshness>, String> {
+    let conn = db::read_db()?;
+    db::get_document_freshness(&conn)
+}
+
+#[tauri::command]
+async fn get_activity_timeline(from_ms: i64, to_ms: i64) -> Result<Vec<db::DayActivity>, String> {
+    let conn = db::read_db()?;
+    db::get_activity_timeline(&conn, from_ms, to_ms)
+}
+
+#[tauri::command]
+async fn document_similarity(path_a: String, path_b: String) -> Result<f64, String> {
+    let conn = db::read_db()?;
+    db::document_similarity(&conn, &path_a, &path_b)
+}
+
+// ============ 引用完整性 ============
+
+#[tauri::command]
+async fn check_referential_integrity() -> Result<db::IntegrityIssues, String> {
+    let conn = db::read_db()?;
+    db::check_referential_integrity(&conn)
+}
+
+#[tauri::command]
+async fn fix_referential_integrity(strategy: db::IntegrityFixStrategy, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::fix_referential_integrity(&conn, strategy)
+}
+
 // ============ 迁移 ============
 
 #[tauri::command]
-async fn migrate_sidecar_files(base_dir: String) -> Result<(), String> {
-    let conn = db::init_db()?;
+async fn migrate_sidecar_files(base_dir: String, presentation: tauri::State<'_, PresentationMode>) -> Result<db::SidecarMigrationReport, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
     db::migrate_sidecar_files(&conn, &base_dir).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn import_sidecar_file(path: String, presentation: tauri::State<'_, PresentationMode>) -> Result<usize, String> {
+    presentation.check()?;
+    let conn = db::init_db_for_write()?;
+    db::import_sidecar_file(&conn, &path)
+}
+
 // ============ 设置 ============
 
 #[tauri::command]
@@ -181,10 +833,44 @@ async fn load_settings() -> Result<String, String> {
 }
 
 #[tauri::command]
-async fn save_settings(settings_json: String) -> Result<(), String> {
+async fn save_settings(settings_json: String, presentation: tauri::State<'_, PresentationMode>) -> Result<(), String> {
     let settings: db::SettingsRecord = serde_json::from_str(&settings_json)
         .map_err(|e| e.to_string())?;
-    db::save_settings(&settings).map_err(|e| e.to_string())
+    db::save_settings(&settings).map_err(|e| e.to_string())?;
+    presentation.0.store(settings.editor.presentation_mode, Ordering::Relaxed);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_default_export_dir() -> Result<String, String> {
+    Ok(db::get_default_export_dir()?.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+async fn set_default_export_dir(dir: Option<String>) -> Result<(), String> {
+    db::set_default_export_dir(dir)
+}
+
+#[tauri::command]
+async fn export_portable_settings() -> Result<String, String> {
+    db::export_portable_settings()
+}
+
+#[tauri::command]
+async fn import_portable_settings(json: String, presentation: tauri::State<'_, PresentationMode>) -> Result<db::SettingsRecord, String> {
+    let settings = db::import_portable_settings(&json)?;
+    presentation.0.store(settings.editor.presentation_mode, Ordering::Relaxed);
+    Ok(settings)
+}
+
+#[tauri::command]
+async fn check_color_contrast(background: String, foreground: String) -> Result<db::ContrastResult, String> {
+    db::check_color_contrast(&background, &foreground)
+}
+
+#[tauri::command]
+async fn get_color_presets() -> Result<Vec<db::ColorPreset>, String> {
+    db::get_color_presets()
 }
 
 #[tauri::command]
@@ -248,6 +934,16 @@ async fn save_ui_settings(settings_json: String) -> Result<(), String> {
     db::save_ui_settings(&settings).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn get_ui_setting(key: String) -> Result<Option<serde_json::Value>, String> {
+    db::get_ui_setting(&key)
+}
+
+#[tauri::command]
+async fn set_ui_setting(key: String, value: serde_json::Value) -> Result<(), String> {
+    db::set_ui_setting(&key, value)
+}
+
 // ============ 排版配置 ============
 
 #[tauri::command]
@@ -279,42 +975,206 @@ async fn save_typography_config(content: String) -> Result<(), String> {
     Ok(())
 }
 
+#[tauri::command]
+async fn reset_typography_config() -> Result<String, String> {
+    db::reset_typography_config()
+}
+
+// ============ 合并配置 ============
+
+#[tauri::command]
+async fn get_effective_config() -> Result<String, String> {
+    db::get_effective_config()
+}
+
+// ============ 配置导入 ============
+
+#[tauri::command]
+async fn import_config_from_dir(source_dir: String, presentation: tauri::State<'_, PresentationMode>) -> Result<(), String> {
+    db::import_config_from_dir(&source_dir)?;
+    // 导入的设置可能切换了演示模式，与 save_settings 一样同步内存中的开关
+    let settings = db::load_settings()?;
+    presentation.0.store(settings.editor.presentation_mode, Ordering::Relaxed);
+    Ok(())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // 启动自检：缺失的表和索引自动重建，问题写入应用日志
+    if let Err(e) = db::run_startup_diagnostics() {
+        db::log_error(&format!("Startup diagnostics failed: {}", e));
+    }
+
+    let presentation_mode = db::load_settings()
+        .map(|s| s.editor.presentation_mode)
+        .unwrap_or(false);
+
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
+        .manage(PresentationMode(AtomicBool::new(presentation_mode)))
         .invoke_handler(tauri::generate_handler![
             read_file_content,
             write_file_content,
             file_exists,
             init_db,
+            run_startup_diagnostics,
+            set_presentation_mode,
+            get_presentation_mode,
             get_current_user,
             update_user_name,
+            reconcile_user,
             generate_random_name,
+            reassign_annotations,
+            delete_user,
             save_document,
             get_document,
+            append_to_document,
+            delete_document,
+            recompute_checksums,
+            compute_file_checksum,
+            detect_document_format,
+            generate_project_manifest,
+            get_document_outline,
+            detect_language,
             get_annotations,
+            get_unnoted_annotations,
+            get_annotations_in_range,
             add_annotation,
+            create_annotation,
             update_annotation,
+            recover_from_journal,
+            read_recent_logs,
+            archive_annotations,
+            unarchive_annotations,
+            get_annotation_context,
+            change_highlight_type,
+            set_all_notes_visible,
+            clamp_note_positions,
+            replace_in_notes,
             delete_annotation,
+            dedupe_annotations,
+            get_annotation_slug,
+            merge_annotations,
+            split_annotation,
+            undo_last_operation,
+            export_annotation_patch,
+            apply_annotation_patch,
+            create_snapshot,
+            list_snapshots,
+            restore_snapshot,
+            reanchor_project,
+            compare_versions,
+            measure_anchor_drift,
+            export_version_diff_html,
+            reindex_annotation_order,
+            audit_annotation_consistency,
+            get_comments,
+            add_comment,
+            get_annotation_thread,
+            set_annotation_tags,
+            get_annotation_tags,
+            list_all_tags,
             export_annotation,
+            export_annotations_chunked,
             import_annotation,
+            preview_import,
+            suggest_import_target,
             merge_imported_annotations,
+            merge_imported_annotations_with_mode,
+            import_packages_from_dir,
             merge_imported_annotation,
+            import_from_csv,
+            import_web_annotations,
+            import_from_markdown_highlights,
             export_as_html,
+            measure_html_export,
             save_html_file,
+            list_recent_exports,
+            preview_export,
+            export_and_save,
+            export_authors_to_dir,
+            export_as_github_review,
+            export_as_cornell,
+            export_as_index_cards,
+            export_as_mermaid,
+            export_as_obsidian,
+            export_as_positioned_notes,
+            export_heatmap_json,
+            export_as_bibtex,
+            export_as_kanban,
+            export_anchors,
+            export_for_llm,
+            export_as_epub,
+            export_project_pdf,
+            export_with_template,
+            export_by_tag,
+            export_note_positions,
+            export_annotations_sqlite,
+            get_highlight_word_frequencies,
+            get_annotation_coverage,
+            get_document_freshness,
+            get_activity_timeline,
+            document_similarity,
+            check_referential_integrity,
+            fix_referential_integrity,
             migrate_sidecar_files,
+            import_sidecar_file,
             load_settings,
             save_settings,
             get_settings_path,
+            get_color_presets,
+            check_color_contrast,
+            export_portable_settings,
+            import_portable_settings,
+            get_default_export_dir,
+            set_default_export_dir,
             open_path,
             get_db_path,
             load_ui_settings,
             save_ui_settings,
+            get_ui_setting,
+            set_ui_setting,
             get_typography_path,
             load_typography_config,
-            save_typography_config
+            save_typography_config,
+            reset_typography_config,
+            import_config_from_dir,
+            get_effective_config
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tauri::Manager;
+
+    #[test]
+    fn presentation_mode_blocks_writes_only_while_enabled() {
+        let presentation = PresentationMode(AtomicBool::new(false));
+        assert!(presentation.check().is_ok());
+
+        presentation.0.store(true, Ordering::Relaxed);
+        assert_eq!(presentation.check(), Err(db::PRESENTATION_MODE_ERROR.to_string()));
+
+        presentation.0.store(false, Ordering::Relaxed);
+        assert!(presentation.check().is_ok());
+    }
+
+    #[test]
+    fn write_commands_are_rejected_in_presentation_mode() {
+        let app = tauri::test::mock_app();
+        app.manage(PresentationMode(AtomicBool::new(true)));
+        let state = || app.state::<PresentationMode>();
+        let rejected = Err(db::PRESENTATION_MODE_ERROR.to_string());
+
+        tauri::async_runtime::block_on(async {
+            assert_eq!(add_annotation("{}".to_string(), state()).await, rejected.clone());
+            assert_eq!(save_document("notes.md".to_string(), "edited".to_string(), state()).await.map(|_| ()), rejected.clone());
+            assert_eq!(delete_annotation("anno".to_string(), state()).await, rejected);
+            // 启动恢复不因演示模式报错，前端初始化得以继续
+            assert_eq!(recover_from_journal(state()).await, Ok(0));
+        });
+    }
+}
@@ -292,7 +292,7 @@ export function useAnnotations() {
       content: contentWithHighlights
     });
 
-    await invoke('save_html_file', { path: savePath, html });
+    await invoke('save_html_file', { path: savePath, html, docId: currentDocId });
   };
 
   // 将锚点高亮应用到 DOM
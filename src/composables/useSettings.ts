@@ -18,6 +18,12 @@ export function useSettings() {
       // 初始化数据库
       await invoke('init_db');
 
+      // 恢复上次异常退出前未写入数据库的注解
+      const recovered = await invoke<number>('recover_from_journal');
+      if (recovered > 0) {
+        console.warn(`已从恢复日志中恢复 ${recovered} 条注解`);
+      }
+
       // 加载设置
       await loadSettings();
 
@@ -40,6 +40,37 @@ export interface AnnotationRecord {
   anchor_data: string;  // JSON string
   created_at: number;
   updated_at: number;
+  tags?: string[];
+  comments?: CommentRecord[];  // 仅导出包中携带
+  context?: AnchorContext;     // 仅导出包中携带，用于重新定位
+}
+
+// 高亮两侧的原文片段
+export interface AnchorContext {
+  prefix: string;
+  suffix: string;
+}
+
+// 注解评论
+export interface CommentRecord {
+  id: string;
+  annotation_id: string;
+  user_id: string;
+  user_name: string;
+  body: string;
+  created_at: number;
+  parent_id?: string | null;  // 回复的评论 ID
+}
+
+// 评论树
+export interface CommentNode {
+  comment: CommentRecord;
+  replies: CommentNode[];
+}
+
+export interface AnnotationThread {
+  annotation: AnnotationRecord;
+  comments: CommentNode[];
 }
 
 // 文档记录
@@ -52,6 +83,25 @@ export interface DocumentRecord {
   created_at: number;
 }
 
+// 文档快照摘要
+export interface SnapshotRecord {
+  id: string;
+  document_id: string;
+  label: string;
+  checksum: string;
+  annotation_count: number;
+  created_at: number;
+}
+
+// 导出记录
+export interface ExportRecord {
+  id: string;
+  document_id?: string | null;
+  format: string;
+  path: string;
+  created_at: number;
+}
+
 // 用户记录
 export interface UserRecord {
   id: string;
@@ -71,23 +121,38 @@ export interface EditorSettingsRecord {
   default_highlight_type: string;
   font_size: number;
   font_family: string;
+  max_document_bytes?: number;  // 超出时返回 DocumentTooLarge
+  color_presets?: ColorPreset[];
+  presentation_mode?: boolean;  // 演示模式下写操作返回 ReadOnlyMode
+}
+
+export interface ColorPreset {
+  name: string;
+  hex: string;
 }
 
 export interface ExportSettingsRecord {
   default_format: string;
   show_notes_by_default: boolean;
+  default_dir?: string | null;  // 相对文件名的导出目录
+  color_labels?: Record<string, string>;  // 高亮颜色 → 图例说明
 }
 
 export interface I18nSettingsRecord {
   language: string;
 }
 
+export interface DatabaseSettingsRecord {
+  read_pool_size: number;  // 只读连接池大小
+}
+
 export interface SettingsRecord {
   version: string;
   user: UserSettingsRecord;
   editor: EditorSettingsRecord;
   export: ExportSettingsRecord;
   i18n: I18nSettingsRecord;
+  database?: DatabaseSettingsRecord;
 }
 
 // 注解导出包